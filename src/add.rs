@@ -0,0 +1,80 @@
+use crate::{
+    config::SymlinkType, operations::adopt_file_or_dir, operations::Op, path_util::pathbuf_to_str,
+};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    borrow::Cow,
+    fs::{read_to_string, write},
+    path::Path,
+};
+use toml_edit::{value, ArrayOfTables, DocumentMut, Item, Table};
+
+/// `~/.config/alacritty` is kept as `alacritty` in the repository
+fn default_from(target: &Path) -> Result<String> {
+    let name = target
+        .file_name()
+        .context(format!("Fail to get file name of {}", target.display()))?
+        .to_string_lossy();
+    Ok(name.trim_start_matches('.').to_string())
+}
+
+/// replace home directory with `~`
+pub(crate) fn contract_home(p: &str) -> String {
+    let home = shellexpand::tilde("~");
+    match p.strip_prefix(home.as_ref()) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+        _ => p.to_string(),
+    }
+}
+
+/// plan moving `target` into the repository and linking it back
+pub fn create_add_ops(
+    target: &str,
+    from: Option<&str>,
+    base_dir: &Path,
+    symlink_type: SymlinkType,
+) -> Result<(String, Vec<Op>)> {
+    let to = shellexpand::tilde(target).to_string();
+    let to_path = Path::new(&to);
+    match to_path.symlink_metadata() {
+        Ok(m) if m.is_symlink() => return Err(anyhow!("{} is already a symbol link", to)),
+        Ok(_) => {}
+        Err(_) => return Err(anyhow!("{} does not exist", to)),
+    }
+    let from = match from {
+        Some(from) => from.to_string(),
+        None => default_from(to_path)?,
+    };
+    let abs_from = base_dir.join(&from);
+    if abs_from.exists() {
+        return Err(anyhow!(
+            "{} already exists in repository",
+            abs_from.display()
+        ));
+    }
+    let mut ops = vec![];
+    adopt_file_or_dir(
+        Cow::Borrowed(pathbuf_to_str(&abs_from)?),
+        Cow::Borrowed(&to),
+        symlink_type,
+        &mut ops,
+    )?;
+    Ok((from, ops))
+}
+
+/// append an `[[entries]]` block to config, keeping its formatting
+pub fn append_entry(config_path: &str, from: &str, to: &str) -> Result<()> {
+    let mut doc = read_to_string(config_path)?
+        .parse::<DocumentMut>()
+        .context(format!("Fail to parse {}", config_path))?;
+    let mut table = Table::new();
+    table["from"] = value(from);
+    table["to"] = value(contract_home(&shellexpand::tilde(to)));
+    doc.entry("entries")
+        .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .context("`entries` should be an array of tables")?
+        .push(table);
+    write(config_path, doc.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use crate::{
+    crypto::{decrypt_reader, encrypt_with, hash_file, remove_plaintext, Key, Naming},
+    path_util::pathbuf_to_str,
+};
+use age::x25519;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{create_dir_all, remove_dir},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// plaintext name of the archive of `dir`, its ciphertext is named after it
+pub fn archive_name(dir: &str) -> String {
+    format!("{}.tar", dir)
+}
+
+/// directory the archive with ciphertext `enc`, named by `naming`, unpacks to
+pub fn archive_dir(naming: &Naming, enc: &str) -> Option<String> {
+    naming
+        .plaintext(enc)?
+        .strip_suffix(".tar")
+        .map(str::to_string)
+}
+
+/// sha256 of paths relative to the archived directory and their content hashes, sorted by path
+fn digest(mut files: Vec<(String, String)>) -> String {
+    files.sort();
+    let mut hasher = Sha256::new();
+    for (path, hash) in files {
+        hasher.update(format!("{}\0{}\n", path, hash));
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn relative(dir: &Path, file: &Path) -> Result<String> {
+    let rel = file.strip_prefix(dir).context(format!(
+        "{} is not in {}",
+        file.display(),
+        dir.display()
+    ))?;
+    Ok(rel.to_string_lossy().replace('\\', "/"))
+}
+
+/// hash of `files` in `dir`, equal to `hash_archive` of their archive
+pub fn hash_dir(dir: &Path, files: &[PathBuf]) -> Result<String> {
+    let hashes = files
+        .iter()
+        .map(|f| Ok((relative(dir, f)?, hash_file(pathbuf_to_str(f)?)?)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(digest(hashes))
+}
+
+/// hash of files in the archive `enc`, plaintext is only streamed through memory
+pub fn hash_archive(enc: &str, key: &Key) -> Result<String> {
+    let mut archive = tar::Archive::new(decrypt_reader(enc, key)?);
+    let mut hashes = vec![];
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.to_string_lossy().replace('\\', "/");
+        let mut hasher = Sha256::new();
+        io::copy(&mut entry, &mut hasher)?;
+        hashes.push((path, format!("{:x}", hasher.finalize())));
+    }
+    Ok(digest(hashes))
+}
+
+/// archive `files` of `dir` and encrypt the archive, the ciphertext is named by `naming` and
+/// gets `mode` or 600
+pub fn encrypt_dir(
+    naming: &Naming,
+    dir: &Path,
+    files: &[PathBuf],
+    key: &Key,
+    recipients: &[x25519::Recipient],
+    mode: Option<u32>,
+    armor: bool,
+) -> Result<String> {
+    let enc = naming.ciphertext(&archive_name(pathbuf_to_str(dir)?));
+    encrypt_with(
+        &enc,
+        key,
+        recipients,
+        mode.unwrap_or(0o600),
+        armor,
+        |writer| {
+            let mut builder = tar::Builder::new(writer);
+            for f in files {
+                builder.append_path_with_name(f, relative(dir, f)?)?;
+            }
+            builder.finish()?;
+            Ok(())
+        },
+    )
+    .context(format!("Fail to encrypt {}", dir.display()))?;
+    Ok(enc)
+}
+
+/// decrypt the archive `enc` and unpack it in place, returning the directory
+pub fn decrypt_dir(naming: &Naming, enc: &str, key: &Key) -> Result<String> {
+    let dir = archive_dir(naming, enc).context(format!("{} is not an archive", enc))?;
+    let res = (|| -> Result<()> {
+        create_dir_all(&dir)?;
+        let mut archive = tar::Archive::new(decrypt_reader(enc, key)?);
+        archive.set_preserve_permissions(true);
+        archive.unpack(&dir)?;
+        Ok(())
+    })();
+    res.context(format!("Fail to decrypt {}", enc))?;
+    Ok(dir)
+}
+
+/// delete archived `files` of `dir`, and directories they leave empty
+pub fn remove_files(dir: &Path, files: &[PathBuf], shred: bool) -> Result<()> {
+    for f in files {
+        remove_plaintext(pathbuf_to_str(f)?, shred)?;
+    }
+    let mut parents = files
+        .iter()
+        .flat_map(|f| f.ancestors().skip(1).take_while(|p| p.starts_with(dir)))
+        .collect::<Vec<_>>();
+    parents.sort();
+    parents.dedup();
+    // deepest first
+    parents.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+    for p in parents {
+        // not empty, e.g. excluded files are left
+        let _ = remove_dir(p);
+    }
+    Ok(())
+}
+
+/// bytes of `files`, for progress of the archive
+pub fn archive_size(files: &[PathBuf]) -> u64 {
+    files
+        .iter()
+        .filter_map(|f| f.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+#[test]
+fn test_archive() {
+    let dir = std::env::temp_dir().join("lkdots-test-archive");
+    let _ = std::fs::remove_dir_all(&dir);
+    let store = dir.join("password-store");
+    std::fs::create_dir_all(store.join("work")).unwrap();
+    std::fs::write(store.join("mail.gpg"), "a").unwrap();
+    std::fs::write(store.join("work/vpn.gpg"), "b").unwrap();
+    let files = vec![store.join("mail.gpg"), store.join("work/vpn.gpg")];
+    let key = Key::Passphrase("abc".to_string());
+    let naming = Naming::default();
+    let hash = hash_dir(&store, &files).unwrap();
+    let enc = encrypt_dir(&naming, &store, &files, &key, &[], None, false).unwrap();
+    assert_eq!(enc, format!("{}.tar.enc", store.display()));
+    assert_eq!(hash_archive(&enc, &key).unwrap(), hash);
+    remove_files(&store, &files, false).unwrap();
+    assert!(!store.exists());
+    assert_eq!(
+        decrypt_dir(&naming, &enc, &key).unwrap(),
+        store.to_string_lossy()
+    );
+    assert_eq!(
+        std::fs::read_to_string(store.join("work/vpn.gpg")).unwrap(),
+        "b"
+    );
+    // armored archives are read the same way
+    let enc = encrypt_dir(&naming, &store, &files, &key, &[], None, true).unwrap();
+    assert!(std::fs::read_to_string(&enc)
+        .unwrap()
+        .starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+    assert_eq!(hash_archive(&enc, &key).unwrap(), hash);
+    remove_files(&store, &files, false).unwrap();
+    decrypt_dir(&naming, &enc, &key).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(store.join("mail.gpg")).unwrap(),
+        "a"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,53 @@
+use crate::git;
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// config file searched in cloned repositories
+const CONFIG_FILE: &str = "lkdots.toml";
+
+/// shallowest `lkdots.toml` in `dir`, outside of `.git`
+pub fn find_config(dir: &Path) -> Option<PathBuf> {
+    WalkDir::new(dir)
+        .max_depth(3)
+        .sort_by_key(|e| e.file_name().to_owned())
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git")
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && e.file_name() == CONFIG_FILE)
+        .min_by_key(|e| e.depth())
+        .map(|e| e.into_path())
+}
+
+/// clone `url` into `dest` and return the config in it, an existing clone in `dest` is
+/// reused so an interrupted bootstrap can be run again
+pub fn clone(url: &str, dest: &Path, simulate: bool) -> Result<Option<PathBuf>> {
+    if dest.join(".git").exists() {
+        println!("using existing repository {}", dest.display());
+    } else if dest.read_dir().is_ok_and(|mut d| d.next().is_some()) {
+        return Err(anyhow!(
+            "{} already exists and is not a git repository",
+            dest.display()
+        ));
+    } else if simulate {
+        println!("git clone {} {}", url, dest.display());
+        return Ok(None);
+    } else {
+        git::clone(url, dest)?;
+    }
+    find_config(dest)
+        .map(Some)
+        .ok_or_else(|| anyhow!("no {} found in {}", CONFIG_FILE, dest.display()))
+}
+
+#[test]
+fn test_find_config() {
+    let dir = std::env::temp_dir().join("lkdots-test-find-config");
+    std::fs::create_dir_all(dir.join(".git")).unwrap();
+    std::fs::create_dir_all(dir.join("dots/nested")).unwrap();
+    std::fs::write(dir.join(".git/lkdots.toml"), "").unwrap();
+    std::fs::write(dir.join("dots/nested/lkdots.toml"), "").unwrap();
+    std::fs::write(dir.join("dots/lkdots.toml"), "").unwrap();
+    assert_eq!(find_config(&dir), Some(dir.join("dots/lkdots.toml")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,191 @@
+use crate::{
+    archive::archive_name,
+    config::{Config, ConfigFileStruct, Entry},
+    crypto::Naming,
+    doctor::{report, Diagnostic},
+    exclude::{is_vcs_path, Exclude},
+    git::tracked_files,
+    path_util::{is_glob, pathbuf_to_str},
+};
+use anyhow::Result;
+use std::{fs::read_to_string, path::Path};
+use walkdir::WalkDir;
+
+/// plaintexts of encrypted entry `e` in the repository without a ciphertext
+fn missing_ciphertexts(
+    e: &Entry,
+    from: &str,
+    naming: &Naming,
+    result: &mut Vec<Diagnostic>,
+) -> Result<()> {
+    let exclude = Exclude::new(from, &e.encrypt_exclude)?;
+    let walker = WalkDir::new(from)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|f| {
+            !is_vcs_path(f.path()) && !exclude.is_excluded(&f.path().to_string_lossy())
+        });
+    for f in walker.filter_map(|f| f.ok()) {
+        let path = pathbuf_to_str(f.path())?;
+        if !f.file_type().is_file() || naming.is_ciphertext(path) {
+            continue;
+        }
+        let enc = naming.ciphertext(path);
+        if !Path::new(&enc).exists() {
+            result.push(Diagnostic::error(
+                format!("{} has no {}", path, enc),
+                "run `lkdots encrypt` and commit the ciphertext",
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_entry(
+    e: &Entry,
+    base_dir: &Path,
+    naming: &Naming,
+    tracked: Option<&[String]>,
+    result: &mut Vec<Diagnostic>,
+) -> Result<()> {
+    let (from, _) = match e.expand_paths(base_dir) {
+        Ok(paths) => paths,
+        Err(err) => {
+            result.push(Diagnostic::error(
+                format!("{}: {}", e.from, err),
+                "define the environment variable in CI or fix `from` and `to`",
+            ));
+            return Ok(());
+        }
+    };
+    let exists = if is_glob(&from) {
+        matches!(e.expand_sources(base_dir), Ok(sources) if !sources.is_empty())
+    } else {
+        // plaintexts are ignored, only ciphertext is in a fresh clone
+        let mirror = naming.mirror_of(Path::new(&from));
+        Path::new(&from).exists()
+            || (e.encrypt
+                && (Path::new(&naming.ciphertext(&from)).exists()
+                    || Path::new(&naming.ciphertext(&archive_name(&from))).exists()
+                    || mirror.is_some_and(|m| m.is_dir())))
+    };
+    if !exists {
+        result.push(Diagnostic::error(
+            format!("source {} of {} does not exist", from, e.to),
+            "fix `from` or commit the source",
+        ));
+        return Ok(());
+    }
+    if !e.encrypt || is_glob(&from) {
+        return Ok(());
+    }
+    let archive = naming.ciphertext(&archive_name(&from));
+    if e.encrypt_archive && !Path::new(&archive).exists() {
+        result.push(Diagnostic::error(
+            format!("{} has no {}", from, archive),
+            "run `lkdots encrypt` and commit the archive",
+        ));
+    } else if !e.encrypt_archive && Path::new(&from).exists() {
+        missing_ciphertexts(e, &from, naming, result)?;
+    }
+    let exclude = Exclude::new(&from, &e.encrypt_exclude)?;
+    let plaintexts = tracked.unwrap_or_default().iter().filter(|f| {
+        let path = base_dir.join(f);
+        let path = path.to_string_lossy();
+        (path == from || path.starts_with(&format!("{}/", from)))
+            && !naming.is_ciphertext(&path)
+            && !is_vcs_path(Path::new(path.as_ref()))
+            && !exclude.is_excluded(&path)
+    });
+    for f in plaintexts {
+        result.push(Diagnostic::error(
+            format!(
+                "{} is plaintext of encrypted entry {} tracked by git",
+                f, e.from
+            ),
+            "remove it with `git rm --cached` and commit its ciphertext instead",
+        ));
+    }
+    Ok(())
+}
+
+/// problems of the config at `config_path` and its repository, for CI. Entries of every
+/// platform are checked, nothing outside of the repository is looked at
+pub fn check(config_path: &Path) -> Result<Vec<Diagnostic>> {
+    let mut result = vec![];
+    let src = match read_to_string(config_path) {
+        Ok(src) => src,
+        Err(err) => {
+            result.push(Diagnostic::error(
+                format!("Fail to read {}: {}", config_path.display(), err),
+                "run it in the repository or give the config with -c",
+            ));
+            return Ok(result);
+        }
+    };
+    let config: Config = match ConfigFileStruct::load(config_path, &src) {
+        Ok(c) => c.into(),
+        Err(err) => {
+            result.push(Diagnostic::error(format!("{:#}", err), "fix the config"));
+            return Ok(result);
+        }
+    };
+    let base_dir = match config_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // ciphertexts are found by paths relative to the canonical repository
+    let base_dir = &base_dir.canonicalize()?;
+    let naming = match config.naming(base_dir) {
+        Ok(naming) => naming,
+        Err(err) => {
+            result.push(Diagnostic::error(format!("{:#}", err), "fix the config"));
+            return Ok(result);
+        }
+    };
+    let tracked = match tracked_files(base_dir) {
+        Ok(files) => Some(files),
+        Err(err) => {
+            result.push(Diagnostic::warning(
+                format!("tracked files are not checked: {:#}", err),
+                "run it in a git repository",
+            ));
+            None
+        }
+    };
+    for e in config.entries.iter() {
+        check_entry(e, base_dir, &naming, tracked.as_deref(), &mut result)?;
+    }
+    Ok(result)
+}
+
+pub fn print_check(config_path: &Path, json: bool) -> Result<()> {
+    report(&check(config_path)?, json)
+}
+
+#[test]
+fn test_check() {
+    let dir = std::env::temp_dir().join("lkdots-test-check");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("ssh")).unwrap();
+    std::fs::write(dir.join("ssh/config"), "").unwrap();
+    std::fs::write(dir.join("ssh/config.enc"), "").unwrap();
+    std::fs::write(dir.join("ssh/id_ed25519"), "").unwrap();
+    std::fs::write(
+        dir.join("lkdots.toml"),
+        "[[entries]]\nfrom = \"ssh\"\nto = \"~/.ssh\"\nencrypt = true\n\
+         [[entries]]\nfrom = \"zsh\"\nto = \"~/.zshrc\"\n",
+    )
+    .unwrap();
+    let messages = check(&dir.join("lkdots.toml"))
+        .unwrap()
+        .into_iter()
+        .map(|d| d.message)
+        .collect::<Vec<_>>();
+    assert!(messages.iter().any(|m| m.ends_with("id_ed25519.enc")));
+    assert!(!messages.iter().any(|m| m.ends_with("config.enc")));
+    assert!(messages
+        .iter()
+        .any(|m| m.contains("zsh of ~/.zshrc does not exist")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,64 +1,403 @@
-use log::debug;
-use std::{env::current_dir, io::Result};
-use structopt::StructOpt;
-
-lazy_static! {
-    static ref LKDOTS_DEFAULT_CONFIG_PATH: String = current_dir()
-        .map(|p| { p.join("lkdots.toml") })
-        .map(|p| {
-            let pt = p.to_str().unwrap();
-            pt.to_owned()
-        })
-        .expect("Fail to found current dir");
+use clap::{ArgAction, ArgGroup, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use log::{debug, LevelFilter};
+use std::{
+    env::current_dir,
+    io::Result,
+    path::{Path, PathBuf},
+};
+
+pub const CONFIG_FILE: &str = "lkdots.toml";
+
+#[derive(PartialEq, Clone, Copy, Debug, ValueEnum)]
+pub enum OutputFormat {
+    Human,
+    Json,
 }
 
-#[derive(PartialEq, StructOpt, Debug)]
 /// A cli tool to create symbol link of dotfiles with encryption and more
-
+#[derive(PartialEq, Parser, Debug, Clone)]
+#[command(name = "lkdots", version)]
+#[command(group(ArgGroup::new("key").args(["identities", "passphrase_file"])))]
 pub struct Cli {
-    /// path to config file
-    #[structopt(short = "c", default_value = &LKDOTS_DEFAULT_CONFIG_PATH)]
-    pub config: String,
+    /// path to config file, defaults to lkdots.toml in the current directory or its closest parent
+    /// having one, then ~/.config/lkdots/lkdots.toml
+    #[arg(short = 'c', long = "config", global = true)]
+    pub config: Option<String>,
 
     /// simulate fs operations, do not actually make any filesystem changes
-    #[structopt(long = "simulate")]
+    #[arg(long = "simulate", global = true)]
     pub simulate: bool,
 
-    #[structopt(subcommand)]
+    /// answer yes to questions, and never ask how to resolve conflicts, nothing is asked either
+    /// when stdin or stdout is not a terminal
+    #[arg(short = 'y', long = "yes", global = true)]
+    pub yes: bool,
+
+    /// move conflicting targets to a backup instead of aborting
+    #[arg(long = "backup")]
+    pub backup: bool,
+
+    /// replace conflicting symbol links created by lkdots, e.g. links to an old checkout location, and re-encrypt unchanged files
+    #[arg(long = "force")]
+    pub force: bool,
+
+    /// show diff between conflicting targets and sources, and of copies and ignore files with --simulate
+    #[arg(long = "diff")]
+    pub diff: bool,
+
+    /// age identity file used to encrypt and decrypt instead of a passphrase
+    #[arg(long = "identity")]
+    pub identities: Vec<String>,
+
+    /// read passphrase from file instead of prompting, LKDOTS_PASSPHRASE env is also supported
+    #[arg(long = "passphrase-file")]
+    pub passphrase_file: Option<String>,
+
+    /// only handle entries with one of these tags
+    #[arg(long = "tags", value_delimiter = ',')]
+    pub tags: Vec<String>,
+
+    /// skip entries with one of these tags
+    #[arg(long = "skip-tags", value_delimiter = ',')]
+    pub skip_tags: Vec<String>,
+
+    /// number of threads walking and encrypting or decrypting files, defaults to the number of
+    /// CPUs
+    #[arg(short = 'j', long = "jobs", visible_alias = "threads")]
+    pub jobs: Option<usize>,
+
+    /// only print errors
+    #[arg(short = 'q', long = "quiet")]
+    pub quiet: bool,
+
+    /// print more logs, -v for every operation, -vv for debugging
+    #[arg(short = 'v', long = "verbose", action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// disable colored output, NO_COLOR env is also supported
+    #[arg(long = "no-color", global = true)]
+    pub no_color: bool,
+
+    /// language of messages, en or zh-CN, defaults to LC_ALL, LC_MESSAGES or LANG
+    #[arg(long = "lang", global = true)]
+    pub lang: Option<String>,
+
+    /// output format of simulate, status, doctor and verify
+    #[arg(long = "output", value_enum, default_value = "human")]
+    pub output: OutputFormat,
+
+    #[command(subcommand)]
     pub cmd: Option<SubCommand>,
 }
 
 impl Cli {
-    pub fn is_encrypt_cmd(&self) -> bool {
-        match self.cmd.as_ref() {
-            Some(SubCommand::Encrypt) => true,
-            Some(SubCommand::Decrypt) => false,
-            None => false,
+    /// `-c`, or the config found like git finds its repository, `lkdots.toml` in the current
+    /// directory if there is none
+    pub fn config_path(&self) -> Result<PathBuf> {
+        if let Some(p) = self.config.as_ref() {
+            return Ok(PathBuf::from(shellexpand::tilde(p).as_ref()));
         }
+        let cwd = current_dir()?;
+        let config_home = std::env::var("XDG_CONFIG_HOME")
+            .unwrap_or_else(|_| shellexpand::tilde("~/.config").to_string());
+        let found = cwd
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .chain(std::iter::once(
+                Path::new(&config_home).join("lkdots").join(CONFIG_FILE),
+            ))
+            .find(|p| p.is_file());
+        debug!("config found: {:?}", found);
+        Ok(found.unwrap_or_else(|| cwd.join(CONFIG_FILE)))
+    }
+    pub fn is_encrypt_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Encrypt { .. }))
     }
     pub fn is_decrypt_cmd(&self) -> bool {
-        match self.cmd.as_ref() {
-            Some(SubCommand::Encrypt) => false,
-            Some(SubCommand::Decrypt) => true,
-            None => false,
+        matches!(self.cmd, Some(SubCommand::Decrypt { .. }))
+    }
+    pub fn is_unlink_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Unlink))
+    }
+    pub fn is_status_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Status))
+    }
+    pub fn is_adopt_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Adopt))
+    }
+    pub fn is_prune_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Prune))
+    }
+    pub fn is_doctor_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Doctor))
+    }
+    pub fn is_diff_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Diff))
+    }
+    pub fn is_verify_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Verify))
+    }
+    pub fn is_rekey_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Rekey { .. }))
+    }
+    /// paths given to encrypt or decrypt
+    pub fn crypt_paths(&self) -> &[String] {
+        match &self.cmd {
+            Some(SubCommand::Encrypt { paths, .. }) | Some(SubCommand::Decrypt { paths }) => paths,
+            _ => &[],
         }
     }
+    /// RUST_LOG still takes precedence
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Error,
+            (_, 0) => LevelFilter::Warn,
+            (_, 1) => LevelFilter::Info,
+            (_, 2) => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+    pub fn is_repair_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Repair))
+    }
+    /// plain `lkdots`, or `lkdots apply <packages>`
+    pub fn is_link_cmd(&self) -> bool {
+        matches!(self.cmd, None | Some(SubCommand::Apply { plan: None, .. })) || self.is_tui_cmd()
+    }
+    #[cfg(feature = "tui")]
+    pub fn is_tui_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Tui))
+    }
+    #[cfg(not(feature = "tui"))]
+    pub fn is_tui_cmd(&self) -> bool {
+        false
+    }
+    /// packages given to apply
+    pub fn packages(&self) -> &[String] {
+        match &self.cmd {
+            Some(SubCommand::Apply { packages, .. }) => packages,
+            _ => &[],
+        }
+    }
+    pub fn is_plan_cmd(&self) -> bool {
+        matches!(self.cmd, Some(SubCommand::Plan { .. }))
+    }
+    pub fn is_json(&self) -> bool {
+        self.output == OutputFormat::Json
+    }
 }
 
-#[derive(StructOpt, PartialEq, Debug)]
+#[derive(Subcommand, PartialEq, Debug, Clone)]
 pub enum SubCommand {
     /// encrypt files to *.enc file
-    Encrypt,
+    Encrypt {
+        /// delete plaintext files after encryption
+        #[arg(long = "remove-plaintext")]
+        remove_plaintext: bool,
+        /// overwrite plaintext files with zeros before deleting them, implies --remove-plaintext
+        #[arg(long = "shred")]
+        shred: bool,
+        /// commit the *.enc files and .gitignore, like `git.auto_commit = true`
+        #[arg(long = "commit")]
+        commit: bool,
+        /// octal mode of *.enc files, overrides `ciphertext_mode` of entries
+        #[arg(long = "mode")]
+        mode: Option<String>,
+        /// write ASCII armored *.enc files, the default unless `armor = false`
+        #[arg(long = "armor", overrides_with = "no_armor")]
+        armor: bool,
+        /// write binary *.enc files, a third smaller for large files, like `armor = false`
+        #[arg(long = "no-armor", overrides_with = "armor")]
+        no_armor: bool,
+        /// only encrypt these files or directories of encrypted entries
+        paths: Vec<String>,
+    },
     /// decrypt files to original position
-    Decrypt,
+    Decrypt {
+        /// only decrypt these files or directories of encrypted entries
+        paths: Vec<String>,
+    },
+    /// re-encrypt *.enc files with a new passphrase or to new recipients without writing plaintext
+    #[command(group(ArgGroup::new("new_key").args(["new_identities", "new_passphrase_file"])))]
+    Rekey {
+        /// re-encrypt to these age identity files, files are re-encrypted to the current
+        /// identities and `recipients` if neither this nor --new-passphrase-file is given
+        #[arg(long = "new-identity")]
+        new_identities: Vec<String>,
+        /// read the new passphrase from file instead of prompting
+        #[arg(long = "new-passphrase-file")]
+        new_passphrase_file: Option<String>,
+    },
+    /// check every *.enc file can be decrypted and every plaintext has an up-to-date *.enc file
+    Verify,
+    /// remove symbol links created by lkdots
+    Unlink,
+    /// show link status of each entry
+    Status,
+    /// move existing targets into the repository when the source is missing, then link them
+    Adopt,
+    /// remove symbol links created by lkdots for entries no longer in config
+    Prune,
+    /// check config and environment for problems
+    Doctor,
+    /// install binaries in `requires` of entries that are missing with apt, brew, pacman or winget
+    InstallMissing,
+    /// show diff between conflicting targets and sources
+    Diff,
+    /// replace dangling symbol links of entries, e.g. after the repository is moved
+    Repair,
+    /// list all entries with their resolved paths and status
+    List {
+        /// only entries for this platform: linux, darwin, windows, freebsd, openbsd, android or wsl
+        #[arg(long = "platform")]
+        platform: Option<String>,
+        #[arg(long = "encrypted-only")]
+        encrypted_only: bool,
+        /// same as `--output json`
+        #[arg(long = "json")]
+        json: bool,
+    },
+    /// move a file or directory into the repository, register it in config and link it
+    Add {
+        /// path to add, e.g. ~/.config/alacritty
+        path: String,
+        /// path in the repository, defaults to the file name without leading dot
+        #[arg(long = "from")]
+        from: Option<String>,
+    },
+    /// write the operations linking would do to a plan file
+    Plan {
+        /// plan file, TOML if it ends with .toml, JSON otherwise
+        #[arg(long = "out", default_value = "lkdots-plan.json")]
+        out: String,
+    },
+    /// link only entries of the given packages, or execute a plan file written by `lkdots plan`
+    Apply {
+        /// plan file to execute
+        #[arg(long = "plan")]
+        plan: Option<String>,
+        /// names of `[[packages]]`
+        packages: Vec<String>,
+    },
+    /// link entries again whenever their sources or the config change
+    Watch {
+        /// also encrypt changed plaintexts of encrypted entries
+        #[arg(long = "encrypt")]
+        encrypt: bool,
+        /// milliseconds to wait for more changes before applying them
+        #[arg(long = "debounce", default_value_t = 500)]
+        debounce: u64,
+    },
+    /// print a completion script, e.g. `lkdots completions bash > /etc/bash_completion.d/lkdots`
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// package or tag names of the config, one per line, used by completion scripts
+    #[command(hide = true)]
+    CompleteNames {
+        #[arg(value_parser = ["packages", "tags"])]
+        kind: String,
+    },
+    /// manage git hooks of the dotfiles repository
+    Githook {
+        #[command(subcommand)]
+        cmd: GithookCmd,
+    },
+    /// run `lkdots apply` periodically with a systemd user timer or a launchd agent
+    Schedule {
+        #[command(subcommand)]
+        cmd: ScheduleCmd,
+    },
+    /// create a starter lkdots.toml and .gitignore
+    Init {
+        /// also run `git init`
+        #[arg(long = "git")]
+        git: bool,
+    },
+    /// check config and repository in CI, sources exist, encrypted entries have .enc files
+    /// and no plaintext is tracked
+    Check,
+    /// clone a dotfiles repository, decrypt its encrypted entries and link everything
+    Bootstrap {
+        /// git url of the repository
+        url: String,
+        /// directory to clone into
+        #[arg(long = "dest", default_value = "~/dotfiles")]
+        dest: String,
+    },
+    /// generate lkdots.toml from dotfiles managed by another tool
+    Import {
+        /// tool managing the dotfiles
+        #[arg(long = "from", value_parser = ["stow", "chezmoi", "dotbot"])]
+        from: String,
+        /// stow directory, chezmoi source directory, or dotbot repository or config file
+        dir: String,
+    },
+    /// review the plan in a terminal UI, toggle entries and resolve conflicts, then link
+    #[cfg(feature = "tui")]
+    Tui,
+    /// `lkdots <name>` runs the `lkdots-<name>` executable in PATH
+    #[command(external_subcommand)]
+    External(Vec<String>),
+    /// replace this executable with the latest release
+    #[cfg(feature = "self-update")]
+    SelfUpdate {
+        /// only check whether a newer release exists
+        #[arg(long = "check")]
+        check: bool,
+    },
+    /// write the config of another tool linking entries of this platform
+    Export {
+        /// format to write
+        #[arg(long = "to", value_parser = ["dotbot"])]
+        to: String,
+        /// file to write, defaults to install.conf.yaml next to the config
+        #[arg(long = "output")]
+        output: Option<String>,
+    },
+}
+
+#[derive(Subcommand, PartialEq, Debug, Clone)]
+pub enum GithookCmd {
+    /// install a pre-commit hook refusing to commit plaintext of encrypted entries
+    Install {
+        /// replace an existing pre-commit hook not installed by lkdots
+        #[arg(long = "force")]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, PartialEq, Debug, Clone)]
+pub enum ScheduleCmd {
+    /// install and start the timer or agent, replacing an installed one
+    Install {
+        #[arg(long = "interval", default_value = "daily", value_parser = ["hourly", "daily", "weekly"])]
+        interval: String,
+    },
+    /// stop and remove the timer or agent
+    Uninstall,
 }
 
 pub fn config() -> Result<Cli> {
-    let args = Cli::from_args();
+    let args = Cli::parse();
     debug!("{:?}", args);
     Ok(args)
 }
 
+#[test]
+fn test_cli() {
+    use clap::CommandFactory;
+    Cli::command().debug_assert();
+    let cli = Cli::parse_from(["lkdots", "status", "--simulate", "-c", "dots.toml"]);
+    assert!(cli.simulate);
+    assert_eq!(cli.config.as_deref(), Some("dots.toml"));
+    assert!(
+        Cli::try_parse_from(["lkdots", "--identity", "key.txt", "--passphrase-file", "p"]).is_err()
+    );
+}
+
 #[test]
 fn test_config_init() {
     println!("{:?}", config().unwrap())
@@ -0,0 +1,51 @@
+use crate::cli::Cli;
+use anyhow::Result;
+use clap::CommandFactory;
+use clap_complete::{generate, Shell};
+use std::io::Write;
+
+/// completes `lkdots apply <package>` and `--tags <tag>` from the config in use,
+/// wrapping the function generated by clap
+const BASH_NAMES: &str = r#"
+_lkdots_names() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    case "$prev" in
+        --tags|--skip-tags)
+            COMPREPLY=($(compgen -W "$(lkdots complete-names tags 2>/dev/null)" -- "$cur"))
+            return 0
+            ;;
+    esac
+    if [[ " ${COMP_WORDS[*]} " == *" apply "* && "$cur" != -* ]]; then
+        COMPREPLY=($(compgen -W "$(lkdots complete-names packages 2>/dev/null)" -- "$cur"))
+        return 0
+    fi
+    _lkdots "$@"
+}
+complete -F _lkdots_names -o bashdefault -o default lkdots
+"#;
+
+const FISH_NAMES: &str = r#"
+complete -c lkdots -n "__fish_seen_subcommand_from apply" -f -a "(lkdots complete-names packages 2>/dev/null)"
+complete -c lkdots -l tags -f -a "(lkdots complete-names tags 2>/dev/null)"
+complete -c lkdots -l skip-tags -f -a "(lkdots complete-names tags 2>/dev/null)"
+"#;
+
+/// completion script of `shell`, package and tag names are completed dynamically in bash and fish
+pub fn write_completions(shell: Shell, out: &mut impl Write) -> Result<()> {
+    generate(shell, &mut Cli::command(), "lkdots", out);
+    match shell {
+        Shell::Bash => out.write_all(BASH_NAMES.as_bytes())?,
+        Shell::Fish => out.write_all(FISH_NAMES.as_bytes())?,
+        _ => {}
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_completions() {
+    let mut out = vec![];
+    write_completions(Shell::Bash, &mut out).unwrap();
+    let script = String::from_utf8(out).unwrap();
+    assert!(script.contains("_lkdots()"));
+    assert!(script.contains("complete-names packages"));
+}
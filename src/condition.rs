@@ -0,0 +1,237 @@
+use crate::config::PLATFORM;
+use anyhow::{anyhow, Result};
+use std::{env, path::Path};
+
+/// value of a condition expression, strings are true when not empty
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Bool(bool),
+    Str(String),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    LParen,
+    RParen,
+    Comma,
+    And,
+    Or,
+    Not,
+    Eq,
+    Ne,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Eq,
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Ne,
+            '!' => Token::Not,
+            '\'' | '"' => {
+                let mut lit = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(ch) => lit.push(ch),
+                        None => return Err(anyhow!("Unterminated string in `{}`", s)),
+                    }
+                }
+                Token::Str(lit)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(ch) = chars.next_if(|ch| ch.is_alphanumeric() || *ch == '_') {
+                    ident.push(ch);
+                }
+                Token::Ident(ident)
+            }
+            c => return Err(anyhow!("Unexpected `{}` in `{}`", c, s)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// executable found in `PATH`
+pub(crate) fn which(cmd: &str) -> bool {
+    let exts = if cfg!(windows) {
+        vec!["", ".exe", ".cmd", ".bat"]
+    } else {
+        vec![""]
+    };
+    env::var_os("PATH")
+        .map(|paths| {
+            env::split_paths(&paths).any(|dir| {
+                exts.iter()
+                    .any(|ext| dir.join(format!("{}{}", cmd, ext)).is_file())
+            })
+        })
+        .unwrap_or(false)
+}
+
+fn call(name: &str, args: &[Value]) -> Result<Value> {
+    let arg = |i: usize| match args.get(i) {
+        Some(Value::Str(s)) => Ok(s.as_str()),
+        _ => Err(anyhow!("{}() expects a string argument", name)),
+    };
+    Ok(match name {
+        "exists" => Value::Bool(Path::new(shellexpand::tilde(arg(0)?).as_ref()).exists()),
+        "which" => Value::Bool(which(arg(0)?)),
+        "env" => Value::Str(env::var(arg(0)?).unwrap_or_default()),
+        "platform" => Value::Str(PLATFORM.to_string()),
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => return Err(anyhow!("Unknown function {}()", name)),
+    })
+}
+
+/// recursive descent evaluator
+///
+/// ```text
+/// or      := and ("||" and)*
+/// and     := unary ("&&" unary)*
+/// unary   := "!" unary | compare
+/// compare := primary (("==" | "!=") primary)?
+/// primary := "(" or ")" | string | ident ("(" (or ("," or)*)? ")")?
+/// ```
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn expect(&mut self, t: Token) -> Result<()> {
+        match self.advance() {
+            Some(n) if n == t => Ok(()),
+            n => Err(anyhow!("Expected {:?}, found {:?}", t, n)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Value> {
+        let mut v = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            let rhs = self.and()?;
+            v = Value::Bool(v.truthy() || rhs.truthy());
+        }
+        Ok(v)
+    }
+
+    fn and(&mut self) -> Result<Value> {
+        let mut v = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.advance();
+            let rhs = self.unary()?;
+            v = Value::Bool(v.truthy() && rhs.truthy());
+        }
+        Ok(v)
+    }
+
+    fn unary(&mut self) -> Result<Value> {
+        if self.peek() == Some(&Token::Not) {
+            self.advance();
+            return Ok(Value::Bool(!self.unary()?.truthy()));
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> Result<Value> {
+        let lhs = self.primary()?;
+        match self.peek() {
+            Some(Token::Eq) => {
+                self.advance();
+                Ok(Value::Bool(lhs == self.primary()?))
+            }
+            Some(Token::Ne) => {
+                self.advance();
+                Ok(Value::Bool(lhs != self.primary()?))
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Value> {
+        match self.advance() {
+            Some(Token::LParen) => {
+                let v = self.or()?;
+                self.expect(Token::RParen)?;
+                Ok(v)
+            }
+            Some(Token::Str(s)) => Ok(Value::Str(s)),
+            Some(Token::Ident(name)) => {
+                let mut args = vec![];
+                if self.peek() == Some(&Token::LParen) {
+                    self.advance();
+                    if self.peek() != Some(&Token::RParen) {
+                        args.push(self.or()?);
+                        while self.peek() == Some(&Token::Comma) {
+                            self.advance();
+                            args.push(self.or()?);
+                        }
+                    }
+                    self.expect(Token::RParen)?;
+                }
+                call(&name, &args)
+            }
+            t => Err(anyhow!("Unexpected {:?}", t)),
+        }
+    }
+}
+
+/// evaluate a `when` expression like `which('nvim') && env('TERM') != 'dumb'`
+pub fn eval(s: &str) -> Result<bool> {
+    let mut parser = Parser {
+        tokens: tokenize(s)?,
+        pos: 0,
+    };
+    let v = parser
+        .or()
+        .map_err(|e| anyhow!("Invalid condition `{}`: {}", s, e))?;
+    if parser.pos != parser.tokens.len() {
+        return Err(anyhow!(
+            "Invalid condition `{}`: unexpected trailing tokens",
+            s
+        ));
+    }
+    Ok(v.truthy())
+}
+
+#[test]
+fn test_eval() {
+    std::env::set_var("LKDOTS_TEST_TERM", "dumb");
+    assert!(eval("exists('/etc/passwd') && env('LKDOTS_TEST_TERM') == 'dumb'").unwrap());
+    assert!(!eval("env('LKDOTS_TEST_TERM') != \"dumb\"").unwrap());
+    assert!(eval("!exists('/no/such/file') || false").unwrap());
+    assert!(eval("(false || which('sh')) && !env('LKDOTS_TEST_UNDEFINED')").unwrap());
+    assert!(eval("exists('/etc/passwd'").is_err());
+    assert!(eval("unknown('x')").is_err());
+}
@@ -1,111 +1,1144 @@
-use crate::operations::{link_file_or_dir, Op};
-use anyhow::Result;
-use log::debug;
+use crate::{
+    archive::archive_name,
+    condition,
+    crypto::{Naming, DEFAULT_BUFFER_SIZE, DEFAULT_SUFFIX},
+    errors::Error,
+    exclude::{is_vcs_path, Exclude},
+    fs_view::{CachedFs, FsView},
+    git::{GitOptions, DEFAULT_COMMIT_MESSAGE},
+    operations::{
+        adopt_file_or_dir, create_permission_ops, create_repair_ops, link_file_or_dir,
+        unlink_file_or_dir, LinkOptions, OnConflict, Op,
+    },
+    path_util::{expand_path, glob_base, is_glob, pathbuf_to_str},
+    perm_util::{parse_mode, set_mode},
+    validate,
+};
+use anyhow::{anyhow, Context, Result};
+use ignore::{WalkBuilder, WalkState};
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
-use std::{borrow::Cow, ffi::OsString, path::Path};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    env::consts::ARCH,
+    fs::{canonicalize, read_to_string},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex, OnceLock},
+};
 
+/// operating system lkdots is built for
 pub const PLATFORM: &str = if cfg!(target_os = "linux") {
     "linux"
 } else if cfg!(target_os = "windows") {
-    "window"
+    "windows"
 } else if cfg!(target_os = "macos") {
     "darwin"
+} else if cfg!(target_os = "freebsd") {
+    "freebsd"
+} else if cfg!(target_os = "openbsd") {
+    "openbsd"
+} else if cfg!(target_os = "android") {
+    "android"
 } else {
     "linux"
 };
 
 // serde
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
-pub enum Platfrom {
+pub enum Platform {
     Linux,
     Darwin,
-    Window,
+    #[serde(alias = "window")]
+    Windows,
+    Freebsd,
+    Openbsd,
+    /// including Termux
+    #[serde(alias = "termux")]
+    Android,
+    /// Linux under WSL, detected at runtime, entries for `linux` apply there too
+    Wsl,
 }
 
-impl PartialEq<Platfrom> for str {
-    fn eq(&self, other: &Platfrom) -> bool {
-        match other {
-            Platfrom::Linux => self == "linux",
-            Platfrom::Darwin => self == "darwin",
-            Platfrom::Window => self == "window",
+#[deprecated(note = "renamed to `Platform`")]
+pub type Platfrom = Platform;
+
+impl Platform {
+    pub const ALL: [Platform; 7] = [
+        Platform::Linux,
+        Platform::Darwin,
+        Platform::Windows,
+        Platform::Freebsd,
+        Platform::Openbsd,
+        Platform::Android,
+        Platform::Wsl,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Linux => "linux",
+            Platform::Darwin => "darwin",
+            Platform::Windows => "windows",
+            Platform::Freebsd => "freebsd",
+            Platform::Openbsd => "openbsd",
+            Platform::Android => "android",
+            Platform::Wsl => "wsl",
         }
     }
 }
 
-impl PartialEq<str> for Platfrom {
+impl PartialEq<Platform> for str {
+    fn eq(&self, other: &Platform) -> bool {
+        self == other.name()
+    }
+}
+
+impl PartialEq<str> for Platform {
     fn eq(&self, other: &str) -> bool {
         other == self
     }
 }
 
+/// `a` as `std::env::consts::ARCH` names it, `amd64` and `arm64` are accepted too
+pub fn arch_name(a: &str) -> &str {
+    match a {
+        "amd64" => "x86_64",
+        "arm64" => "aarch64",
+        a => a,
+    }
+}
+
+/// WSL sets `WSL_DISTRO_NAME`, its kernel release names Microsoft
+fn is_wsl() -> bool {
+    std::env::var_os("WSL_DISTRO_NAME").is_some()
+        || read_to_string("/proc/sys/kernel/osrelease")
+            .is_ok_and(|r| r.to_lowercase().contains("microsoft"))
+}
+
+/// Linux binaries run in Termux, which sets `TERMUX_VERSION`
+fn is_termux() -> bool {
+    std::env::var_os("TERMUX_VERSION").is_some()
+        || std::env::var("PREFIX").is_ok_and(|p| p.contains("com.termux"))
+}
+
+static CURRENT_PLATFORMS: OnceLock<Vec<Platform>> = OnceLock::new();
+
+/// platforms this runs on, most specific first, e.g. `wsl` before `linux`
+pub fn current_platforms() -> &'static [Platform] {
+    CURRENT_PLATFORMS.get_or_init(|| {
+        let os = Platform::ALL
+            .into_iter()
+            .find(|p| p == PLATFORM)
+            .unwrap_or(Platform::Linux);
+        let mut result = vec![];
+        if os == Platform::Linux && is_wsl() {
+            result.push(Platform::Wsl);
+        }
+        if os == Platform::Linux && is_termux() {
+            result.push(Platform::Android);
+        }
+        result.push(os);
+        result
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Symlink,
+    Copy,
+    Hardlink,
+}
+
+/// how `encrypt` entries are encrypted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptMode {
+    /// one *.enc file per file
+    #[default]
+    Files,
+    /// the source directory as a single archive
+    Archive,
+}
+
+/// how directories are linked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStrategy {
+    /// link the whole directory, or merge file by file if the target directory exists
+    #[default]
+    Dir,
+    /// create the target directory and link each direct child
+    Contents,
+    /// create target directories recursively and only link files, like `stow --no-folding`
+    Recursive,
+}
+
+/// content of created symbol links
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkType {
+    /// relative to the directory of the link, keeps working when home and repository move together
+    #[default]
+    Relative,
+    /// for tools or network mounted homes breaking with relative links across mount points
+    Absolute,
+}
+
+/// what to do on Windows when creating symbol links is not permitted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowsFallback {
+    /// directory junctions for directories and hard links for files
+    #[default]
+    Junction,
+    Copy,
+    Error,
+}
+
+/// what to do when the target of an entry is inside the target of another one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overlap {
+    #[default]
+    Warn,
+    Error,
+    Allow,
+}
+
+/// where patterns ignoring plaintexts of encrypted entries are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GitignoreMode {
+    /// the lkdots section of `gitignore`
+    #[default]
+    Gitignore,
+    /// `.git/info/exclude` of the repository, keeps the committed `.gitignore` clean
+    Exclude,
+    /// ignores are managed by the user
+    None,
+}
+
+/// `gitignore`, a path or several paths when entries span git repositories
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Gitignore {
+    Path(String),
+    Paths(Vec<String>),
+}
+
+impl Default for Gitignore {
+    fn default() -> Self {
+        Gitignore::Paths(vec![])
+    }
+}
+
+impl Gitignore {
+    pub fn paths(&self) -> Vec<String> {
+        match self {
+            Gitignore::Path(p) if p.is_empty() => vec![],
+            Gitignore::Path(p) => vec![p.clone()],
+            Gitignore::Paths(ps) => ps.clone(),
+        }
+    }
+}
+
+/// `to` of an entry, a path or paths keyed by platform
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Target {
+    Path(String),
+    Platforms(BTreeMap<Platform, String>),
+}
+
+impl Target {
+    fn on(&self, p: &Platform) -> Option<&String> {
+        match self {
+            Target::Path(s) => Some(s),
+            Target::Platforms(m) => m.get(p),
+        }
+    }
+    fn current(&self) -> Option<&String> {
+        match self {
+            Target::Path(s) => Some(s),
+            Target::Platforms(m) => current_platforms().iter().find_map(|p| m.get(p)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigFileEntry {
     pub from: String,
-    pub to: String,
-    pub platforms: Option<Vec<Platfrom>>,
+    pub to: Target,
+    pub platforms: Option<Vec<Platform>>,
+    /// CPU architectures like `x86_64` or `aarch64`, all if missing
+    pub arch: Option<Vec<String>>,
     pub encrypt: Option<bool>,
+    pub encrypt_mode: Option<EncryptMode>,
+    /// octal mode of *.enc files, defaults to the mode of their plaintext
+    pub ciphertext_mode: Option<String>,
+    /// gitignore of this entry instead of the closest top-level one
+    pub gitignore: Option<String>,
+    pub exclude: Option<Vec<String>>,
+    pub encrypt_exclude: Option<Vec<String>>,
+    /// encrypt files behind symbol links in the source tree instead of skipping them
+    pub encrypt_follow_symlinks: Option<bool>,
+    pub backup: Option<bool>,
+    pub recipients: Option<Vec<String>>,
+    pub tags: Option<Vec<String>>,
+    /// binaries the entry is for, `package` or `package:binary`
+    pub requires: Option<Vec<String>>,
+    /// shown instead of `from -> to` in output and errors
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// names of entries linked before this one
+    pub after: Option<Vec<String>>,
+    pub mode: Option<Mode>,
+    pub link_strategy: Option<LinkStrategy>,
+    pub symlink_type: Option<SymlinkType>,
+    pub chmod: Option<String>,
+    pub chown: Option<String>,
+    pub when: Option<String>,
+    pub pre_link: Option<String>,
+    pub post_link: Option<String>,
+}
+
+/// entries applied together with `lkdots apply <name>`, like a stow package
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigFilePackage {
+    pub name: String,
+    /// directory relative `from` of its entries are in, defaults to `name`
+    pub base_dir: Option<String>,
+    #[serde(default)]
+    pub entries: Vec<ConfigFileEntry>,
+}
+
+/// `[git]` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigFileGit {
+    pub auto_commit: Option<bool>,
+    pub commit_message: Option<String>,
+}
+
+/// `[hooks]` table, commands run around linking with JSON on stdin
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigFileHooks {
+    /// gets the entries about to be planned
+    pub pre_plan: Option<String>,
+    /// gets the plan before it is applied, failing aborts linking
+    pub pre_apply: Option<String>,
+    /// gets the applied plan
+    pub post_apply: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigFileStruct {
+    /// files merged after this one, later files override earlier ones
+    pub include: Option<Vec<String>>,
+    #[serde(default)]
     pub entries: Vec<ConfigFileEntry>,
-    pub gitignore: String,
+    #[serde(default)]
+    pub packages: Vec<ConfigFilePackage>,
+    /// `.gitignore` of the git repository containing the config file if missing
+    #[serde(default)]
+    pub gitignore: Gitignore,
+    pub gitignore_mode: Option<GitignoreMode>,
+    pub exclude: Option<Vec<String>>,
+    pub backup_dir: Option<String>,
+    pub identities: Option<Vec<String>>,
+    pub recipients: Option<Vec<String>>,
+    pub pre_link: Option<String>,
+    pub post_link: Option<String>,
+    pub windows_fallback: Option<WindowsFallback>,
+    pub remove_plaintext: Option<bool>,
+    pub crypt_buffer_size: Option<usize>,
+    pub max_file_size: Option<u64>,
+    /// suffix of ciphertexts, `.enc` by default
+    pub encrypted_suffix: Option<String>,
+    /// directory mirroring the repository with ciphertexts, instead of next to plaintexts
+    pub encrypted_dir: Option<String>,
+    /// ASCII armored ciphertexts, binary ones if `false`
+    pub armor: Option<bool>,
+    pub symlink_type: Option<SymlinkType>,
+    /// targets of entries nested in each other, e.g. `~/.config` and `~/.config/nvim`
+    pub overlap: Option<Overlap>,
+    pub git: Option<ConfigFileGit>,
+    pub hooks: Option<ConfigFileHooks>,
+}
+
+/// encrypted entries are meant for secrets, not large binaries
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// included files deeper than this are most likely a cycle
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// hostname is often a shell variable only, fall back to the `hostname` command
+fn env_var(name: &str) -> Result<Option<String>, std::env::VarError> {
+    match std::env::var(name) {
+        Ok(v) => Ok(Some(v)),
+        Err(_) if name == "HOSTNAME" => Ok(Command::new("hostname")
+            .output()
+            .ok()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())),
+        Err(err) => Err(err),
+    }
+}
+
+fn home_dir() -> Option<String> {
+    Some(shellexpand::tilde("~").to_string())
+}
+
+impl ConfigFileStruct {
+    /// parse config file at `path` with its content `s`, merging included files
+    pub fn load(path: &Path, s: &str) -> Result<ConfigFileStruct> {
+        ConfigFileStruct::load_included(path, s, 0)
+    }
+
+    fn load_included(path: &Path, s: &str, depth: usize) -> Result<ConfigFileStruct> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(anyhow!("Too deep includes at {}", path.display()));
+        }
+        validate::validate(path, s)?;
+        let mut c: ConfigFileStruct = toml::from_str(s)
+            .map_err(|e| Error::ParseError(format!("Fail to parse {}: {}", path.display(), e)))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        for inc in c.include.take().unwrap_or_default() {
+            let inc = shellexpand::full_with_context(&inc, home_dir, env_var)
+                .map_err(|e| anyhow!("Fail to expand include {}: {}", inc, e))?;
+            let inc_path = dir.join(inc.as_ref());
+            let inc_str = match read_to_string(&inc_path) {
+                Ok(s) => s,
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    // e.g. no host specific config for this machine
+                    info!("skip missing include {}", inc_path.display());
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            c = c.merge(ConfigFileStruct::load_included(
+                &inc_path,
+                &inc_str,
+                depth + 1,
+            )?);
+        }
+        Ok(c)
+    }
+
+    /// layer `other` over `self`, entries of `other` replace ones with the same `to`,
+    /// and packages replace ones with the same name
+    fn merge(mut self, other: ConfigFileStruct) -> ConfigFileStruct {
+        self.entries
+            .retain(|e| !other.entries.iter().any(|o| o.to == e.to));
+        self.entries.extend(other.entries);
+        self.packages
+            .retain(|p| !other.packages.iter().any(|o| o.name == p.name));
+        self.packages.extend(other.packages);
+        ConfigFileStruct {
+            include: None,
+            entries: self.entries,
+            packages: self.packages,
+            gitignore: if other.gitignore.paths().is_empty() {
+                self.gitignore
+            } else {
+                other.gitignore
+            },
+            gitignore_mode: other.gitignore_mode.or(self.gitignore_mode),
+            exclude: other.exclude.or(self.exclude),
+            backup_dir: other.backup_dir.or(self.backup_dir),
+            identities: other.identities.or(self.identities),
+            recipients: other.recipients.or(self.recipients),
+            pre_link: other.pre_link.or(self.pre_link),
+            post_link: other.post_link.or(self.post_link),
+            windows_fallback: other.windows_fallback.or(self.windows_fallback),
+            remove_plaintext: other.remove_plaintext.or(self.remove_plaintext),
+            crypt_buffer_size: other.crypt_buffer_size.or(self.crypt_buffer_size),
+            max_file_size: other.max_file_size.or(self.max_file_size),
+            encrypted_suffix: other.encrypted_suffix.or(self.encrypted_suffix),
+            encrypted_dir: other.encrypted_dir.or(self.encrypted_dir),
+            armor: other.armor.or(self.armor),
+            symlink_type: other.symlink_type.or(self.symlink_type),
+            overlap: other.overlap.or(self.overlap),
+            git: other.git.or(self.git),
+            hooks: other.hooks.or(self.hooks),
+        }
+    }
 }
 
 // END serde
 
 #[derive(Debug, Clone)]
 pub struct Entry<'a> {
+    /// name of the package containing this entry
+    pub package: Option<String>,
     pub from: Cow<'a, String>,
     pub to: Cow<'a, String>,
-    pub platforms: Cow<'a, Vec<Platfrom>>,
+    pub platforms: Cow<'a, Vec<Platform>>,
+    pub arch: Cow<'a, Vec<String>>,
     pub encrypt: bool,
+    pub ciphertext_mode: Option<String>,
+    pub gitignore: Option<String>,
+    pub exclude: Cow<'a, Vec<String>>,
+    pub encrypt_exclude: Cow<'a, Vec<String>>,
+    pub encrypt_follow_symlinks: bool,
+    /// `encrypt_mode = "archive"`
+    pub encrypt_archive: bool,
+    pub backup: bool,
+    pub backup_dir: Option<String>,
+    pub force: bool,
+    /// links lkdots created according to the state file, set before planning
+    pub owned_links: Option<Arc<HashSet<String>>>,
+    /// view shared by entries planned together, set before planning
+    pub fs: Option<Arc<dyn FsView>>,
+    /// where ciphertexts of this entry are, set by `Config::resolve_naming`
+    pub naming: Arc<Naming>,
+    pub recipients: Cow<'a, Vec<String>>,
+    pub tags: Cow<'a, Vec<String>>,
+    pub requires: Cow<'a, Vec<String>>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub after: Cow<'a, Vec<String>>,
+    pub mode: Mode,
+    pub link_strategy: LinkStrategy,
+    pub symlink_type: SymlinkType,
+    pub chmod: Option<String>,
+    pub chown: Option<String>,
+    pub when: Option<String>,
+    pub pre_link: Option<String>,
+    pub post_link: Option<String>,
+}
+
+/// symbol link pointing to one of its ancestors
+fn is_loop(err: &ignore::Error) -> bool {
+    match err {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+        _ => false,
+    }
 }
 
 impl<'a> Entry<'a> {
-    pub fn create_ops(&self, base_dir: &Path) -> Result<Vec<Op>> {
-        let from_osstr: OsString = if self.from.starts_with('/') || self.from.starts_with('~') {
-            self.from.as_ref().into()
+    /// `name` of the entry, or `from -> to` if it has none
+    pub fn label(&self) -> String {
+        match self.name.as_ref() {
+            Some(name) => name.clone(),
+            None => format!("{} -> {}", self.from, self.to),
+        }
+    }
+    pub fn expand_paths(&self, base_dir: &Path) -> Result<(String, String)> {
+        let from = expand_path(&self.from)?;
+        let from = if from.starts_with('/') || Path::new(&from).is_absolute() {
+            from
         } else {
-            base_dir.join(&self.from.as_ref()).into_os_string()
+            pathbuf_to_str(&base_dir.join(&from))?.to_owned()
         };
-        let from = from_osstr.to_str().unwrap();
-        let from = shellexpand::tilde(from);
-        let to = shellexpand::tilde(self.to.as_ref());
+        let to = expand_path(&self.to)?;
         debug!("from: {}, to: {}", from, to);
+        Ok((from, to))
+    }
+    /// expand glob pattern in `from` into pairs of (source, target)
+    pub fn expand_sources(&self, base_dir: &Path) -> Result<Vec<(String, String)>> {
+        let (from, to) = self.expand_paths(base_dir)?;
+        if !is_glob(&from) {
+            return Ok(vec![(from, to)]);
+        }
+        let base = glob_base(&from);
+        let mut result = vec![];
+        for path in glob::glob(&from)? {
+            let path = path?;
+            let target = Path::new(&to).join(path.strip_prefix(&base)?);
+            result.push((
+                pathbuf_to_str(&path)?.to_owned(),
+                pathbuf_to_str(&target)?.to_owned(),
+            ));
+        }
+        debug!("glob {} matched {} paths", from, result.len());
+        Ok(result)
+    }
+    pub fn exclude(&self, base_dir: &Path) -> Result<Exclude> {
+        let (from, _) = self.expand_paths(base_dir)?;
+        let root = if is_glob(&from) {
+            pathbuf_to_str(&glob_base(&from))?.to_owned()
+        } else {
+            from
+        };
+        Exclude::new(&root, &self.exclude)
+    }
+    pub fn link_options(&self, base_dir: &Path) -> Result<LinkOptions> {
+        let on_conflict = if self.backup {
+            OnConflict::Backup(self.backup_dir.clone())
+        } else {
+            OnConflict::Abort
+        };
+        Ok(LinkOptions {
+            exclude: self.exclude(base_dir)?,
+            on_conflict,
+            force: self.force,
+            owned_links: self.owned_links.clone(),
+            naming: self.naming.clone(),
+            fs: self
+                .fs
+                .clone()
+                .unwrap_or_else(|| Arc::new(CachedFs::default())),
+            mode: self.mode,
+            strategy: self.link_strategy,
+            symlink_type: self.symlink_type,
+        })
+    }
+    pub fn create_ops(&self, base_dir: &Path) -> Result<Vec<Op>> {
+        let opts = self.link_options(base_dir)?;
         let mut result = Vec::<Op>::new();
-        link_file_or_dir(from, to, &mut result)?;
+        if let Some(cmd) = self.pre_link.as_ref() {
+            result.push(Op::Run(cmd.clone()));
+        }
+        for (from, to) in self.expand_sources(base_dir)? {
+            if opts.exclude.is_excluded(&from) {
+                continue;
+            }
+            link_file_or_dir(Cow::Owned(from), Cow::Owned(to), &opts, &mut result)?;
+        }
+        let permission_ops = self.create_permission_ops(&result)?;
+        result.extend(permission_ops);
+        if let Some(cmd) = self.post_link.as_ref() {
+            result.push(Op::Run(cmd.clone()));
+        }
         Ok(result)
     }
+    /// `chmod` and `chown` of targets placed or kept by `ops`
+    pub fn create_permission_ops(&self, ops: &[Op]) -> Result<Vec<Op>> {
+        Ok(create_permission_ops(
+            ops,
+            self.file_mode()?,
+            self.chown.as_deref(),
+        ))
+    }
+    pub fn file_mode(&self) -> Result<Option<u32>> {
+        self.chmod.as_deref().map(parse_mode).transpose()
+    }
+    /// mode of *.enc files of this entry
+    pub fn ciphertext_mode(&self) -> Result<Option<u32>> {
+        self.ciphertext_mode.as_deref().map(parse_mode).transpose()
+    }
+    /// apply `chmod` of entry to a decrypted file
+    pub fn chmod_plaintext(&self, p: &str) -> Result<()> {
+        if let Some(mode) = self.file_mode()? {
+            set_mode(Path::new(p), mode).context(format!("Fail to chmod {}", p))?;
+        }
+        Ok(())
+    }
+    /// canonical source of an encrypted entry, a fresh clone may only have its ciphertexts
+    fn crypt_source(&self, base_dir: &Path) -> Result<PathBuf> {
+        let (from, _) = self.expand_paths(base_dir)?;
+        match canonicalize(&from) {
+            Ok(from) => Ok(from),
+            Err(_) if self.naming.mirror.is_some() || self.encrypt_archive => {
+                Ok(base_dir.join(from))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+    /// files `encrypt` and `decrypt` handle, only those under one of `paths` if given.
+    /// Mirrored ciphertexts of these files are included. An archive entry has its
+    /// source directory, if it has files to archive, and its archive
+    pub fn crypt_files(&self, base_dir: &Path, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let naming = &self.naming;
+        let from = self.crypt_source(base_dir)?;
+        if self.encrypt_archive {
+            // the archive is encrypted as a whole for any path in it
+            if !paths.is_empty() && !paths.iter().any(|p| p.starts_with(&from)) {
+                return Ok(vec![]);
+            }
+            let enc = naming.ciphertext(&archive_name(pathbuf_to_str(&from)?));
+            let mut result = vec![];
+            if !self.archive_files(base_dir)?.is_empty() {
+                result.push(from);
+            }
+            result.extend(Some(PathBuf::from(enc)).filter(|p| p.exists()));
+            return Ok(result);
+        }
+        let roots = if paths.is_empty() {
+            vec![from.clone()]
+        } else {
+            paths
+                .iter()
+                .filter(|p| p.starts_with(&from))
+                .cloned()
+                .collect()
+        };
+        // exclude patterns are relative to the source or its mirror
+        let mut result = vec![];
+        for root in roots {
+            self.walk_crypt_files(&from, &root, &mut result)?;
+            if let (Some(mirror_from), Some(mirror)) =
+                (naming.mirror_of(&from), naming.mirror_of(&root))
+            {
+                // a file has a single ciphertext
+                let mirror = if mirror.is_dir() {
+                    mirror
+                } else {
+                    PathBuf::from(naming.ciphertext(pathbuf_to_str(&root)?))
+                };
+                self.walk_crypt_files(&mirror_from, &mirror, &mut result)?;
+            }
+        }
+        Ok(result)
+    }
+    /// files an archive entry puts into its archive
+    pub fn archive_files(&self, base_dir: &Path) -> Result<Vec<PathBuf>> {
+        let from = self.crypt_source(base_dir)?;
+        let mut result = vec![];
+        self.walk_crypt_files(&from, &from, &mut result)?;
+        Ok(result)
+    }
+    /// files under `root` not excluded by patterns relative to `from`, sorted by path. Walked
+    /// with as many threads as the current rayon pool, `--jobs` when encrypting
+    fn walk_crypt_files(&self, from: &Path, root: &Path, result: &mut Vec<PathBuf>) -> Result<()> {
+        if !root.exists() {
+            return Ok(());
+        }
+        let follow = self.encrypt_follow_symlinks;
+        let exclude = Exclude::new(pathbuf_to_str(from)?, &self.encrypt_exclude)?;
+        // plaintexts are in the lkdots section of .gitignore, and dotfiles are hidden
+        let walker = WalkBuilder::new(root)
+            .standard_filters(false)
+            .follow_links(follow)
+            .threads(rayon::current_num_threads())
+            .filter_entry(move |e| {
+                if e.path_is_symlink() && !follow {
+                    warn!(
+                        "skip symbol link {}, set `encrypt_follow_symlinks` to encrypt it",
+                        e.path().display()
+                    );
+                    return false;
+                }
+                !is_vcs_path(e.path()) && !exclude.is_excluded(&e.path().to_string_lossy())
+            })
+            .build_parallel();
+        let files = Mutex::new(vec![]);
+        let error = Mutex::new(None);
+        walker.run(|| {
+            Box::new(|entry| {
+                let entry = match entry {
+                    Err(err) if is_loop(&err) => {
+                        warn!("skip symbol link loop: {}", err);
+                        return WalkState::Continue;
+                    }
+                    Err(err) => {
+                        *error.lock().unwrap() = Some(err);
+                        return WalkState::Quit;
+                    }
+                    Ok(entry) => entry,
+                };
+                if entry.file_type().is_some_and(|t| t.is_file()) {
+                    files.lock().unwrap().push(entry.into_path());
+                }
+                WalkState::Continue
+            })
+        });
+        if let Some(err) = error.into_inner().unwrap() {
+            return Err(err.into());
+        }
+        let mut files = files.into_inner().unwrap();
+        files.sort();
+        result.extend(files);
+        Ok(())
+    }
+    /// ciphertexts of an encrypted entry whose plaintext is missing
+    pub fn missing_plaintexts(&self, base_dir: &Path) -> Result<Vec<String>> {
+        let naming = &self.naming;
+        let (from, _) = self.expand_paths(base_dir)?;
+        // a fresh clone may only have the mirror or the archive of the source
+        if !Path::new(&from).exists() && naming.mirror.is_none() && !self.encrypt_archive {
+            return Ok(vec![]);
+        }
+        let files = self.crypt_files(base_dir, &[])?;
+        if self.encrypt_archive {
+            // only the archive is there, its source directory has no files
+            return Ok(match files.as_slice() {
+                [enc] if naming.is_ciphertext(pathbuf_to_str(enc)?) => {
+                    vec![pathbuf_to_str(enc)?.to_owned()]
+                }
+                _ => vec![],
+            });
+        }
+        let mut result = vec![];
+        for file in files {
+            let path = pathbuf_to_str(&file)?;
+            match naming.plaintext(path) {
+                Some(plain) if !Path::new(&plain).exists() => result.push(path.to_owned()),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+    pub fn create_adopt_ops(&self, base_dir: &Path) -> Result<Vec<Op>> {
+        let (from, to) = self.expand_paths(base_dir)?;
+        let mut result = Vec::<Op>::new();
+        if is_glob(&from) {
+            debug!("skip adopting glob entry {}", from);
+            return Ok(result);
+        }
+        adopt_file_or_dir(
+            Cow::Owned(from),
+            Cow::Owned(to),
+            self.symlink_type,
+            &mut result,
+        )?;
+        Ok(result)
+    }
+    pub fn create_repair_ops(&self, base_dir: &Path) -> Result<Vec<Op>> {
+        // dangling links are conflicts unless backup or force is set
+        let mut plain = self.clone();
+        plain.backup = false;
+        plain.force = false;
+        create_repair_ops(&plain.create_ops(base_dir)?, &plain.link_options(base_dir)?)
+    }
+    pub fn create_unlink_ops(&self, base_dir: &Path) -> Result<Vec<Op>> {
+        let opts = self.link_options(base_dir)?;
+        let mut result = Vec::<Op>::new();
+        for (from, to) in self.expand_sources(base_dir)? {
+            if opts.exclude.is_excluded(&from) {
+                continue;
+            }
+            unlink_file_or_dir(Cow::Owned(from), Cow::Owned(to), &opts, &mut result)?;
+        }
+        // links pointing to the source are left alone unless lkdots created them
+        result.retain(|op| match op {
+            Op::Unlink(to) if !self.owns_link(to) => {
+                warn!("skip {}, it was not linked by lkdots", to);
+                false
+            }
+            _ => true,
+        });
+        Ok(result)
+    }
+    /// `to` is a link lkdots created, or ownership is not known
+    pub fn owns_link(&self, to: &str) -> bool {
+        self.owned_links
+            .as_ref()
+            .is_none_or(|links| links.contains(to))
+    }
+    /// entry is for this platform and architecture
     pub fn match_platform(&self) -> bool {
-        self.platforms.iter().any(|p| p == PLATFORM)
+        self.platforms
+            .iter()
+            .any(|p| current_platforms().contains(p))
+            && (self.arch.is_empty() || self.arch.iter().any(|a| arch_name(a) == ARCH))
+    }
+    /// `when` condition of entry is true or not given
+    pub fn match_condition(&self) -> Result<bool> {
+        match self.when.as_ref() {
+            Some(when) => condition::eval(when),
+            None => Ok(true),
+        }
+    }
+    /// entry should have one of `tags` if given, and none of `skip_tags`
+    pub fn match_tags(&self, tags: &[String], skip_tags: &[String]) -> bool {
+        (tags.is_empty() || self.tags.iter().any(|t| tags.contains(t)))
+            && !self.tags.iter().any(|t| skip_tags.contains(t))
+    }
+    /// entry is in one of `packages`, or `packages` is empty
+    pub fn match_packages(&self, packages: &[String]) -> bool {
+        packages.is_empty()
+            || self
+                .package
+                .as_ref()
+                .map(|p| packages.contains(p))
+                .unwrap_or(false)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Config<'a> {
     pub entries: Vec<Entry<'a>>,
-    pub gitignore: String,
+    /// gitignore files, patterns of an entry go to the closest one above its source,
+    /// empty to detect the one of the repository
+    pub gitignore: Vec<String>,
+    pub gitignore_mode: GitignoreMode,
+    pub identities: Vec<String>,
+    pub recipients: Vec<String>,
+    pub pre_link: Option<String>,
+    pub post_link: Option<String>,
+    pub windows_fallback: WindowsFallback,
+    pub remove_plaintext: bool,
+    /// bytes read at a time when encrypting or decrypting
+    pub crypt_buffer_size: usize,
+    /// files larger than this are warned about when encrypting
+    pub max_file_size: u64,
+    pub encrypted_suffix: String,
+    /// relative to the config file
+    pub encrypted_dir: Option<String>,
+    pub armor: bool,
+    pub symlink_type: SymlinkType,
+    pub overlap: Overlap,
+    pub git: GitOptions,
+    pub hooks: ConfigFileHooks,
+}
+
+impl<'a> Config<'a> {
+    /// where ciphertexts are, `base_dir` is the directory of the config file
+    pub fn naming(&self, base_dir: &Path) -> Result<Naming> {
+        Naming::new(
+            &self.encrypted_suffix,
+            self.encrypted_dir.as_deref(),
+            base_dir,
+        )
+    }
+    /// resolve where ciphertexts are against `base_dir` for every entry
+    pub fn resolve_naming(&mut self, base_dir: &Path) -> Result<Arc<Naming>> {
+        let naming = Arc::new(self.naming(base_dir)?);
+        for e in self.entries.iter_mut() {
+            e.naming = naming.clone();
+        }
+        Ok(naming)
+    }
+}
+
+/// relative `from` of a package entry is in the package directory
+fn in_package(base: &str, from: &str) -> String {
+    if from.starts_with(['~', '$', '{']) || Path::new(from).is_absolute() {
+        return from.to_string();
+    }
+    Path::new(base).join(from).to_string_lossy().to_string()
 }
 
 impl From<ConfigFileStruct> for Config<'static> {
     fn from(c: ConfigFileStruct) -> Self {
+        let global_exclude = c.exclude.unwrap_or_default();
+        let backup_dir = c.backup_dir;
+        let encrypted_suffix = c
+            .encrypted_suffix
+            .unwrap_or_else(|| DEFAULT_SUFFIX.to_string());
+        // ciphertexts are next to their plaintexts until the mirror is resolved
+        let naming = Arc::new(Naming {
+            suffix: encrypted_suffix.clone(),
+            mirror: None,
+        });
         Config {
-            gitignore: c.gitignore,
+            gitignore: c.gitignore.paths(),
+            gitignore_mode: c.gitignore_mode.unwrap_or_default(),
+            identities: c.identities.unwrap_or_default(),
+            recipients: c.recipients.unwrap_or_default(),
+            pre_link: c.pre_link,
+            post_link: c.post_link,
+            windows_fallback: c.windows_fallback.unwrap_or_default(),
+            remove_plaintext: c.remove_plaintext.unwrap_or(false),
+            crypt_buffer_size: c.crypt_buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE),
+            max_file_size: c.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE),
+            encrypted_suffix,
+            encrypted_dir: c.encrypted_dir,
+            armor: c.armor.unwrap_or(true),
+            symlink_type: c.symlink_type.unwrap_or_default(),
+            overlap: c.overlap.unwrap_or_default(),
+            git: c
+                .git
+                .map(|g| GitOptions {
+                    auto_commit: g.auto_commit.unwrap_or(false),
+                    commit_message: g
+                        .commit_message
+                        .unwrap_or_else(|| DEFAULT_COMMIT_MESSAGE.to_string()),
+                })
+                .unwrap_or_default(),
+            hooks: c.hooks.unwrap_or_default(),
             entries: c
                 .entries
                 .into_iter()
-                .map(|e| Entry {
+                .map(|e| (None, e))
+                .chain(c.packages.into_iter().flat_map(|p| {
+                    let base = p.base_dir.unwrap_or_else(|| p.name.clone());
+                    let name = p.name;
+                    p.entries.into_iter().map(move |mut e| {
+                        e.from = in_package(&base, &e.from);
+                        (Some(name.clone()), e)
+                    })
+                }))
+                .map(|(package, e)| Entry {
+                    package,
                     from: Cow::Owned(e.from),
-                    to: Cow::Owned(e.to),
-                    platforms: Cow::Owned(e.platforms.unwrap_or_else(|| {
-                        vec![Platfrom::Linux, Platfrom::Darwin, Platfrom::Window]
-                    })),
+                    // entries without a target for this platform never match it
+                    to: Cow::Owned(e.to.current().cloned().unwrap_or_default()),
+                    platforms: Cow::Owned(
+                        e.platforms
+                            .unwrap_or_else(|| Platform::ALL.to_vec())
+                            .into_iter()
+                            .filter(|p| e.to.on(p).is_some())
+                            .collect(),
+                    ),
+                    arch: Cow::Owned(e.arch.unwrap_or_default()),
                     encrypt: e.encrypt.unwrap_or(false),
+                    exclude: Cow::Owned(
+                        global_exclude
+                            .iter()
+                            .cloned()
+                            .chain(e.exclude.unwrap_or_default())
+                            .collect(),
+                    ),
+                    encrypt_exclude: Cow::Owned(e.encrypt_exclude.unwrap_or_default()),
+                    encrypt_follow_symlinks: e.encrypt_follow_symlinks.unwrap_or(false),
+                    encrypt_archive: e.encrypt_mode == Some(EncryptMode::Archive),
+                    backup: e.backup.unwrap_or(false),
+                    backup_dir: backup_dir.clone(),
+                    force: false,
+                    owned_links: None,
+                    fs: None,
+                    naming: naming.clone(),
+                    recipients: Cow::Owned(e.recipients.unwrap_or_default()),
+                    tags: Cow::Owned(e.tags.unwrap_or_default()),
+                    requires: Cow::Owned(e.requires.unwrap_or_default()),
+                    name: e.name,
+                    description: e.description,
+                    after: Cow::Owned(e.after.unwrap_or_default()),
+                    mode: e.mode.unwrap_or_default(),
+                    link_strategy: e.link_strategy.unwrap_or_default(),
+                    symlink_type: e.symlink_type.or(c.symlink_type).unwrap_or_default(),
+                    ciphertext_mode: e.ciphertext_mode,
+                    gitignore: e.gitignore,
+                    chmod: e.chmod,
+                    chown: e.chown,
+                    when: e.when,
+                    pre_link: e.pre_link,
+                    post_link: e.post_link,
                 })
                 .collect(),
         }
     }
 }
+
+#[test]
+fn test_include() {
+    let dir = std::env::temp_dir().join("lkdots-test-include");
+    std::fs::create_dir_all(dir.join("hosts")).unwrap();
+    std::env::set_var("LKDOTS_TEST_HOST", "laptop");
+    std::fs::write(
+        dir.join("hosts/laptop.toml"),
+        "backup_dir = \"~/backup\"\n[[entries]]\nfrom = \"zsh-laptop\"\nto = \"~/.zshrc\"\n",
+    )
+    .unwrap();
+    let main = "gitignore = \".gitignore\"\ninclude = [\"hosts/${LKDOTS_TEST_HOST}.toml\", \"missing.toml\"]\n\
+        [[entries]]\nfrom = \"zsh\"\nto = \"~/.zshrc\"\n[[entries]]\nfrom = \"git\"\nto = \"~/.gitconfig\"\n";
+    let c = ConfigFileStruct::load(&dir.join("lkdots.toml"), main).unwrap();
+    let froms = c
+        .entries
+        .iter()
+        .map(|e| e.from.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(froms, vec!["git", "zsh-laptop"]);
+    assert_eq!(c.backup_dir.as_deref(), Some("~/backup"));
+    assert_eq!(c.gitignore.paths(), [".gitignore"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_packages() {
+    let s = "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"git\"\nto = \"~/.gitconfig\"\n\
+        [[packages]]\nname = \"nvim\"\n[[packages.entries]]\nfrom = \"init.lua\"\nto = \"~/.config/nvim/init.lua\"\n\
+        [[packages]]\nname = \"shell\"\nbase_dir = \"sh\"\n[[packages.entries]]\nfrom = \"zshrc\"\nto = \"~/.zshrc\"\n";
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    let froms = c
+        .entries
+        .iter()
+        .map(|e| (e.package.as_deref(), e.from.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        froms,
+        vec![
+            (None, "git"),
+            (Some("nvim"), "nvim/init.lua"),
+            (Some("shell"), "sh/zshrc")
+        ]
+    );
+    let nvim = ["nvim".to_string()];
+    assert!(c.entries[1].match_packages(&nvim));
+    assert!(!c.entries[0].match_packages(&nvim));
+    assert!(c.entries[0].match_packages(&[]));
+}
+
+#[test]
+fn test_entry_label() {
+    let s = "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"git\"\nto = \"~/.gitconfig\"\n\
+        [[entries]]\nname = \"neovim config\"\nfrom = \"nvim\"\nto = \"~/.config/nvim\"\n";
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    assert_eq!(c.entries[0].label(), "git -> ~/.gitconfig");
+    assert_eq!(c.entries[1].label(), "neovim config");
+}
+
+#[test]
+fn test_platforms() {
+    let s = "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"bashrc\"\nto = \"~/.bashrc\"\n\
+        platforms = [\"termux\", \"wsl\", \"freebsd\"]\n";
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    assert_eq!(
+        *c.entries[0].platforms,
+        vec![Platform::Android, Platform::Wsl, Platform::Freebsd]
+    );
+    assert_eq!(current_platforms().last().unwrap(), PLATFORM);
+}
+
+#[test]
+fn test_arch() {
+    let s = "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"tmux\"\nto = \"~/.tmux\"\n\
+        arch = [\"arm64\"]\n[[entries]]\nfrom = \"zshrc\"\nto = \"~/.zshrc\"\n";
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    assert_eq!(arch_name("arm64"), "aarch64");
+    assert_eq!(c.entries[0].match_platform(), ARCH == "aarch64");
+    assert!(c.entries[1].match_platform());
+}
+
+#[test]
+fn test_missing_plaintexts() {
+    let dir = std::env::temp_dir().join("lkdots-test-missing-plaintexts");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("ssh/keys")).unwrap();
+    for f in ["ssh/config", "ssh/config.enc", "ssh/keys/id.enc"] {
+        std::fs::write(dir.join(f), "").unwrap();
+    }
+    let s = "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"ssh\"\nto = \"~/.ssh\"\nencrypt = true\n\
+        [[entries]]\nfrom = \"gnupg\"\nto = \"~/.gnupg\"\nencrypt = true\n";
+    let c: Config = ConfigFileStruct::load(&dir.join("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    let missing = c.entries[0].missing_plaintexts(&dir).unwrap();
+    assert_eq!(
+        missing,
+        vec![pathbuf_to_str(&dir.join("ssh/keys/id.enc")).unwrap()]
+    );
+    // nothing to decrypt for a source not there
+    assert!(c.entries[1].missing_plaintexts(&dir).unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_resolve_naming() {
+    let dir = std::env::temp_dir().join("lkdots-test-resolve-naming");
+    let load = |s: &str| -> Config {
+        ConfigFileStruct::load(&dir.join("lkdots.toml"), s)
+            .unwrap()
+            .into()
+    };
+    let entry = "[[entries]]\nfrom = \"ssh\"\nto = \"~/.ssh\"\nencrypt = true\n";
+    let mut age = load(&format!("encrypted_suffix = \".age\"\n{}", entry));
+    // the suffix is known before the mirror is resolved
+    assert_eq!(age.entries[0].naming.suffix, ".age");
+    age.resolve_naming(&dir).unwrap();
+    let mut mirrored = load(&format!("encrypted_dir = \"secrets\"\n{}", entry));
+    let naming = mirrored.resolve_naming(&dir).unwrap();
+    // configs loaded side by side keep their own naming
+    assert_eq!(age.entries[0].naming.suffix, ".age");
+    assert!(age.entries[0].naming.mirror.is_none());
+    assert_eq!(mirrored.entries[0].naming, naming);
+    assert_eq!(naming.mirror, Some((dir.clone(), dir.join("secrets"))));
+}
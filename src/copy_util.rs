@@ -0,0 +1,76 @@
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::{
+    fs::{copy, create_dir_all, Metadata},
+    io::Result,
+    path::Path,
+};
+use walkdir::WalkDir;
+
+/// target has the same size and is not older than source
+pub fn is_up_to_date(src: &Metadata, dst: &Metadata) -> bool {
+    match (src.modified(), dst.modified()) {
+        (Ok(src_mtime), Ok(dst_mtime)) => src.len() == dst.len() && dst_mtime >= src_mtime,
+        _ => false,
+    }
+}
+
+/// target is missing or outdated
+pub fn needs_copy(src: &str, dst: &str) -> Result<bool> {
+    let src_meta = Path::new(src).metadata()?;
+    if let Ok(dst_meta) = Path::new(dst).symlink_metadata() {
+        if dst_meta.is_file() && is_up_to_date(&src_meta, &dst_meta) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+pub fn copy_file(src: &str, dst: &str) -> Result<()> {
+    copy(src, dst)?;
+    Ok(())
+}
+
+pub fn copy_dir_all(src: &str, dst: &str) -> Result<()> {
+    for entry in WalkDir::new(src) {
+        let entry = entry?;
+        let rel = entry
+            .path()
+            .strip_prefix(src)
+            .unwrap_or_else(|_| entry.path());
+        let target = Path::new(dst).join(rel);
+        if entry.file_type().is_dir() {
+            create_dir_all(&target)?;
+        } else {
+            copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// both metadata point to the same file, i.e. they are hard links of each other
+#[cfg(unix)]
+pub fn is_same_file(a: &Metadata, b: &Metadata) -> bool {
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+pub fn is_same_file(_a: &Metadata, _b: &Metadata) -> bool {
+    false
+}
+
+/// `dst` (or its closest existing parent) is on the same device as `src`
+#[cfg(unix)]
+pub fn is_same_device(src: &str, dst: &str) -> Result<bool> {
+    let src_meta = Path::new(src).metadata()?;
+    match Path::new(dst).ancestors().find(|p| p.exists()) {
+        Some(p) => Ok(p.metadata()?.dev() == src_meta.dev()),
+        None => Ok(false),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn is_same_device(_src: &str, _dst: &str) -> Result<bool> {
+    // let the hard link itself fail
+    Ok(true)
+}
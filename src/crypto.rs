@@ -1,49 +1,454 @@
+use crate::errors::Error;
+use crate::perm_util::{mode_of, set_mode};
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
 use age::cli_common::file_io::{OutputFormat, OutputWriter};
 use age::secrecy::Secret;
-use anyhow::Result;
-use log::debug;
-use std::fs::OpenOptions;
+use age::stream::StreamReader;
+use age::x25519;
+use age::{IdentityFile, IdentityFileEntry};
+use anyhow::{anyhow, Context, Result};
+use indicatif::ProgressBar;
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::fs::{create_dir_all, remove_file, rename, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
 #[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
-use std::io;
+use std::path::{Path, PathBuf};
 
-pub fn encrypt_file(src: &str, passphrase: &str) -> Result<()> {
-    debug!("passphrase length: {}", passphrase.len());
+/// suffix of ciphertexts if `encrypted_suffix` is not set
+pub const DEFAULT_SUFFIX: &str = ".enc";
+
+/// where the ciphertext of a plaintext is, `encrypted_suffix` and `encrypted_dir` of config
+#[derive(Debug, Clone, PartialEq)]
+pub struct Naming {
+    pub suffix: String,
+    /// the repository and the directory mirroring it with ciphertexts, ciphertexts are next to
+    /// their plaintexts without it
+    pub mirror: Option<(PathBuf, PathBuf)>,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Naming {
+            suffix: DEFAULT_SUFFIX.to_string(),
+            mirror: None,
+        }
+    }
+}
+
+impl Naming {
+    /// `dir` is relative to `base_dir`, the directory of the config file
+    pub fn new(suffix: &str, dir: Option<&str>, base_dir: &Path) -> Result<Naming> {
+        if suffix.is_empty() && dir.is_none() {
+            return Err(anyhow!(
+                "encrypted_suffix can only be empty with encrypted_dir"
+            ));
+        }
+        // sources are canonicalized when encrypting
+        let base = base_dir
+            .canonicalize()
+            .unwrap_or_else(|_| base_dir.to_path_buf());
+        Ok(Naming {
+            suffix: suffix.to_string(),
+            mirror: dir.map(|dir| {
+                let dir = base.join(dir);
+                (base, dir)
+            }),
+        })
+    }
+
+    /// directory with ciphertexts of the source directory `from`, if they are mirrored
+    pub fn mirror_of(&self, from: &Path) -> Option<PathBuf> {
+        let (base, mirror) = self.mirror.as_ref()?;
+        from.strip_prefix(base).ok().map(|rel| mirror.join(rel))
+    }
+
+    /// path of the ciphertext of `plain`. Sources outside of the repository keep their
+    /// ciphertexts next to them
+    pub fn ciphertext(&self, plain: &str) -> String {
+        match self.mirror_of(Path::new(plain)) {
+            Some(p) => format!("{}{}", p.to_string_lossy(), self.suffix),
+            None => format!("{}{}", plain, self.suffix),
+        }
+    }
+
+    /// path of the plaintext of `enc`, `None` if it is not a ciphertext
+    pub fn plaintext(&self, enc: &str) -> Option<String> {
+        let plain = enc.strip_suffix(&self.suffix)?;
+        let (base, mirror) = match self.mirror.as_ref() {
+            Some(m) => m,
+            None => return Some(plain.to_string()),
+        };
+        let plain = Path::new(plain);
+        match plain.strip_prefix(mirror) {
+            Ok(rel) => Some(base.join(rel).to_string_lossy().to_string()),
+            Err(_) if plain.starts_with(base) || self.suffix.is_empty() => None,
+            Err(_) => Some(plain.to_string_lossy().to_string()),
+        }
+    }
+
+    pub fn is_ciphertext(&self, p: &str) -> bool {
+        self.plaintext(p).is_some()
+    }
+
+    /// suffix of ciphertexts inside source directories, empty if they are mirrored
+    pub fn suffix_in_sources(&self) -> &str {
+        match self.mirror {
+            Some(_) => "",
+            None => &self.suffix,
+        }
+    }
+}
+
+/// key material used to encrypt or decrypt files
+#[derive(Clone)]
+pub enum Key {
+    Passphrase(String),
+    Identity {
+        identities: Vec<x25519::Identity>,
+        recipients: Vec<x25519::Recipient>,
+    },
+}
+
+impl Key {
+    /// read age identity files, files are encrypted to the public keys of these identities
+    /// and the extra `recipients`
+    pub fn from_identity_files(
+        paths: &[String],
+        recipients: Vec<x25519::Recipient>,
+    ) -> Result<Key> {
+        let mut identities = vec![];
+        for p in paths {
+            let p = shellexpand::tilde(p).to_string();
+            let file = IdentityFile::from_file(p.clone())
+                .context(format!("Fail to read identity file {}", p))?;
+            for entry in file.into_identities() {
+                match entry {
+                    IdentityFileEntry::Native(i) => identities.push(i),
+                    _ => warn!(
+                        "only native age identities are supported, skip one in {}",
+                        p
+                    ),
+                }
+            }
+        }
+        if !paths.is_empty() && identities.is_empty() {
+            return Err(anyhow!("No age identity found in {}", paths.join(", ")));
+        }
+        let recipients = identities
+            .iter()
+            .map(|i| i.to_public())
+            .chain(recipients)
+            .collect();
+        Ok(Key::Identity {
+            identities,
+            recipients,
+        })
+    }
+
+    fn encryptor(&self, extra_recipients: &[x25519::Recipient]) -> Result<age::Encryptor> {
+        match self {
+            Key::Passphrase(_) if !extra_recipients.is_empty() => Err(anyhow!(
+                "Cannot encrypt to recipients with a passphrase, use an identity file"
+            )),
+            Key::Passphrase(passphrase) => {
+                debug!("passphrase length: {}", passphrase.len());
+                Ok(age::Encryptor::with_user_passphrase(Secret::new(
+                    passphrase.to_owned(),
+                )))
+            }
+            Key::Identity { recipients, .. } => {
+                let recipients: Vec<Box<dyn age::Recipient>> = recipients
+                    .iter()
+                    .chain(extra_recipients)
+                    .map(|r| Box::new(r.clone()) as Box<dyn age::Recipient>)
+                    .collect();
+                if recipients.is_empty() {
+                    return Err(anyhow!("No recipient to encrypt to"));
+                }
+                Ok(age::Encryptor::with_recipients(recipients))
+            }
+        }
+    }
+}
+
+pub fn parse_recipients(recipients: &[String]) -> Result<Vec<x25519::Recipient>> {
+    recipients
+        .iter()
+        .map(|r| {
+            r.parse::<x25519::Recipient>()
+                .map_err(|e| anyhow!("Invalid age recipient {}: {}", r, e))
+        })
+        .collect()
+}
+
+/// sha256 of file content in hex
+pub fn hash_file(p: &str) -> Result<String> {
+    let mut f = OpenOptions::new().read(true).open(p)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut f, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// default size of chunks read from files while encrypting or decrypting
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// `io::copy` with a `buffer_size` buffer, advancing `bar` by bytes copied,
+/// so memory stays constant for any file size
+fn copy_stream(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    buffer_size: usize,
+    bar: &ProgressBar,
+) -> io::Result<u64> {
+    let mut buf = vec![0; buffer_size.max(1)];
+    let mut total = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        total += n as u64;
+        bar.inc(n as u64);
+    }
+    Ok(total)
+}
+
+/// encrypt `src` to a ciphertext next to it
+pub fn encrypt_file(src: &str, key: &Key, recipients: &[x25519::Recipient]) -> Result<()> {
+    encrypt_file_with_progress(
+        &Naming::default(),
+        src,
+        key,
+        recipients,
+        None,
+        false,
+        DEFAULT_BUFFER_SIZE,
+        &ProgressBar::hidden(),
+    )
+}
+
+fn armor_format(armor: bool) -> Format {
+    if armor {
+        Format::AsciiArmor
+    } else {
+        Format::Binary
+    }
+}
+
+/// first line of armored ciphertexts
+const ARMOR_BEGIN: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// `src` is an armored ciphertext
+fn is_armored(src: &str) -> Result<bool> {
+    let mut head = vec![0; ARMOR_BEGIN.len()];
+    let n = File::open(src)?.read(&mut head)?;
+    Ok(head[..n] == *ARMOR_BEGIN)
+}
+
+/// ciphertext is created with the mode of its plaintext, masked by umask, and an existing one
+/// loses permissions its plaintext doesn't have, so ciphertexts of private keys stay private
+fn chmod_ciphertext(src: &str, enc: &str, mode: Option<u32>) -> Result<()> {
+    let enc = Path::new(enc);
+    let res = match (mode, mode_of(Path::new(src)), mode_of(enc)) {
+        (Some(mode), _, _) => set_mode(enc, mode),
+        (None, Ok(src_mode), Ok(enc_mode)) if enc_mode & !src_mode != 0 => {
+            set_mode(enc, enc_mode & src_mode)
+        }
+        _ => Ok(()),
+    };
+    res.context(format!("Fail to chmod {}", enc.display()))
+}
+
+/// `encrypt_file`, reading `buffer_size` bytes at a time and advancing `bar` by bytes.
+/// The ciphertext of `src` gets `mode`, or the mode of `src` if it's `None`, and is ASCII
+/// armored with `armor`. The ciphertext is named by `naming`
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt_file_with_progress(
+    naming: &Naming,
+    src: &str,
+    key: &Key,
+    recipients: &[x25519::Recipient],
+    mode: Option<u32>,
+    armor: bool,
+    buffer_size: usize,
+    bar: &ProgressBar,
+) -> Result<()> {
     let mut reader = OpenOptions::new().read(true).open(src)?;
-    let encryptor = age::Encryptor::with_user_passphrase(Secret::new(passphrase.to_owned()));
-    let writer = OutputWriter::new(Some(format!("{}.enc", src)), OutputFormat::Text, 0o644)?;
-    let mut writer = encryptor.wrap_output(writer)?;
+    let enc = naming.ciphertext(src);
+    let create_mode = mode
+        .or_else(|| mode_of(Path::new(src)).ok())
+        .unwrap_or(0o644);
+    encrypt_with(&enc, key, recipients, create_mode, armor, |mut writer| {
+        copy_stream(&mut reader, &mut writer, buffer_size, bar)?;
+        Ok(())
+    })?;
 
-    io::copy(&mut reader, &mut writer)?;
-    writer.finish()?;
+    chmod_ciphertext(src, &enc, mode)
+}
 
+/// encrypt what `write` writes into `enc`, created with `create_mode`
+pub(crate) fn encrypt_with(
+    enc: &str,
+    key: &Key,
+    recipients: &[x25519::Recipient],
+    create_mode: u32,
+    armor: bool,
+    write: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    let encryptor = key.encryptor(recipients)?;
+    if let Some(dir) = Path::new(enc).parent() {
+        create_dir_all(dir)?;
+    }
+    let writer = OutputWriter::new(Some(enc.to_string()), OutputFormat::Text, create_mode)?;
+    let writer = ArmoredWriter::wrap_output(writer, armor_format(armor))?;
+    let mut writer = encryptor.wrap_output(writer)?;
+    write(&mut writer)?;
+    writer.finish()?.finish()?;
     Ok(())
 }
 
-pub fn decrypt_file(src: &str, passphrase: &str) -> Result<()> {
-    let strip_fname = &src[0..src.len() - 4];
+/// delete a plaintext file, overwrite its content with zeros first if `shred`
+pub fn remove_plaintext(p: &str, shred: bool) -> Result<()> {
+    if shred {
+        let mut f = OpenOptions::new().write(true).open(p)?;
+        let len = f.metadata()?.len();
+        io::copy(&mut io::repeat(0).take(len), &mut f)?;
+        f.flush()?;
+        f.sync_all()?;
+    }
+    remove_file(p).context(format!("Fail to remove {}", p))
+}
+
+/// plaintext of `src`, decrypted while reading, armored or not
+pub(crate) fn decrypt_reader(
+    src: &str,
+    key: &Key,
+) -> Result<StreamReader<ArmoredReader<BufReader<File>>>> {
     let encrypted_file = OpenOptions::new().create(false).read(true).open(src)?;
-    let decryptor = match age::Decryptor::new(encrypted_file)? {
-        age::Decryptor::Passphrase(d) => d,
-        _ => unreachable!(),
+    let encrypted_file = ArmoredReader::new(encrypted_file);
+    let crypto_error = |e: age::DecryptError| Error::CryptoError(format!("{}: {}", src, e));
+    let decryptor = age::Decryptor::new(encrypted_file).map_err(crypto_error)?;
+
+    let reader = match (decryptor, key) {
+        (age::Decryptor::Passphrase(d), Key::Passphrase(passphrase)) => d
+            .decrypt(&Secret::new(passphrase.to_owned()), None)
+            .map_err(crypto_error)?,
+        (age::Decryptor::Recipients(d), Key::Identity { identities, .. }) => d
+            .decrypt(identities.iter().map(|i| i as &dyn age::Identity))
+            .map_err(crypto_error)?,
+        (age::Decryptor::Passphrase(_), _) => {
+            return Err(
+                Error::CryptoError(format!("{} is encrypted with a passphrase", src)).into(),
+            )
+        }
+        (age::Decryptor::Recipients(_), _) => {
+            return Err(
+                Error::CryptoError(format!("{} is encrypted to age recipients", src)).into(),
+            )
+        }
     };
+    Ok(reader)
+}
+
+/// decrypt `src`, a ciphertext next to its plaintext
+pub fn decrypt_file(src: &str, key: &Key) -> Result<()> {
+    decrypt_file_with_progress(
+        &Naming::default(),
+        src,
+        key,
+        DEFAULT_BUFFER_SIZE,
+        &ProgressBar::hidden(),
+    )
+}
 
-    let mut decrypted = {
+/// `decrypt_file` of a ciphertext named by `naming`, reading `buffer_size` bytes at a time and
+/// advancing `bar` by bytes
+pub fn decrypt_file_with_progress(
+    naming: &Naming,
+    src: &str,
+    key: &Key,
+    buffer_size: usize,
+    bar: &ProgressBar,
+) -> Result<()> {
+    let plain = naming
+        .plaintext(src)
+        .context(format!("{} is not a ciphertext", src))?;
+    let strip_fname = plain.as_str();
+    let mut reader = decrypt_reader(src, key)?;
+
+    // a failure half way must not leave a truncated plaintext behind
+    let tmp = format!("{}.lkdots.tmp", strip_fname);
+    let res = (|| -> Result<()> {
+        if let Some(dir) = Path::new(strip_fname).parent() {
+            create_dir_all(dir)?;
+        }
         let mut op = OpenOptions::new();
 
-        op.create(true)
-        .write(true);
+        op.create(true).write(true).truncate(true);
 
         if cfg!(unix) {
             op.mode(0o600);
         }
-        let file = op.open(strip_fname)?;
-        file
-    };
-        
-    let mut reader = decryptor.decrypt(&Secret::new(passphrase.to_owned()), None)?;
-    io::copy(&mut reader, &mut decrypted)?;
-    Ok(())
+        let mut decrypted = op.open(&tmp)?;
+
+        copy_stream(&mut reader, &mut decrypted, buffer_size, bar)?;
+        decrypted.sync_all()?;
+        rename(&tmp, strip_fname)?;
+        Ok(())
+    })();
+    if res.is_err() {
+        let _ = remove_file(&tmp);
+    }
+    res.context(format!("Fail to decrypt {}", src))
+}
+
+/// sha256 of the plaintext of `src` in hex, plaintext is only streamed through memory
+pub fn hash_decrypted(src: &str, key: &Key) -> Result<String> {
+    let mut reader = decrypt_reader(src, key)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// re-encrypt `src` from `old` key to `new` key, plaintext is only streamed through memory.
+/// Armored ciphertexts stay armored
+pub fn rekey_file(src: &str, old: &Key, new: &Key, recipients: &[x25519::Recipient]) -> Result<()> {
+    let tmp = rekey_to_temp(src, old, new, recipients)?;
+    rename(&tmp, src).context(format!("Fail to rekey {}", src))
+}
+
+/// `rekey_file` into a temporary file next to `src`, which is returned, so ciphertexts can be
+/// replaced only after all of them are re-encrypted
+pub fn rekey_to_temp(
+    src: &str,
+    old: &Key,
+    new: &Key,
+    recipients: &[x25519::Recipient],
+) -> Result<String> {
+    let tmp = format!("{}.lkdots.tmp", src);
+    let res = (|| -> Result<()> {
+        let armor = is_armored(src)?;
+        let mut reader = decrypt_reader(src, old)?;
+        let encryptor = new.encryptor(recipients)?;
+        // keep the mode of the ciphertext
+        let mode = mode_of(Path::new(src)).unwrap_or(0o644);
+        let writer = OutputWriter::new(Some(tmp.clone()), OutputFormat::Text, mode)?;
+        let writer = ArmoredWriter::wrap_output(writer, armor_format(armor))?;
+        let mut writer = encryptor.wrap_output(writer)?;
+        io::copy(&mut reader, &mut writer)?;
+        writer.finish()?.finish()?;
+        Ok(())
+    })();
+    if res.is_err() {
+        let _ = remove_file(&tmp);
+    }
+    res.context(format!("Fail to rekey {}", src))?;
+    Ok(tmp)
 }
 
 #[cfg(test)]
@@ -52,16 +457,177 @@ mod tests {
 
     #[test]
     fn test_crypto() {
-        let passphrase = "abc";
-        let p = "./tests/test-data/private.key";
-        let original = std::fs::read_to_string(p).unwrap();
+        let key = Key::Passphrase("abc".to_string());
+        let p = fixture("lkdots-test-crypto.key");
+        let original = std::fs::read_to_string(&p).unwrap();
         let encrypted_path = format!("{}.enc", p);
-        encrypt_file(p, passphrase).unwrap();
-        decrypt_file(&encrypted_path, passphrase).unwrap();
+        encrypt_file(&p, &key, &[]).unwrap();
+        decrypt_file(&encrypted_path, &key).unwrap();
         let encrypted_str =
             std::fs::read_to_string(encrypted_path).unwrap_or_else(|_| "".to_string());
-        let decrypted_str = std::fs::read_to_string(p).unwrap();
+        let decrypted_str = std::fs::read_to_string(&p).unwrap();
         assert_eq!(original, decrypted_str);
         assert_ne!(original, encrypted_str)
     }
+
+    /// copy of the test key, so that tests running in parallel don't share files
+    fn fixture(name: &str) -> String {
+        let p = std::env::temp_dir().join(name);
+        std::fs::copy("./tests/test-data/private.key", &p).unwrap();
+        p.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_crypto_identity() {
+        let identity = x25519::Identity::generate();
+        let key = Key::Identity {
+            recipients: vec![identity.to_public()],
+            identities: vec![identity],
+        };
+        let p = fixture("lkdots-test-identity.key");
+        let original = std::fs::read_to_string(&p).unwrap();
+        let encrypted_path = format!("{}.enc", p);
+        encrypt_file(&p, &key, &[]).unwrap();
+        decrypt_file(&encrypted_path, &key).unwrap();
+        let decrypted_str = std::fs::read_to_string(&p).unwrap();
+        assert_eq!(original, decrypted_str);
+        assert!(decrypt_file(&encrypted_path, &Key::Passphrase("abc".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_crypto_multiple_recipients() {
+        let mine = x25519::Identity::generate();
+        let coworker = x25519::Identity::generate();
+        let key = Key::Identity {
+            recipients: vec![mine.to_public()],
+            identities: vec![mine],
+        };
+        let p = fixture("lkdots-test-recipients.key");
+        let original = std::fs::read_to_string(&p).unwrap();
+        let encrypted_path = format!("{}.enc", p);
+        encrypt_file(&p, &key, &[coworker.to_public()]).unwrap();
+
+        let coworker_key = Key::Identity {
+            identities: vec![coworker],
+            recipients: vec![],
+        };
+        decrypt_file(&encrypted_path, &coworker_key).unwrap();
+        assert_eq!(original, std::fs::read_to_string(&p).unwrap());
+
+        let passphrase = Key::Passphrase("abc".to_string());
+        assert!(
+            encrypt_file(&p, &passphrase, &[x25519::Identity::generate().to_public()]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_decrypt_corrupted() {
+        let key = Key::Passphrase("abc".to_string());
+        let p = fixture("lkdots-test-corrupted.key");
+        let encrypted_path = format!("{}.enc", p);
+        encrypt_file(&p, &key, &[]).unwrap();
+        let ciphertext = std::fs::read(&encrypted_path).unwrap();
+        std::fs::write(&encrypted_path, &ciphertext[..ciphertext.len() - 8]).unwrap();
+        std::fs::write(&p, "keep").unwrap();
+        assert!(decrypt_file(&encrypted_path, &key).is_err());
+        assert_eq!(std::fs::read_to_string(&p).unwrap(), "keep");
+        assert!(!std::path::Path::new(&format!("{}.lkdots.tmp", p)).exists());
+    }
+
+    #[test]
+    fn test_rekey() {
+        let old = Key::Passphrase("abc".to_string());
+        let new = Key::Passphrase("def".to_string());
+        let p = fixture("lkdots-test-rekey.key");
+        let original = std::fs::read_to_string(&p).unwrap();
+        let encrypted_path = format!("{}.enc", p);
+        encrypt_file(&p, &old, &[]).unwrap();
+        let tmp = rekey_to_temp(&encrypted_path, &old, &new, &[]).unwrap();
+        decrypt_file(&encrypted_path, &old).unwrap();
+        std::fs::rename(&tmp, &encrypted_path).unwrap();
+        assert!(decrypt_file(&encrypted_path, &old).is_err());
+        decrypt_file(&encrypted_path, &new).unwrap();
+        assert_eq!(original, std::fs::read_to_string(&p).unwrap());
+        assert!(rekey_file(&encrypted_path, &old, &new, &[]).is_err());
+        assert!(!std::path::Path::new(&format!("{}.lkdots.tmp", encrypted_path)).exists());
+    }
+
+    #[test]
+    fn test_crypto_armor() {
+        let key = Key::Passphrase("abc".to_string());
+        let p = fixture("lkdots-test-armor.key");
+        let original = std::fs::read_to_string(&p).unwrap();
+        let encrypted_path = format!("{}.enc", p);
+        let bar = ProgressBar::hidden();
+        encrypt_file_with_progress(
+            &Naming::default(),
+            &p,
+            &key,
+            &[],
+            None,
+            true,
+            DEFAULT_BUFFER_SIZE,
+            &bar,
+        )
+        .unwrap();
+        assert!(is_armored(&encrypted_path).unwrap());
+        rekey_file(&encrypted_path, &key, &key, &[]).unwrap();
+        assert!(is_armored(&encrypted_path).unwrap());
+        decrypt_file(&encrypted_path, &key).unwrap();
+        assert_eq!(original, std::fs::read_to_string(&p).unwrap());
+        encrypt_file(&p, &key, &[]).unwrap();
+        assert!(!is_armored(&encrypted_path).unwrap());
+        decrypt_file(&encrypted_path, &key).unwrap();
+        assert_eq!(original, std::fs::read_to_string(&p).unwrap());
+    }
+
+    #[test]
+    fn test_naming() {
+        let naming = Naming {
+            suffix: ".age".to_string(),
+            mirror: Some((PathBuf::from("/dots"), PathBuf::from("/dots/secrets"))),
+        };
+        assert_eq!(
+            naming.ciphertext("/dots/ssh/config"),
+            "/dots/secrets/ssh/config.age"
+        );
+        assert_eq!(
+            naming.plaintext("/dots/secrets/ssh/config.age").as_deref(),
+            Some("/dots/ssh/config")
+        );
+        // a plaintext which happens to have the suffix
+        assert_eq!(naming.plaintext("/dots/ssh/key.age"), None);
+        assert_eq!(naming.ciphertext("/etc/hosts"), "/etc/hosts.age");
+        assert!(naming.is_ciphertext("/etc/hosts.age"));
+        assert_eq!(Naming::default().plaintext("a.enc").as_deref(), Some("a"));
+        assert!(Naming::new("", None, Path::new("/dots")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ciphertext_mode() {
+        let key = Key::Passphrase("abc".to_string());
+        let p = fixture("lkdots-test-mode.key");
+        let enc = format!("{}.enc", p);
+        // ciphertext written by an older version
+        std::fs::write(&enc, "").unwrap();
+        set_mode(Path::new(&enc), 0o644).unwrap();
+        set_mode(Path::new(&p), 0o600).unwrap();
+        encrypt_file(&p, &key, &[]).unwrap();
+        assert_eq!(mode_of(Path::new(&enc)).unwrap() & 0o077, 0);
+        let buffer = DEFAULT_BUFFER_SIZE;
+        let bar = ProgressBar::hidden();
+        encrypt_file_with_progress(
+            &Naming::default(),
+            &p,
+            &key,
+            &[],
+            Some(0o640),
+            false,
+            buffer,
+            &bar,
+        )
+        .unwrap();
+        assert_eq!(mode_of(Path::new(&enc)).unwrap(), 0o640);
+    }
 }
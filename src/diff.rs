@@ -0,0 +1,76 @@
+use crate::{config::Entry, operations::Op, output};
+use anyhow::Result;
+use rayon::prelude::*;
+use similar::TextDiff;
+use std::{fs::read_to_string, path::Path};
+
+/// unified diff from the existing target to the source
+pub fn diff(from: &str, to: &str) -> String {
+    if Path::new(to).is_dir() || Path::new(from).is_dir() {
+        return format!("{} or {} is a directory\n", to, from);
+    }
+    match (read_to_string(to), read_to_string(from)) {
+        (Ok(old), Ok(new)) => TextDiff::from_lines(&old, &new)
+            .unified_diff()
+            .header(to, from)
+            .to_string(),
+        _ => format!("Cannot diff {} and {}, not text files\n", to, from),
+    }
+}
+
+/// unified diff of `path` from `old` to `new` content, empty if they are the same
+pub fn unified(path: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(path, path)
+        .to_string()
+}
+
+/// print diffs of conflicts in ops
+pub fn print_conflict_diffs(ops: &[Op]) {
+    for op in ops {
+        if let Op::Conflict(from, to) = op {
+            print!("{}", diff(from, to));
+        }
+    }
+}
+
+/// print diffs between conflicting targets and sources of entries
+pub fn print_diffs(entries: &[Entry], base_dir: &Path) -> Result<()> {
+    let opss = entries
+        .par_iter()
+        .filter(|e| e.match_platform())
+        .map(|e| {
+            // diff against what is there, instead of planned backups
+            let mut plain = e.clone();
+            plain.backup = false;
+            plain.force = false;
+            plain.create_ops(base_dir)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for (entry, ops) in entries.iter().filter(|e| e.match_platform()).zip(opss) {
+        if ops.iter().any(|op| matches!(op, Op::Conflict(_, _))) {
+            println!("{}", output::bold(entry.label()));
+            print_conflict_diffs(&ops);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_diff() {
+    let root = std::env::temp_dir().join("lkdots-test-diff");
+    std::fs::create_dir_all(&root).unwrap();
+    let from = root.join("from");
+    let to = root.join("to");
+    std::fs::write(&from, "a\nb\n").unwrap();
+    std::fs::write(&to, "a\nc\n").unwrap();
+    let d = diff(from.to_str().unwrap(), to.to_str().unwrap());
+    assert!(d.contains("-c\n"));
+    assert!(d.contains("+b\n"));
+    let d = unified(".gitignore", "target\n", "target\nssh/**\n");
+    assert!(d.starts_with("--- .gitignore\n+++ .gitignore\n"));
+    assert!(d.contains("+ssh/**\n"));
+    assert_eq!(unified(".gitignore", "target\n", "target\n"), "");
+    std::fs::remove_dir_all(&root).unwrap();
+}
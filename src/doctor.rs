@@ -0,0 +1,207 @@
+use crate::{
+    config::Entry,
+    output,
+    path_util::{is_glob, is_within},
+    requires::missing,
+    state::State,
+    status::{status_from_ops, Status},
+    symlink_util::can_write,
+};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::{collections::HashMap, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub hint: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn error(message: String, hint: &str) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            hint: hint.to_string(),
+        }
+    }
+    pub(crate) fn warning(message: String, hint: &str) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message,
+            hint: hint.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self.severity {
+            Severity::Error => output::red("error"),
+            Severity::Warning => output::yellow("warning"),
+        };
+        write!(f, "{}: {}\n  hint: {}", label, self.message, self.hint)
+    }
+}
+
+fn check_entry(e: &Entry, base_dir: &Path, result: &mut Vec<Diagnostic>) {
+    let (from, to) = match e.expand_paths(base_dir) {
+        Ok(paths) => paths,
+        Err(err) => {
+            result.push(Diagnostic::error(
+                err.to_string(),
+                "define the environment variable or fix `from` and `to`",
+            ));
+            return;
+        }
+    };
+    let from_exists = if is_glob(&from) {
+        matches!(e.expand_sources(base_dir), Ok(sources) if !sources.is_empty())
+    } else {
+        Path::new(&from).exists()
+    };
+    if !from_exists {
+        result.push(Diagnostic::error(
+            format!("source {} does not exist", from),
+            "fix `from` or run `lkdots adopt` to move the existing target into the repository",
+        ));
+        return;
+    }
+
+    if is_within(&to, &from) || (is_within(&from, &to) && !Path::new(&to).is_dir()) {
+        result.push(Diagnostic::error(
+            format!("target {} and source {} are inside each other", to, from),
+            "move `to` out of the source tree, linking it would create a loop",
+        ));
+        return;
+    }
+
+    // closest existing path the target will be created in
+    match Path::new(&to).ancestors().find(|p| p.exists()) {
+        Some(existing) if existing != Path::new(&to) && !existing.is_dir() => {
+            result.push(Diagnostic::error(
+                format!(
+                    "parent {} of target {} is not a directory",
+                    existing.display(),
+                    to
+                ),
+                "fix `to` or remove the file in the way",
+            ));
+        }
+        Some(existing) => {
+            let existing = existing.to_string_lossy();
+            if !can_write(&existing).unwrap_or(false) {
+                result.push(Diagnostic::error(
+                    format!("{} is not writable", existing),
+                    "check permissions of the target directory",
+                ));
+            }
+        }
+        None => {
+            result.push(Diagnostic::error(
+                format!("parent of target {} is unreachable", to),
+                "fix `to`",
+            ));
+        }
+    }
+
+    if let Ok(ops) = e.create_ops(base_dir) {
+        for (to, status) in status_from_ops(&ops) {
+            if status == Status::Broken {
+                result.push(Diagnostic::warning(
+                    format!("{} is a dangling symbol link", to),
+                    "remove it or rerun with --force",
+                ));
+            }
+        }
+    }
+}
+
+pub fn diagnose(entries: &[Entry], state: &State, base_dir: &Path) -> Vec<Diagnostic> {
+    let mut result = vec![];
+    let entries = entries
+        .iter()
+        .filter(|e| e.match_platform())
+        .collect::<Vec<_>>();
+
+    let mut targets: HashMap<String, Vec<String>> = HashMap::new();
+    for e in entries.iter() {
+        check_entry(e, base_dir, &mut result);
+        if let Ok((from, to)) = e.expand_paths(base_dir) {
+            targets.entry(to).or_default().push(from);
+        }
+    }
+    // several directories can be merged into the same target
+    for (to, froms) in targets {
+        if froms.len() > 1 && !froms.iter().all(|f| Path::new(f).is_dir()) {
+            result.push(Diagnostic::error(
+                format!("{} is the target of {}", to, froms.join(", ")),
+                "only directories can share the same `to`",
+            ));
+        }
+    }
+
+    for link in state.links.iter() {
+        let p = Path::new(&link.to);
+        if p.symlink_metadata()
+            .map(|m| m.is_symlink())
+            .unwrap_or(false)
+            && !p.exists()
+        {
+            result.push(Diagnostic::warning(
+                format!("{} created by lkdots is a dangling symbol link", link.to),
+                "run `lkdots prune` or link it again",
+            ));
+        }
+    }
+    for (r, froms) in missing(entries.iter().copied()) {
+        result.push(Diagnostic::warning(
+            format!(
+                "{} required by {} is not installed",
+                r.binary,
+                froms.join(", ")
+            ),
+            "install it or run `lkdots install-missing`",
+        ));
+    }
+    result
+}
+
+pub fn print_doctor(entries: &[Entry], state: &State, base_dir: &Path, json: bool) -> Result<()> {
+    report(&diagnose(entries, state, base_dir), json)
+}
+
+/// print `diagnostics`, failing if any of them is an error
+pub fn report(diagnostics: &[Diagnostic], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diagnostics)?);
+    } else {
+        for d in diagnostics.iter() {
+            println!("{}", d);
+        }
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    if errors > 0 {
+        return Err(anyhow!("{} errors, {} warnings", errors, warnings));
+    }
+    if !json {
+        println!(
+            "{} {} warnings",
+            output::green("everything looks fine,"),
+            warnings
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::io;
+use thiserror::Error;
+
+pub const EXIT_ERROR: i32 = 1;
+/// targets conflict with sources, nothing is changed or the conflicts are skipped
+pub const EXIT_CONFLICT: i32 = 2;
+pub const EXIT_CONFIG_NOT_FOUND: i32 = 3;
+pub const EXIT_PARSE_ERROR: i32 = 4;
+pub const EXIT_PERMISSION_DENIED: i32 = 5;
+pub const EXIT_CRYPTO_ERROR: i32 = 6;
+/// a passphrase or an answer is needed but nobody is at the terminal
+pub const EXIT_INPUT_REQUIRED: i32 = 7;
+
+/// errors scripts can tell apart by exit code, anything else exits with `EXIT_ERROR`
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(
+        "Cannot found config toml (default: lkdots.toml in current or parent directories, \
+        or ~/.config/lkdots/lkdots.toml)"
+    )]
+    ConfigNotFound,
+    /// invalid TOML, or a config failing validation
+    #[error("{0}")]
+    ParseError(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    PermissionDenied(String),
+    /// wrong key, or a corrupted or tampered ciphertext
+    #[error("{0}")]
+    CryptoError(String),
+    #[error("{0}")]
+    InputRequired(String),
+}
+
+/// exit code of `err`, `io::ErrorKind::PermissionDenied` anywhere in its chain is
+/// `EXIT_PERMISSION_DENIED` as well
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    for cause in err.chain() {
+        if let Some(e) = cause.downcast_ref::<Error>() {
+            return match e {
+                Error::ConfigNotFound => EXIT_CONFIG_NOT_FOUND,
+                Error::ParseError(_) => EXIT_PARSE_ERROR,
+                Error::Conflict(_) => EXIT_CONFLICT,
+                Error::PermissionDenied(_) => EXIT_PERMISSION_DENIED,
+                Error::CryptoError(_) => EXIT_CRYPTO_ERROR,
+                Error::InputRequired(_) => EXIT_INPUT_REQUIRED,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            if e.kind() == io::ErrorKind::PermissionDenied {
+                return EXIT_PERMISSION_DENIED;
+            }
+        }
+    }
+    EXIT_ERROR
+}
+
+#[test]
+fn test_exit_code() {
+    use anyhow::Context;
+    let err = anyhow::Error::from(Error::ConfigNotFound);
+    assert_eq!(exit_code(&err), EXIT_CONFIG_NOT_FOUND);
+    let err = Err::<(), _>(Error::CryptoError("bad key".to_string()))
+        .context("Fail to decrypt a.enc")
+        .unwrap_err();
+    assert_eq!(exit_code(&err), EXIT_CRYPTO_ERROR);
+    let err = anyhow::Error::from(io::Error::from(io::ErrorKind::PermissionDenied));
+    assert_eq!(exit_code(&err), EXIT_PERMISSION_DENIED);
+    assert_eq!(exit_code(&anyhow::anyhow!("oops")), EXIT_ERROR);
+}
@@ -0,0 +1,171 @@
+use anyhow::Result;
+use glob::{MatchOptions, Pattern};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use log::warn;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// version control directories, never encrypted
+pub const VCS_DIRS: [&str; 4] = [".git", ".hg", ".svn", ".jj"];
+
+/// gitignore-style exclusions next to the files, applying to its directory
+pub const IGNORE_FILE: &str = ".lkdotsignore";
+
+/// `.git` directory, or `.git` file of a submodule or worktree
+pub fn is_vcs_path(p: &Path) -> bool {
+    p.file_name()
+        .map(|name| VCS_DIRS.iter().any(|d| name == *d))
+        .unwrap_or(false)
+}
+
+/// glob patterns of files to skip, relative to the entry source, and `.lkdotsignore` files
+/// in the source tree
+#[derive(Debug, Clone)]
+pub struct Exclude {
+    root: PathBuf,
+    patterns: Vec<(bool, Pattern)>,
+    /// `.lkdotsignore` of each directory looked at, read the first time a path in it is checked
+    ignores: Arc<Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>>,
+}
+
+impl Exclude {
+    pub fn new(root: &str, patterns: &[String]) -> Result<Self> {
+        let mut compiled = vec![];
+        for p in patterns {
+            let has_sep = p.contains('/');
+            compiled.push((has_sep, Pattern::new(p)?));
+            // `dir/**` should exclude the directory itself too
+            if let Some(dir) = p.strip_suffix("/**") {
+                compiled.push((has_sep, Pattern::new(dir)?));
+            }
+        }
+        Ok(Exclude {
+            root: PathBuf::from(root),
+            patterns: compiled,
+            ignores: Arc::default(),
+        })
+    }
+
+    /// `.lkdotsignore` in `dir`, unreadable ones are warned about and skipped
+    fn ignore_of(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        let mut ignores = self.ignores.lock().unwrap();
+        let ignore = ignores.entry(dir.to_path_buf()).or_insert_with(|| {
+            let f = dir.join(IGNORE_FILE);
+            if !f.is_file() {
+                return None;
+            }
+            let mut builder = GitignoreBuilder::new(dir);
+            if let Some(err) = builder.add(&f) {
+                warn!("Fail to read {}: {}", f.display(), err);
+            }
+            match builder.build() {
+                Ok(ignore) => Some(Arc::new(ignore)),
+                Err(err) => {
+                    warn!("Fail to read {}: {}", f.display(), err);
+                    None
+                }
+            }
+        });
+        ignore.clone()
+    }
+
+    fn matches_patterns(&self, path: &Path) -> bool {
+        let rel = path.strip_prefix(&self.root).unwrap_or(path);
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+        self.patterns.iter().any(|(has_sep, p)| {
+            if *has_sep {
+                p.matches_path_with(rel, MATCH_OPTIONS)
+            } else {
+                p.matches_with(&name, MATCH_OPTIONS)
+            }
+        })
+    }
+
+    /// the closest `.lkdotsignore` with a matching rule decides, `!` patterns re-include
+    fn matches_ignores(&self, path: &Path) -> bool {
+        let is_dir = path.is_dir();
+        // deepest first, so nested files override their parents
+        let dirs = path
+            .ancestors()
+            .skip(1)
+            .take_while(|d| d.starts_with(&self.root));
+        for dir in dirs {
+            let Some(ignore) = self.ignore_of(dir) else {
+                continue;
+            };
+            match ignore.matched_path_or_any_parents(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+
+    pub fn is_excluded(&self, path: &str) -> bool {
+        let path = Path::new(path);
+        path.file_name().is_some_and(|n| n == IGNORE_FILE)
+            || self.matches_patterns(path)
+            || self.matches_ignores(path)
+    }
+}
+
+#[test]
+fn test_exclude() {
+    let exclude = Exclude::new(
+        "/dotfiles/zsh",
+        &[
+            "*.log".to_string(),
+            ".DS_Store".to_string(),
+            "node_modules/**".to_string(),
+        ],
+    )
+    .unwrap();
+    assert!(exclude.is_excluded("/dotfiles/zsh/a.log"));
+    assert!(exclude.is_excluded("/dotfiles/zsh/plugins/b.log"));
+    assert!(exclude.is_excluded("/dotfiles/zsh/.DS_Store"));
+    assert!(exclude.is_excluded("/dotfiles/zsh/node_modules"));
+    assert!(exclude.is_excluded("/dotfiles/zsh/node_modules/x/y.js"));
+    assert!(!exclude.is_excluded("/dotfiles/zsh/.zshrc"));
+    assert!(!exclude.is_excluded("/dotfiles/zsh/plugins/node_modules"));
+}
+
+#[test]
+fn test_lkdotsignore() {
+    let dir = std::env::temp_dir().join("lkdots-test-lkdotsignore");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("nvim/plugin/cache")).unwrap();
+    std::fs::write(dir.join("nvim/.lkdotsignore"), "*.log\ncache/\n").unwrap();
+    std::fs::write(dir.join("nvim/plugin/.lkdotsignore"), "!keep.log\n").unwrap();
+    let root = dir.join("nvim");
+    let exclude = Exclude::new(&root.to_string_lossy(), &[]).unwrap();
+    let excluded = |p: &str| exclude.is_excluded(&root.join(p).to_string_lossy());
+    assert!(excluded(".lkdotsignore"));
+    assert!(excluded("a.log"));
+    assert!(excluded("plugin/cache"));
+    assert!(excluded("plugin/cache/x.lua"));
+    assert!(!excluded("plugin/keep.log"));
+    assert!(!excluded("init.lua"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_is_vcs_path() {
+    assert!(is_vcs_path(Path::new("/dotfiles/nvim/.git")));
+    assert!(!is_vcs_path(Path::new("/dotfiles/nvim/.gitignore")));
+}
@@ -0,0 +1,157 @@
+use crate::{
+    add::contract_home,
+    config::{Entry, Mode, Platform, SymlinkType},
+    path_util::{is_glob, relative_path},
+};
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use serde_yaml::{Mapping, Value};
+use std::{fs::write, path::Path};
+
+/// name of the config dotbot's install script reads
+pub const DOTBOT_CONFIG: &str = "install.conf.yaml";
+
+/// dotbot `if` condition limiting a link to `platforms`, none when it is linked everywhere
+fn platform_condition(platforms: &[Platform]) -> Option<String> {
+    if platforms.len() == Platform::ALL.len() {
+        return None;
+    }
+    let conditions = platforms
+        .iter()
+        .map(|p| match p {
+            Platform::Linux => "[ \"$(uname)\" = Linux ]",
+            Platform::Darwin => "[ \"$(uname)\" = Darwin ]",
+            Platform::Windows => "[ \"$OS\" = Windows_NT ]",
+            Platform::Freebsd => "[ \"$(uname)\" = FreeBSD ]",
+            Platform::Openbsd => "[ \"$(uname)\" = OpenBSD ]",
+            Platform::Android => "[ \"$(uname -o)\" = Android ]",
+            Platform::Wsl => "grep -qi microsoft /proc/sys/kernel/osrelease",
+        })
+        .collect::<Vec<_>>();
+    Some(conditions.join(" || "))
+}
+
+/// warn about settings of `e` dotbot has no equivalent for
+fn warn_unsupported(e: &Entry) {
+    if e.encrypt {
+        warn!("{} is encrypted, decrypt it before running dotbot", e.from);
+    }
+    let lost = [
+        ("mode", e.mode != Mode::Symlink),
+        ("exclude", !e.exclude.is_empty()),
+        ("chmod", e.chmod.is_some()),
+        ("chown", e.chown.is_some()),
+        ("when", e.when.is_some()),
+    ];
+    for (key, _) in lost.iter().filter(|(_, set)| *set) {
+        warn!("`{}` of {} is not exported", key, e.from);
+    }
+}
+
+/// `install.conf.yaml` in `out_dir` linking `entries` of this platform like lkdots does
+pub fn to_dotbot(entries: &[Entry], base_dir: &Path, out_dir: &Path) -> Result<String> {
+    let mut pre = vec![];
+    let mut post = vec![];
+    let mut links = Mapping::new();
+    for e in entries {
+        if !e.match_platform() {
+            warn!(
+                "{} is not exported, it is not linked on this platform",
+                e.from
+            );
+            continue;
+        }
+        warn_unsupported(e);
+        let (from, to) = e.expand_paths(base_dir)?;
+        let path = relative_path(&from, out_dir.to_str().unwrap_or("."))?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let mut target = contract_home(&to);
+        let mut link = Mapping::new();
+        link.insert("path".into(), path.clone().into());
+        if is_glob(&from) {
+            link.insert("glob".into(), true.into());
+            target.push('/');
+        }
+        if let Some(condition) = platform_condition(&e.platforms) {
+            link.insert("if".into(), condition.into());
+        }
+        if e.symlink_type == SymlinkType::Absolute {
+            link.insert("relative".into(), false.into());
+        }
+        let link = if link.len() == 1 {
+            Value::String(path)
+        } else {
+            Value::Mapping(link)
+        };
+        if links.insert(target.clone().into(), link).is_some() {
+            return Err(anyhow!("{} is the target of several entries", target));
+        }
+        pre.extend(e.pre_link.iter().cloned().map(Value::from));
+        post.extend(e.post_link.iter().cloned().map(Value::from));
+    }
+
+    // lkdots creates parent directories and relative links by default
+    let mut defaults = Mapping::new();
+    defaults.insert("create".into(), true.into());
+    defaults.insert("relative".into(), true.into());
+    let mut directives = vec![directive("defaults", directive("link", defaults.into()))];
+    if !pre.is_empty() {
+        directives.push(directive("shell", pre.into()));
+    }
+    directives.push(directive("link", links.into()));
+    if !post.is_empty() {
+        directives.push(directive("shell", post.into()));
+    }
+    Ok(serde_yaml::to_string(&directives)?)
+}
+
+fn directive(name: &str, value: Value) -> Value {
+    let mut m = Mapping::new();
+    m.insert(name.into(), value);
+    Value::Mapping(m)
+}
+
+/// write dotbot config of `entries` to `out`, an absolute path, `simulate` only prints it
+pub fn export_dotbot(
+    entries: &[Entry],
+    base_dir: &Path,
+    out: &Path,
+    force: bool,
+    simulate: bool,
+) -> Result<()> {
+    let out_dir = out.parent().context("Fail to get output directory")?;
+    let config = to_dotbot(entries, base_dir, out_dir)?;
+    if simulate {
+        print!("{}", config);
+        return Ok(());
+    }
+    if out.exists() && !force {
+        return Err(anyhow!(
+            "{} already exists, use --force to overwrite it",
+            out.display()
+        ));
+    }
+    write(out, config)?;
+    // entries of other platforms are skipped by `to_dotbot`
+    let exported = entries.iter().filter(|e| e.match_platform()).count();
+    println!("exported {} entries to {}", exported, out.display());
+    Ok(())
+}
+
+#[test]
+fn test_to_dotbot() {
+    use crate::config::{Config, ConfigFileStruct};
+    let s = "[[entries]]\nfrom = \"git\"\nto = \"~/.gitconfig\"\n\
+        [[entries]]\nfrom = \"bin/*\"\nto = \"~/.local/bin\"\nsymlink_type = \"absolute\"\npost_link = \"echo done\"\n";
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    let yaml = to_dotbot(&c.entries, Path::new("/dots"), Path::new("/dots")).unwrap();
+    assert_eq!(
+        yaml,
+        "- defaults:\n    link:\n      create: true\n      relative: true\n\
+         - link:\n    ~/.gitconfig: git\n    ~/.local/bin/:\n      path: bin/*\n      glob: true\n      relative: false\n\
+         - shell:\n  - echo done\n"
+    );
+}
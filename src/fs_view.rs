@@ -0,0 +1,976 @@
+use crate::{config::WindowsFallback, copy_util, operations::Op, perm_util, symlink_util};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::OsString,
+    fmt::Debug,
+    fs::read_dir,
+    hash::Hash,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// type of a path itself, symbol links are not followed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+/// the filesystem as planning sees it, so entries sharing paths look them up once and
+/// planning can be tested without touching the disk
+pub trait FsView: Debug + Send + Sync {
+    /// type of `path`, `None` if it doesn't exist
+    fn kind(&self, path: &Path) -> Option<Kind>;
+    /// absolute `path` with symbol links resolved
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+    /// names in directory `path`, sorted so plans are the same on every run
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>>;
+
+    /// `path` exists, following symbol links
+    fn exists(&self, path: &Path) -> bool {
+        match self.kind(path) {
+            Some(Kind::Symlink) => self.canonicalize(path).is_ok(),
+            kind => kind.is_some(),
+        }
+    }
+    /// `path` is a directory, following symbol links
+    fn is_dir(&self, path: &Path) -> bool {
+        self.resolved_kind(path) == Some(Kind::Dir)
+    }
+    /// `path` is a file, following symbol links
+    fn is_file(&self, path: &Path) -> bool {
+        self.resolved_kind(path) == Some(Kind::File)
+    }
+    fn resolved_kind(&self, path: &Path) -> Option<Kind> {
+        match self.kind(path)? {
+            Kind::Symlink => self.kind(&self.canonicalize(path).ok()?),
+            kind => Some(kind),
+        }
+    }
+}
+
+/// filesystem changes made by `excute` and by writing ignore files, so they can be tested in
+/// memory and simulate can't touch the disk
+pub trait Fs: FsView {
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+    fn write(&self, path: &Path, contents: &str) -> io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// symbol link `dst` pointing to `relative`, which is `src` relative to `dst` or absolute
+    fn symlink(&self, src: &Path, dst: &Path, relative: &Path) -> io::Result<()>;
+    /// symbol link `dst` pointing to `target`, which may not exist
+    fn restore_symlink(&self, target: &Path, dst: &Path) -> io::Result<()>;
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+    fn remove_symlink(&self, path: &Path) -> io::Result<()>;
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// `to` is missing or outdated
+    fn needs_copy(&self, from: &Path, to: &Path) -> io::Result<bool>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn mode(&self, path: &Path) -> io::Result<u32>;
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()>;
+    /// uid and gid of `path`
+    fn owner(&self, path: &Path) -> io::Result<(u32, u32)>;
+    /// `owner` is `user`, `user:group` or `:group` like the `chown` command
+    fn chown(&self, path: &Path, owner: &str) -> anyhow::Result<()>;
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> io::Result<()>;
+
+    /// `path` itself if it is a file, otherwise every file below it, following symbol links
+    fn files(&self, path: &Path) -> Vec<PathBuf> {
+        if self.is_file(path) {
+            return vec![path.to_path_buf()];
+        }
+        self.read_dir(path)
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|name| self.files(&path.join(name)))
+            .collect()
+    }
+}
+
+fn kind_of(path: &Path) -> Option<Kind> {
+    let meta = path.symlink_metadata().ok()?;
+    Some(if meta.is_symlink() {
+        Kind::Symlink
+    } else if meta.is_dir() {
+        Kind::Dir
+    } else if meta.is_file() {
+        Kind::File
+    } else {
+        Kind::Other
+    })
+}
+
+fn sorted_names(path: &Path) -> io::Result<Vec<OsString>> {
+    let mut names = read_dir(path)?
+        .map(|e| e.map(|e| e.file_name()))
+        .collect::<io::Result<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+/// the real filesystem, every call goes to the disk
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RealFs {
+    /// what replaces symbol links Windows doesn't permit
+    pub windows_fallback: WindowsFallback,
+}
+
+impl FsView for RealFs {
+    fn kind(&self, path: &Path) -> Option<Kind> {
+        kind_of(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        sorted_names(path)
+    }
+}
+
+/// utils of this crate take `&str` paths
+fn to_str(p: &Path) -> io::Result<&str> {
+    p.to_str().ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not valid UTF-8", p.display()),
+        )
+    })
+}
+
+impl Fs for RealFs {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn write(&self, path: &Path, contents: &str) -> io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn symlink(&self, src: &Path, dst: &Path, relative: &Path) -> io::Result<()> {
+        symlink_util::create_symlink(
+            to_str(src)?,
+            to_str(dst)?,
+            to_str(relative)?,
+            self.windows_fallback,
+        )
+    }
+
+    fn restore_symlink(&self, target: &Path, dst: &Path) -> io::Result<()> {
+        symlink::symlink_auto(target, dst)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        std::fs::read_link(path)
+    }
+
+    fn remove_symlink(&self, path: &Path) -> io::Result<()> {
+        symlink_util::remove_symlink(to_str(path)?)
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::hard_link(from, to)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        copy_util::copy_file(to_str(from)?, to_str(to)?)
+    }
+
+    fn needs_copy(&self, from: &Path, to: &Path) -> io::Result<bool> {
+        copy_util::needs_copy(to_str(from)?, to_str(to)?)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_dir_all(path)
+    }
+
+    fn mode(&self, path: &Path) -> io::Result<u32> {
+        perm_util::mode_of(path)
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        perm_util::set_mode(path, mode)
+    }
+
+    fn owner(&self, path: &Path) -> io::Result<(u32, u32)> {
+        perm_util::owner_of(path)
+    }
+
+    fn chown(&self, path: &Path, owner: &str) -> anyhow::Result<()> {
+        perm_util::chown(path, owner)
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+        perm_util::restore_owner(path, uid, gid)
+    }
+
+    fn files(&self, path: &Path) -> Vec<PathBuf> {
+        perm_util::files(&path.to_string_lossy())
+    }
+}
+
+/// the real filesystem for simulate, reads go to the disk and every change fails, so a
+/// simulated run can't write even by mistake
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReadOnlyFs;
+
+fn read_only(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::PermissionDenied,
+        format!("simulate must not change {}", path.display()),
+    )
+}
+
+impl FsView for ReadOnlyFs {
+    fn kind(&self, path: &Path) -> Option<Kind> {
+        kind_of(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        sorted_names(path)
+    }
+}
+
+impl Fs for ReadOnlyFs {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        RealFs::default().read_to_string(path)
+    }
+
+    fn write(&self, path: &Path, _contents: &str) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn symlink(&self, _src: &Path, dst: &Path, _relative: &Path) -> io::Result<()> {
+        Err(read_only(dst))
+    }
+
+    fn restore_symlink(&self, _target: &Path, dst: &Path) -> io::Result<()> {
+        Err(read_only(dst))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        RealFs::default().read_link(path)
+    }
+
+    fn remove_symlink(&self, path: &Path) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn hard_link(&self, _from: &Path, to: &Path) -> io::Result<()> {
+        Err(read_only(to))
+    }
+
+    fn copy(&self, _from: &Path, to: &Path) -> io::Result<()> {
+        Err(read_only(to))
+    }
+
+    fn needs_copy(&self, from: &Path, to: &Path) -> io::Result<bool> {
+        RealFs::default().needs_copy(from, to)
+    }
+
+    fn rename(&self, from: &Path, _to: &Path) -> io::Result<()> {
+        Err(read_only(from))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn mode(&self, path: &Path) -> io::Result<u32> {
+        RealFs::default().mode(path)
+    }
+
+    fn set_mode(&self, path: &Path, _mode: u32) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn owner(&self, path: &Path) -> io::Result<(u32, u32)> {
+        RealFs::default().owner(path)
+    }
+
+    fn chown(&self, path: &Path, _owner: &str) -> anyhow::Result<()> {
+        Err(read_only(path).into())
+    }
+
+    fn set_owner(&self, path: &Path, _uid: u32, _gid: u32) -> io::Result<()> {
+        Err(read_only(path))
+    }
+
+    fn files(&self, path: &Path) -> Vec<PathBuf> {
+        RealFs::default().files(path)
+    }
+}
+
+/// the real filesystem, remembering every answer. Meant to live for one planning pass,
+/// changes made after a lookup are not seen
+#[derive(Debug, Default)]
+pub struct CachedFs {
+    kinds: Mutex<HashMap<PathBuf, Option<Kind>>>,
+    canonical: Mutex<HashMap<PathBuf, Result<PathBuf, ErrorKind>>>,
+    dirs: Mutex<HashMap<PathBuf, Result<Vec<OsString>, ErrorKind>>>,
+}
+
+/// value of `key` in `cache`, computed by `f` the first time. Entries planned in parallel
+/// may compute it twice, which gives the same answer
+fn memo<K: Eq + Hash + Clone, V: Clone>(
+    cache: &Mutex<HashMap<K, V>>,
+    key: &K,
+    f: impl FnOnce() -> V,
+) -> V {
+    if let Some(v) = cache.lock().unwrap().get(key) {
+        return v.clone();
+    }
+    let v = f();
+    cache.lock().unwrap().insert(key.clone(), v.clone());
+    v
+}
+
+impl FsView for CachedFs {
+    fn kind(&self, path: &Path) -> Option<Kind> {
+        memo(&self.kinds, &path.to_path_buf(), || kind_of(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        memo(&self.canonical, &path.to_path_buf(), || {
+            path.canonicalize().map_err(|e| e.kind())
+        })
+        .map_err(io::Error::from)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        memo(&self.dirs, &path.to_path_buf(), || {
+            sorted_names(path).map_err(|e| e.kind())
+        })
+        .map_err(io::Error::from)
+    }
+}
+
+/// what a planned op leaves at a path
+#[derive(Debug, Clone)]
+enum Change {
+    Removed,
+    Dir,
+    File,
+    /// symbol link to a source
+    Symlink(PathBuf),
+    /// moved from a path of the view below
+    Moved(PathBuf),
+}
+
+/// where a path is with the planned ops applied
+enum Place {
+    /// resolved path and what a planned op left there
+    Changed(PathBuf, Change),
+    /// path in the view below
+    Below(PathBuf),
+    Missing,
+}
+
+/// `base` as it is after planned ops, so an entry linked after others is planned against
+/// their results. Paths are absolute, what the ops leave in place is not read
+#[derive(Debug)]
+pub struct PlannedFs {
+    base: Arc<dyn FsView>,
+    changes: BTreeMap<PathBuf, Change>,
+}
+
+impl PlannedFs {
+    pub fn new(base: Arc<dyn FsView>, ops: &[Op]) -> Self {
+        let mut fs = PlannedFs {
+            base,
+            changes: BTreeMap::new(),
+        };
+        for op in ops {
+            fs.apply(op);
+        }
+        fs
+    }
+
+    fn apply(&mut self, op: &Op) {
+        match op {
+            Op::Mkdirp(p) => {
+                let mut ancestors = Path::new(p).ancestors().collect::<Vec<_>>();
+                ancestors.reverse();
+                for dir in ancestors {
+                    if !self.exists(dir) {
+                        self.set(dir, Change::Dir);
+                    }
+                }
+            }
+            Op::Symlink(from, to, _) => self.set(Path::new(to), Change::Symlink(from.into())),
+            Op::Copy(_, to) | Op::Hardlink(_, to) => self.set(Path::new(to), Change::File),
+            Op::Unlink(p) | Op::Remove(p) => self.set(Path::new(p), Change::Removed),
+            Op::Backup(from, to) | Op::Move(from, to) => {
+                if let Place::Below(moved) = self.locate(Path::new(from), false) {
+                    self.set(Path::new(to), Change::Moved(moved));
+                }
+                self.set(Path::new(from), Change::Removed);
+            }
+            _ => {}
+        }
+    }
+
+    /// `change` replaces whatever is at `path`, its parents resolved
+    fn set(&mut self, path: &Path, change: Change) {
+        let path = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => match self.locate(parent, true) {
+                Place::Changed(p, _) | Place::Below(p) => p.join(name),
+                Place::Missing => parent.join(name),
+            },
+            _ => path.to_path_buf(),
+        };
+        self.changes.retain(|p, _| !p.starts_with(&path));
+        self.changes.insert(path, change);
+    }
+
+    /// place of `path`, planned symbol links of its parents are followed, and of itself
+    /// if `follow`
+    fn locate(&self, path: &Path, follow: bool) -> Place {
+        let mut path = path.to_path_buf();
+        // links linking to each other are given up on like on disk
+        for _ in 0..40 {
+            match self.step(&path, follow) {
+                Ok(place) => return place,
+                Err(next) => path = next,
+            }
+        }
+        Place::Missing
+    }
+
+    /// place of `path`, or the path a planned symbol link redirects it to
+    fn step(&self, path: &Path, follow: bool) -> Result<Place, PathBuf> {
+        let mut ancestors = path.ancestors().collect::<Vec<_>>();
+        ancestors.reverse();
+        // nothing was below a created directory
+        let mut created = false;
+        for dir in ancestors {
+            let change = match self.changes.get(dir) {
+                Some(change) => change,
+                None => continue,
+            };
+            let rest = path.strip_prefix(dir).unwrap_or(path);
+            let is_self = rest.as_os_str().is_empty();
+            let join = |p: &Path| {
+                if is_self {
+                    p.to_path_buf()
+                } else {
+                    p.join(rest)
+                }
+            };
+            match change {
+                Change::Symlink(src) if !is_self || follow => return Err(join(src)),
+                Change::Moved(src) => return Ok(Place::Below(join(src))),
+                change if is_self => return Ok(Place::Changed(path.to_path_buf(), change.clone())),
+                Change::Dir => created = true,
+                _ => return Ok(Place::Missing),
+            }
+        }
+        Ok(match created {
+            true => Place::Missing,
+            false => Place::Below(path.to_path_buf()),
+        })
+    }
+}
+
+impl FsView for PlannedFs {
+    fn kind(&self, path: &Path) -> Option<Kind> {
+        match self.locate(path, false) {
+            Place::Changed(_, Change::Dir) => Some(Kind::Dir),
+            Place::Changed(_, Change::File) => Some(Kind::File),
+            Place::Changed(_, Change::Symlink(_)) => Some(Kind::Symlink),
+            Place::Below(p) => self.base.kind(&p),
+            _ => None,
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.locate(path, true) {
+            Place::Changed(p, Change::Dir | Change::File) => Ok(p),
+            Place::Below(p) => self.base.canonicalize(&p),
+            _ => Err(error(ErrorKind::NotFound, path)),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let (dir, mut names) = match self.locate(path, true) {
+            Place::Changed(p, Change::Dir) => (p, BTreeSet::new()),
+            Place::Below(p) => {
+                let names = self.base.read_dir(&p)?;
+                (p, names.into_iter().collect())
+            }
+            _ => return Err(error(ErrorKind::NotFound, path)),
+        };
+        let children = self
+            .changes
+            .iter()
+            .filter(|(p, _)| p.parent() == Some(dir.as_path()));
+        for (p, change) in children {
+            if let Some(name) = p.file_name() {
+                match change {
+                    Change::Removed => names.remove(name),
+                    _ => names.insert(name.to_os_string()),
+                };
+            }
+        }
+        Ok(names.into_iter().collect())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Dir,
+    File(String),
+    Symlink(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+struct Inode {
+    node: Node,
+    mode: u32,
+    owner: (u32, u32),
+}
+
+/// a filesystem in memory, for testing planning and execution without the disk. Paths are
+/// absolute, hard links are copies and copies are up to date when their content is the same
+#[derive(Debug)]
+pub struct MemFs {
+    inodes: Mutex<BTreeMap<PathBuf, Inode>>,
+}
+
+impl Default for MemFs {
+    fn default() -> Self {
+        MemFs::new()
+    }
+}
+
+fn error(kind: ErrorKind, path: &Path) -> io::Error {
+    io::Error::new(kind, path.display().to_string())
+}
+
+/// `path` with symbol links of its parents resolved, and of itself if `follow`
+fn resolve(inodes: &BTreeMap<PathBuf, Inode>, path: &Path, follow: bool) -> io::Result<PathBuf> {
+    let mut resolved = PathBuf::from("/");
+    let mut pending = path
+        .components()
+        .rev()
+        .map(|c| c.as_os_str().to_os_string())
+        .collect::<Vec<_>>();
+    let mut hops = 0;
+    while let Some(c) = pending.pop() {
+        match c.to_str() {
+            Some("/") => resolved = PathBuf::from("/"),
+            Some(".") => {}
+            Some("..") => {
+                resolved.pop();
+            }
+            _ => {
+                let next = resolved.join(&c);
+                match inodes.get(&next).map(|i| &i.node) {
+                    Some(Node::Symlink(target)) if follow || !pending.is_empty() => {
+                        hops += 1;
+                        if hops > 40 {
+                            return Err(error(ErrorKind::InvalidInput, path));
+                        }
+                        pending.extend(
+                            target
+                                .components()
+                                .rev()
+                                .map(|c| c.as_os_str().to_os_string()),
+                        );
+                    }
+                    _ => resolved = next,
+                }
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+impl MemFs {
+    /// an empty filesystem with only `/`
+    pub fn new() -> Self {
+        let root = Inode {
+            node: Node::Dir,
+            mode: 0o755,
+            owner: (0, 0),
+        };
+        MemFs {
+            inodes: Mutex::new(BTreeMap::from([(PathBuf::from("/"), root)])),
+        }
+    }
+
+    /// `f` on the inode at `path`
+    fn with<T>(
+        &self,
+        path: &Path,
+        follow: bool,
+        f: impl FnOnce(&mut Inode) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut inodes = self.inodes.lock().unwrap();
+        let p = resolve(&inodes, path, follow)?;
+        match inodes.get_mut(&p) {
+            Some(inode) => f(inode),
+            None => Err(error(ErrorKind::NotFound, path)),
+        }
+    }
+
+    /// add `node` at `path`, which must not exist yet, replaced if `replace`
+    fn insert(&self, path: &Path, node: Node, mode: u32, replace: bool) -> io::Result<()> {
+        let mut inodes = self.inodes.lock().unwrap();
+        let p = resolve(&inodes, path, replace)?;
+        let parent = p.parent().map(Path::to_path_buf).unwrap_or_default();
+        if !matches!(inodes.get(&parent).map(|i| &i.node), Some(Node::Dir)) {
+            return Err(error(ErrorKind::NotFound, path));
+        }
+        match inodes.get(&p).map(|i| &i.node) {
+            Some(Node::File(_)) if replace => {}
+            Some(_) => return Err(error(ErrorKind::AlreadyExists, path)),
+            None => {}
+        }
+        let owner = (0, 0);
+        inodes.insert(p, Inode { node, mode, owner });
+        Ok(())
+    }
+
+    /// remove the inode at `path` if `check` allows it, with everything below it
+    fn remove(&self, path: &Path, check: impl FnOnce(&Node, bool) -> bool) -> io::Result<()> {
+        let mut inodes = self.inodes.lock().unwrap();
+        let p = resolve(&inodes, path, false)?;
+        let node = &inodes
+            .get(&p)
+            .ok_or_else(|| error(ErrorKind::NotFound, path))?
+            .node;
+        let has_children = inodes.keys().any(|k| k.parent() == Some(p.as_path()));
+        if !check(node, has_children) {
+            return Err(error(ErrorKind::InvalidInput, path));
+        }
+        let is_symlink = matches!(node, Node::Symlink(_));
+        inodes.retain(|k, _| !(k == &p || (!is_symlink && k.starts_with(&p))));
+        Ok(())
+    }
+}
+
+impl FsView for MemFs {
+    fn kind(&self, path: &Path) -> Option<Kind> {
+        self.with(path, false, |i| {
+            Ok(match i.node {
+                Node::Dir => Kind::Dir,
+                Node::File(_) => Kind::File,
+                Node::Symlink(_) => Kind::Symlink,
+            })
+        })
+        .ok()
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let inodes = self.inodes.lock().unwrap();
+        let p = resolve(&inodes, path, true)?;
+        match inodes.contains_key(&p) {
+            true => Ok(p),
+            false => Err(error(ErrorKind::NotFound, path)),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let dir = self.canonicalize(path)?;
+        if !self.is_dir(&dir) {
+            return Err(error(ErrorKind::InvalidInput, path));
+        }
+        let inodes = self.inodes.lock().unwrap();
+        let mut names = inodes
+            .keys()
+            .filter(|k| k.parent() == Some(dir.as_path()))
+            .filter_map(|k| k.file_name().map(|n| n.to_os_string()))
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+}
+
+impl Fs for MemFs {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.with(path, true, |i| match &i.node {
+            Node::File(content) => Ok(content.clone()),
+            _ => Err(error(ErrorKind::InvalidInput, path)),
+        })
+    }
+
+    fn write(&self, path: &Path, contents: &str) -> io::Result<()> {
+        let mode = self.mode(path).unwrap_or(0o644);
+        self.insert(path, Node::File(contents.to_string()), mode, true)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut ancestors = path.ancestors().collect::<Vec<_>>();
+        ancestors.reverse();
+        for dir in ancestors {
+            match self.kind(dir).map(|_| self.is_dir(dir)) {
+                Some(true) => {}
+                Some(false) => return Err(error(ErrorKind::AlreadyExists, dir)),
+                None => self.insert(dir, Node::Dir, 0o755, false)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn symlink(&self, src: &Path, dst: &Path, relative: &Path) -> io::Result<()> {
+        if !self.exists(src) {
+            return Err(error(ErrorKind::NotFound, src));
+        }
+        self.restore_symlink(relative, dst)
+    }
+
+    fn restore_symlink(&self, target: &Path, dst: &Path) -> io::Result<()> {
+        self.insert(dst, Node::Symlink(target.to_path_buf()), 0o777, false)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.with(path, false, |i| match &i.node {
+            Node::Symlink(target) => Ok(target.clone()),
+            _ => Err(error(ErrorKind::InvalidInput, path)),
+        })
+    }
+
+    fn remove_symlink(&self, path: &Path) -> io::Result<()> {
+        self.remove(path, |node, _| matches!(node, Node::Symlink(_)))
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let content = self.read_to_string(from)?;
+        let mode = self.mode(from)?;
+        self.insert(to, Node::File(content), mode, false)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let content = self.read_to_string(from)?;
+        let mode = self.mode(from)?;
+        self.insert(to, Node::File(content), mode, true)
+    }
+
+    fn needs_copy(&self, from: &Path, to: &Path) -> io::Result<bool> {
+        let content = self.read_to_string(from)?;
+        Ok(self.kind(to) != Some(Kind::File) || self.read_to_string(to)? != content)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut inodes = self.inodes.lock().unwrap();
+        let (from_p, to_p) = (resolve(&inodes, from, false)?, resolve(&inodes, to, false)?);
+        if !inodes.contains_key(&from_p) {
+            return Err(error(ErrorKind::NotFound, from));
+        }
+        if inodes.keys().any(|k| k.parent() == Some(to_p.as_path())) {
+            return Err(error(ErrorKind::AlreadyExists, to));
+        }
+        let parent = to_p.parent().map(Path::to_path_buf).unwrap_or_default();
+        if !matches!(inodes.get(&parent).map(|i| &i.node), Some(Node::Dir)) {
+            return Err(error(ErrorKind::NotFound, to));
+        }
+        let moved = inodes
+            .keys()
+            .filter(|k| k.starts_with(&from_p))
+            .cloned()
+            .collect::<Vec<_>>();
+        inodes.remove(&to_p);
+        for k in moved {
+            let inode = inodes.remove(&k).unwrap();
+            let rel = k.strip_prefix(&from_p).unwrap();
+            inodes.insert(to_p.join(rel), inode);
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.remove(path, |node, _| !matches!(node, Node::Dir))
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.remove(path, |node, has_children| {
+            matches!(node, Node::Dir) && !has_children
+        })
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.remove(path, |_, _| true)
+    }
+
+    fn mode(&self, path: &Path) -> io::Result<u32> {
+        self.with(path, true, |i| Ok(i.mode))
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.with(path, true, |i| {
+            i.mode = mode;
+            Ok(())
+        })
+    }
+
+    fn owner(&self, path: &Path) -> io::Result<(u32, u32)> {
+        self.with(path, true, |i| Ok(i.owner))
+    }
+
+    /// only numeric ids, there are no user names in memory
+    fn chown(&self, path: &Path, owner: &str) -> anyhow::Result<()> {
+        let (user, group) = owner.split_once(':').unwrap_or((owner, ""));
+        let id = |s: &str| -> anyhow::Result<Option<u32>> {
+            match s {
+                "" => Ok(None),
+                s => Ok(Some(s.parse()?)),
+            }
+        };
+        let (uid, gid) = (id(user)?, id(group)?);
+        self.with(path, true, |i| {
+            i.owner = (uid.unwrap_or(i.owner.0), gid.unwrap_or(i.owner.1));
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    fn set_owner(&self, path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+        self.with(path, true, |i| {
+            i.owner = (uid, gid);
+            Ok(())
+        })
+    }
+}
+
+#[test]
+fn test_cached_fs() {
+    let dir = std::env::temp_dir().join("lkdots-test-fs-view");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("nvim")).unwrap();
+    std::fs::write(dir.join("nvim/init.lua"), "").unwrap();
+    let fs = CachedFs::default();
+    assert!(fs.is_dir(&dir.join("nvim")));
+    assert_eq!(fs.read_dir(&dir.join("nvim")).unwrap(), vec!["init.lua"]);
+    assert_eq!(fs.kind(&dir.join("zshrc")), None);
+    // answers are kept for the planning pass
+    std::fs::write(dir.join("zshrc"), "").unwrap();
+    assert_eq!(fs.kind(&dir.join("zshrc")), None);
+    assert_eq!(
+        fs.canonicalize(&dir.join("missing")).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mem_fs() {
+    let fs = MemFs::new();
+    fs.create_dir_all(Path::new("/dots/nvim")).unwrap();
+    fs.write(Path::new("/dots/nvim/init.lua"), "vim").unwrap();
+    fs.create_dir_all(Path::new("/home/a/.config")).unwrap();
+    fs.symlink(
+        Path::new("/dots/nvim"),
+        Path::new("/home/a/.config/nvim"),
+        Path::new("../../../dots/nvim"),
+    )
+    .unwrap();
+    let link = Path::new("/home/a/.config/nvim");
+    assert_eq!(fs.kind(link), Some(Kind::Symlink));
+    assert!(fs.is_dir(link));
+    assert_eq!(fs.canonicalize(link).unwrap(), Path::new("/dots/nvim"));
+    assert_eq!(fs.read_dir(link).unwrap(), vec!["init.lua"]);
+    assert_eq!(fs.read_to_string(&link.join("init.lua")).unwrap(), "vim");
+    // parents must exist like on disk
+    assert_eq!(
+        fs.write(Path::new("/missing/zshrc"), "")
+            .unwrap_err()
+            .kind(),
+        ErrorKind::NotFound
+    );
+    assert!(fs.remove_dir(Path::new("/dots")).is_err());
+    fs.remove_symlink(link).unwrap();
+    assert!(fs.exists(Path::new("/dots/nvim/init.lua")));
+    fs.rename(Path::new("/dots/nvim"), Path::new("/dots/vim"))
+        .unwrap();
+    assert_eq!(
+        fs.files(Path::new("/dots")),
+        vec![Path::new("/dots/vim/init.lua")]
+    );
+}
+
+#[test]
+fn test_planned_fs() {
+    let base = Arc::new(MemFs::new());
+    base.create_dir_all(Path::new("/dots/nvim")).unwrap();
+    base.write(Path::new("/dots/nvim/init.lua"), "").unwrap();
+    base.create_dir_all(Path::new("/home/a")).unwrap();
+    base.write(Path::new("/home/a/.zshrc"), "").unwrap();
+    let s = String::from;
+    let ops = [
+        Op::Mkdirp(s("/home/a/.config")),
+        Op::Symlink(s("/dots/nvim"), s("/home/a/.config/nvim"), s("")),
+        Op::Symlink(s("/dots/lua"), s("/home/a/.config/nvim/lua"), s("")),
+        Op::Backup(s("/home/a/.zshrc"), s("/home/a/.zshrc.bak")),
+    ];
+    let fs = PlannedFs::new(base.clone(), &ops);
+    let nvim = Path::new("/home/a/.config/nvim");
+    assert_eq!(fs.kind(nvim), Some(Kind::Symlink));
+    assert_eq!(fs.canonicalize(nvim).unwrap(), Path::new("/dots/nvim"));
+    assert_eq!(fs.read_dir(nvim).unwrap(), vec!["init.lua", "lua"]);
+    assert_eq!(fs.kind(&nvim.join("lua")), Some(Kind::Symlink));
+    assert_eq!(
+        fs.read_dir(Path::new("/home/a/.config")).unwrap(),
+        vec!["nvim"]
+    );
+    assert_eq!(fs.kind(Path::new("/home/a/.zshrc")), None);
+    assert_eq!(fs.kind(Path::new("/home/a/.zshrc.bak")), Some(Kind::File));
+    // the view below is left as it is
+    assert_eq!(base.kind(nvim), None);
+}
+
+#[test]
+fn test_read_only_fs() {
+    let dir = std::env::temp_dir().join("lkdots-test-read-only-fs");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("zshrc"), "zsh").unwrap();
+    let fs = ReadOnlyFs;
+    assert_eq!(fs.read_to_string(&dir.join("zshrc")).unwrap(), "zsh");
+    let err = fs.write(&dir.join("zshrc"), "").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert!(fs.create_dir_all(&dir.join("nvim")).is_err());
+    assert!(fs.rename(&dir.join("zshrc"), &dir.join(".zshrc")).is_err());
+    assert_eq!(std::fs::read_to_string(dir.join("zshrc")).unwrap(), "zsh");
+    assert_eq!(fs.read_dir(&dir).unwrap(), vec!["zshrc"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
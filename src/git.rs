@@ -0,0 +1,372 @@
+use crate::{
+    exclude::IGNORE_FILE,
+    init::{GITIGNORE_BEGIN, GITIGNORE_END},
+    perm_util::set_mode,
+};
+use anyhow::{anyhow, Context, Result};
+use git2::{
+    build::RepoBuilder, Cred, CredentialType, ErrorCode, FetchOptions, Index, RemoteCallbacks,
+    Repository,
+};
+use std::{
+    fs::{create_dir_all, read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+/// commit message used when `git.commit_message` is not set
+pub const DEFAULT_COMMIT_MESSAGE: &str = "lkdots: encrypt {count} files";
+
+#[derive(Debug, Clone)]
+pub struct GitOptions {
+    /// commit after every `lkdots encrypt` as if `--commit` is given
+    pub auto_commit: bool,
+    /// `{count}` and `{files}` are replaced with the number and the list of encrypted files
+    pub commit_message: String,
+}
+
+impl Default for GitOptions {
+    fn default() -> Self {
+        GitOptions {
+            auto_commit: false,
+            commit_message: DEFAULT_COMMIT_MESSAGE.to_string(),
+        }
+    }
+}
+
+fn open(dir: &Path) -> Result<Repository> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    Repository::discover(dir).context(format!("{} is not in a git repository", dir.display()))
+}
+
+/// working tree of the repository containing `dir`
+pub(crate) fn top_level(dir: &Path) -> Result<PathBuf> {
+    let repo = open(dir)?;
+    let top = repo
+        .workdir()
+        .context(format!("{} is in a bare repository", dir.display()))?;
+    Ok(top.to_path_buf())
+}
+
+/// `info/exclude` of the repository containing `dir`, shared by its worktrees
+pub(crate) fn exclude_file(dir: &Path) -> Result<PathBuf> {
+    Ok(open(dir)?.commondir().join("info").join("exclude"))
+}
+
+/// hooks directory of the repository containing `dir`, `core.hooksPath` if it is set
+pub(crate) fn hooks_dir(dir: &Path) -> Result<PathBuf> {
+    let repo = open(dir)?;
+    match repo.config()?.get_path("core.hooksPath") {
+        // relative to the top level, where hooks run
+        Ok(p) => Ok(top_level(dir)?.join(p)),
+        Err(_) => Ok(repo.commondir().join("hooks")),
+    }
+}
+
+/// `dir` relative to the top level of its repository
+pub(crate) fn in_work_tree(dir: &Path) -> Result<PathBuf> {
+    let top = top_level(dir)?.canonicalize()?;
+    let dir = dir.canonicalize()?;
+    Ok(dir
+        .strip_prefix(&top)
+        .context(format!("{} is not in the working tree", dir.display()))?
+        .to_path_buf())
+}
+
+/// files in the index below `dir`, relative to it
+pub(crate) fn tracked_files(dir: &Path) -> Result<Vec<String>> {
+    let repo = open(dir)?;
+    let prefix = in_work_tree(dir)?;
+    let index = repo.index()?;
+    let mut files = vec![];
+    for entry in index.iter() {
+        let path = String::from_utf8_lossy(&entry.path).to_string();
+        if let Ok(rel) = Path::new(&path).strip_prefix(&prefix) {
+            files.push(rel.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(files)
+}
+
+/// credentials of the ssh agent for ssh URLs and of git credential helpers for https ones
+fn credentials(
+    config: &git2::Config,
+    url: &str,
+    username: Option<&str>,
+    allowed: CredentialType,
+) -> std::result::Result<Cred, git2::Error> {
+    if allowed.contains(CredentialType::SSH_KEY) {
+        return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+    }
+    if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+        return Cred::credential_helper(config, url, username);
+    }
+    Cred::default()
+}
+
+/// clone `url` into `dest`, whose parent directories are created
+pub fn clone(url: &str, dest: &Path) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        create_dir_all(parent)?;
+    }
+    let config = git2::Config::open_default()?;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(|url, username, allowed| credentials(&config, url, username, allowed));
+    let mut fetch = FetchOptions::new();
+    fetch.remote_callbacks(callbacks);
+    RepoBuilder::new()
+        .fetch_options(fetch)
+        .clone(url, dest)
+        .context(format!("Fail to clone {}", url))?;
+    Ok(())
+}
+
+pub fn commit_message(template: &str, files: &[String]) -> String {
+    template
+        .replace("{count}", &files.len().to_string())
+        .replace("{files}", &files.join(", "))
+}
+
+/// stage `files` in the repository at `dir` and commit only them, other staged changes are
+/// left alone. `files` are relative to `dir`. Hooks are not run
+pub fn commit(dir: &Path, files: &[String], message: &str) -> Result<()> {
+    let repo = open(dir)?;
+    let prefix = in_work_tree(dir)?;
+    // index paths are relative to the top level
+    let files = files.iter().map(|f| prefix.join(f)).collect::<Vec<_>>();
+    let mut index = repo.index()?;
+    for f in files.iter() {
+        index
+            .add_path(f)
+            .context(format!("Fail to stage {}", f.display()))?;
+    }
+    index.write()?;
+    let head = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(err) if err.code() == ErrorCode::UnbornBranch => None,
+        Err(err) => return Err(err.into()),
+    };
+    // the committed tree is HEAD with only `files` taken from the index
+    let mut tree_index = Index::new()?;
+    if let Some(head) = head.as_ref() {
+        tree_index.read_tree(&head.tree()?)?;
+    }
+    for f in files.iter() {
+        let entry = index
+            .get_path(f, 0)
+            .context(format!("Fail to stage {}", f.display()))?;
+        tree_index.add(&entry)?;
+    }
+    let tree = repo.find_tree(tree_index.write_tree_to(&repo)?)?;
+    let signature = repo
+        .signature()
+        .context("Fail to get the committer, set user.name and user.email of git")?;
+    let parents = head.iter().collect::<Vec<_>>();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )
+    .context("Fail to commit")?;
+    Ok(())
+}
+
+/// first line after the shebang, marks hooks which are safe to overwrite
+const HOOK_MARKER: &str = "# installed by `lkdots githook install`";
+
+/// quote `s` as a single word of a POSIX shell
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// `dirs` of encrypted entries are read from the `dir/**` lines of the lkdots section of
+/// `gitignore`, so the hook follows config changes without being reinstalled. Files ending
+/// with `suffix` are ciphertexts, there are none in sources if it is empty
+fn pre_commit_hook(gitignore: &str, prefix: &str, suffix: &str) -> String {
+    format!(
+        r#"#!/bin/sh
+{marker}
+# refuses to commit plaintext files of encrypted entries
+gitignore={gitignore}
+prefix={prefix}
+suffix={suffix}
+[ -f "$gitignore" ] || exit 0
+dirs=$(sed -n -e '/^{begin}$/,/^{end}$/s|^\([^!#].*\)/\*\*$|\1|p' -e 's|^!\(.*\)/\*\.enc$|\1|p' "$gitignore")
+[ -n "$dirs" ] || exit 0
+leaks=$(git diff --cached --name-only --diff-filter=ACMR | while IFS= read -r f; do
+    if [ -n "$suffix" ]; then
+        case "$f" in *"$suffix") continue ;; esac
+    fi
+    case "$f" in */{ignore_file}) continue ;; esac
+    printf '%s\n' "$dirs" | while IFS= read -r d; do
+        case "$f" in "$prefix$d"/*) printf '%s\n' "$f" ;; esac
+    done
+done)
+if [ -n "$leaks" ]; then
+    echo "lkdots: refusing to commit plaintext of encrypted entries:" >&2
+    printf '%s\n' "$leaks" | sed 's/^/  /' >&2
+    echo "run \`lkdots encrypt\` and \`git rm --cached\` them, or commit with --no-verify" >&2
+    exit 1
+fi
+"#,
+        marker = HOOK_MARKER,
+        ignore_file = IGNORE_FILE,
+        gitignore = sh_quote(gitignore),
+        prefix = sh_quote(prefix),
+        suffix = sh_quote(suffix),
+        begin = GITIGNORE_BEGIN,
+        end = GITIGNORE_END,
+    )
+}
+
+/// write a pre-commit hook to the repository of `gitignore`, whose patterns are relative to
+/// `dir`, an existing hook not written by lkdots is only replaced with `force`. `simulate`
+/// only returns the path of the hook
+pub fn install_pre_commit(
+    gitignore: &Path,
+    dir: &Path,
+    suffix: &str,
+    force: bool,
+    simulate: bool,
+) -> Result<PathBuf> {
+    let name = match gitignore.strip_prefix(dir) {
+        Ok(name) => name.to_string_lossy().replace('\\', "/"),
+        // e.g. info/exclude of a worktree
+        Err(_) => gitignore.to_string_lossy().to_string(),
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let top = top_level(dir)?;
+    let hooks = hooks_dir(dir)?;
+    let hook = hooks.join("pre-commit");
+    match read_to_string(&hook) {
+        Ok(s) if !s.contains(HOOK_MARKER) && !force => {
+            return Err(anyhow!(
+                "{} already exists, use --force to replace it",
+                hook.display()
+            ))
+        }
+        _ => {}
+    }
+    let dir = dir.canonicalize()?;
+    let top = top.canonicalize()?;
+    // patterns are relative to the directory of .gitignore, hooks run at the top level
+    let prefix = dir
+        .strip_prefix(&top)
+        .context("gitignore is not in the git repository")?
+        .to_string_lossy()
+        .replace('\\', "/");
+    let prefix = if prefix.is_empty() {
+        prefix
+    } else {
+        format!("{}/", prefix)
+    };
+    let gitignore = if Path::new(&name).is_absolute() {
+        name
+    } else {
+        format!("{}{}", prefix, name)
+    };
+    if simulate {
+        return Ok(hook);
+    }
+    create_dir_all(&hooks)?;
+    write(&hook, pre_commit_hook(&gitignore, &prefix, suffix))?;
+    set_mode(&hook, 0o755)?;
+    Ok(hook)
+}
+
+#[test]
+fn test_commit_message() {
+    let files = vec!["ssh/config.enc".to_string(), "gnupg/key.enc".to_string()];
+    assert_eq!(
+        commit_message(DEFAULT_COMMIT_MESSAGE, &files),
+        "lkdots: encrypt 2 files"
+    );
+    assert_eq!(
+        commit_message("secrets: {files}", &files),
+        "secrets: ssh/config.enc, gnupg/key.enc"
+    );
+}
+
+#[test]
+fn test_pre_commit_hook() {
+    let dir = std::env::temp_dir().join("lkdots-test-pre-commit");
+    let _ = std::fs::remove_dir_all(&dir);
+    let dotfiles = dir.join("dotfiles");
+    create_dir_all(dotfiles.join("ssh")).unwrap();
+    let repo = Repository::init(&dir).unwrap();
+    let gitignore = dotfiles.join(".gitignore");
+    write(
+        &gitignore,
+        format!("{}\nssh/**\n{}\n", GITIGNORE_BEGIN, GITIGNORE_END),
+    )
+    .unwrap();
+    let run = |suffix: &str, files: &[&str]| {
+        let hook = install_pre_commit(&gitignore, &dotfiles, suffix, false, false).unwrap();
+        let mut index = repo.index().unwrap();
+        index.clear().unwrap();
+        for f in files {
+            write(dotfiles.join(f), "").unwrap();
+            index.add_path(&Path::new("dotfiles").join(f)).unwrap();
+        }
+        index.write().unwrap();
+        std::process::Command::new("sh")
+            .arg(&hook)
+            .current_dir(&dir)
+            .output()
+            .unwrap()
+            .status
+            .success()
+    };
+    // quotes in the suffix don't break the hook
+    assert!(run(".it's", &["ssh/key.it's"]));
+    assert!(!run(".it's", &["ssh/key.it's", "ssh/key"]));
+    // ciphertexts are mirrored outside of sources
+    assert!(!run("", &["ssh/key.enc"]));
+    assert!(run("", &[".gitignore"]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_commit() {
+    let dir = std::env::temp_dir().join("lkdots-test-commit");
+    let _ = std::fs::remove_dir_all(&dir);
+    let dotfiles = dir.join("dotfiles");
+    create_dir_all(&dotfiles).unwrap();
+    let repo = Repository::init(&dir).unwrap();
+    let mut config = repo.config().unwrap();
+    config.set_str("user.name", "lkdots").unwrap();
+    config.set_str("user.email", "lkdots@example.com").unwrap();
+    for f in ["config.enc", "notes", ".gitignore"] {
+        write(dotfiles.join(f), f).unwrap();
+    }
+    // staged by the user, not part of the commit
+    let mut index = repo.index().unwrap();
+    index.add_path(Path::new("dotfiles/notes")).unwrap();
+    index.write().unwrap();
+    let files = ["config.enc".to_string(), ".gitignore".to_string()];
+    commit(&dotfiles, &files, "lkdots: encrypt 1 files").unwrap();
+    commit(&dotfiles, &files[..1], "unchanged").unwrap();
+
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.message(), Some("unchanged"));
+    assert_eq!(head.parent_count(), 1);
+    let tree = head.tree().unwrap();
+    assert!(tree.get_path(Path::new("dotfiles/config.enc")).is_ok());
+    assert!(tree.get_path(Path::new("dotfiles/.gitignore")).is_ok());
+    assert!(tree.get_path(Path::new("dotfiles/notes")).is_err());
+    assert_eq!(
+        tracked_files(&dotfiles).unwrap(),
+        vec![".gitignore", "config.enc", "notes"]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,445 @@
+use crate::{
+    config::{Config, Entry, GitignoreMode},
+    diff::unified,
+    exclude::IGNORE_FILE,
+    fs_view::Fs,
+    git,
+    init::{GITIGNORE_BEGIN, GITIGNORE_END},
+    output,
+    path_util::{expand_path, pathbuf_to_str, relative_path},
+    state::State,
+};
+use anyhow::{Context, Result};
+use log::debug;
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// absolute path of gitignore `p`, relative paths are relative to the config directory
+pub fn resolve(p: &str, base_dir: &Path) -> Result<PathBuf> {
+    Ok(base_dir.join(expand_path(p)?))
+}
+
+/// `.gitignore` at the root of the git repository containing `base_dir`, or in `base_dir` if
+/// it's not in a repository
+pub fn detect(base_dir: &Path) -> PathBuf {
+    // relative `-c` paths must still find repositories above the current directory
+    let base_dir = &std::env::current_dir()
+        .map(|cwd| cwd.join(base_dir))
+        .unwrap_or_else(|_| base_dir.to_path_buf());
+    let root = base_dir
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or_else(|| {
+            debug!("{} is not in a git repository", base_dir.display());
+            base_dir
+        });
+    root.join(".gitignore")
+}
+
+/// configured gitignore files, or the detected one
+fn gitignores(cfg: &Config, base_dir: &Path) -> Result<Vec<PathBuf>> {
+    if cfg.gitignore.is_empty() {
+        return Ok(vec![detect(base_dir)]);
+    }
+    cfg.gitignore.iter().map(|g| resolve(g, base_dir)).collect()
+}
+
+/// gitignore patterns of `e` go to, its own `gitignore`, or the one of `gitignores` in the
+/// closest directory above its source
+fn of_entry(e: &Entry, gitignores: &[PathBuf], base_dir: &Path) -> Result<PathBuf> {
+    if let Some(g) = e.gitignore.as_ref() {
+        return resolve(g, base_dir);
+    }
+    let (from, _) = e.expand_paths(base_dir)?;
+    gitignores
+        .iter()
+        .filter(|g| {
+            g.parent()
+                .is_some_and(|dir| Path::new(&from).starts_with(dir))
+        })
+        .max_by_key(|g| g.components().count())
+        .or_else(|| gitignores.first())
+        .cloned()
+        .context("`gitignore` is missing")
+}
+
+/// a file lkdots writes its patterns to
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IgnoreFile {
+    pub path: PathBuf,
+    /// directory the patterns are relative to
+    pub dir: PathBuf,
+}
+
+/// where patterns meant for `gitignore` go under `mode`, as absolute paths
+fn ignore_file(gitignore: &Path, mode: GitignoreMode) -> Result<IgnoreFile> {
+    let gitignore = &std::env::current_dir()?.join(gitignore);
+    let dir = gitignore
+        .parent()
+        .context("Fail to get git repository root")?;
+    if mode != GitignoreMode::Exclude {
+        return Ok(IgnoreFile {
+            path: gitignore.to_path_buf(),
+            dir: dir.to_path_buf(),
+        });
+    }
+    // info/exclude patterns are relative to the top level, not to the directory of gitignore
+    let depth = git::in_work_tree(dir)?.components().count();
+    Ok(IgnoreFile {
+        path: git::exclude_file(dir)?,
+        dir: dir
+            .ancestors()
+            .nth(depth)
+            .context("Fail to get git repository root")?
+            .to_path_buf(),
+    })
+}
+
+/// patterns keeping plaintexts of encrypted entries out of git, grouped by the file they are
+/// written to according to `gitignore_mode`
+pub fn patterns(cfg: &Config, base_dir: &Path) -> Result<BTreeMap<IgnoreFile, Vec<String>>> {
+    let gitignores = gitignores(cfg, base_dir)?;
+    let mut files: BTreeMap<PathBuf, IgnoreFile> = BTreeMap::new();
+    let mut file_of = |gitignore: PathBuf| -> Result<IgnoreFile> {
+        if let Some(f) = files.get(&gitignore) {
+            return Ok(f.clone());
+        }
+        let f = ignore_file(&gitignore, cfg.gitignore_mode)?;
+        files.insert(gitignore, f.clone());
+        Ok(f)
+    };
+    // relative `-c` paths give relative sources
+    let cwd = std::env::current_dir()?;
+    let mut result: BTreeMap<IgnoreFile, Vec<String>> = BTreeMap::new();
+    for g in gitignores.iter() {
+        result.insert(file_of(g.clone())?, vec![]);
+    }
+    for e in cfg.entries.iter().filter(|e| e.encrypt) {
+        let file = file_of(of_entry(e, &gitignores, base_dir)?)?;
+        let (from, _) = e.expand_paths(base_dir)?;
+        let p = relative_path(
+            pathbuf_to_str(&cwd.join(&from))?,
+            pathbuf_to_str(&file.dir)?,
+        )?
+        .to_string_lossy()
+        .replace('\\', "/");
+        let lines = result.entry(file).or_default();
+        // directories have to be un-ignored for git to look for ciphertexts in them,
+        // mirrored ciphertexts are outside of sources
+        let suffix = e.naming.suffix_in_sources();
+        let unignored = match suffix {
+            // the archive is next to the source
+            _ if e.encrypt_archive => vec![],
+            "" => vec![],
+            // `.lkdotsignore` files are committed unencrypted
+            suffix => vec![
+                format!("!{}/**/", p),
+                format!("!{}/**/*{}", p, suffix),
+                format!("!{}/**/{}", p, IGNORE_FILE),
+            ],
+        };
+        for line in std::iter::once(format!("{}/**", p)).chain(unignored) {
+            if !lines.contains(&line) {
+                lines.push(line);
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// `dir/*` and `!dir/*.enc` pairs appended by versions before sections existed
+fn is_legacy(line: &str, content: &[&str]) -> bool {
+    match (line.strip_suffix("/*"), line.strip_prefix('!')) {
+        (Some(dir), _) if !dir.starts_with('!') => {
+            content.contains(&format!("!{}/*.enc", dir).as_str())
+        }
+        (_, Some(enc)) => enc
+            .strip_suffix("/*.enc")
+            .is_some_and(|dir| content.contains(&format!("{}/*", dir).as_str())),
+        _ => false,
+    }
+}
+
+/// the lkdots section begins at `lines[i]`, a begin marker without an end marker after it
+/// is a section of its own line only, so the lines after it are kept
+fn begins_section(lines: &[&str], i: usize) -> bool {
+    lines[i] == GITIGNORE_BEGIN && lines[i + 1..].contains(&GITIGNORE_END)
+}
+
+/// lines of the lkdots section of `content`, or the patterns appended by versions before
+/// sections existed if it has none
+pub fn section_lines(content: &str) -> Vec<String> {
+    let lines = content.lines().collect::<Vec<_>>();
+    match (0..lines.len()).find(|&i| begins_section(&lines, i)) {
+        Some(i) => lines[i + 1..]
+            .iter()
+            .take_while(|&&l| l != GITIGNORE_END)
+            .map(|l| l.to_string())
+            .collect(),
+        None => lines
+            .iter()
+            .filter(|l| is_legacy(l, &lines))
+            .map(|l| l.to_string())
+            .collect(),
+    }
+}
+
+/// `content` with its lkdots section replaced by `lines`, appended if there is none.
+/// Patterns appended by older versions are moved into a new section, other lines already
+/// outside the section are left out of it
+pub fn update_section(content: &str, lines: &[String]) -> String {
+    let (mut before, mut after) = (vec![], vec![]);
+    let (mut in_section, mut found) = (false, false);
+    let content = content.lines().collect::<Vec<_>>();
+    for (i, &line) in content.iter().enumerate() {
+        if line == GITIGNORE_BEGIN {
+            in_section = begins_section(&content, i);
+            found = true;
+        } else if line == GITIGNORE_END {
+            in_section = false;
+        } else if !in_section {
+            if found {
+                after.push(line);
+            } else {
+                before.push(line);
+            }
+        }
+    }
+    if !found {
+        let legacy = before.clone();
+        before.retain(|l| !is_legacy(l, &legacy));
+    }
+    let outside = |l: &&String| !before.contains(&l.as_str()) && !after.contains(&l.as_str());
+    let section = lines.iter().filter(outside).map(String::as_str);
+    let mut result = before.clone();
+    result.push(GITIGNORE_BEGIN);
+    result.extend(section);
+    result.push(GITIGNORE_END);
+    result.extend(after.iter().copied());
+    result.join("\n") + "\n"
+}
+
+/// `content` without its lkdots section
+pub fn remove_section(content: &str) -> String {
+    if !content.lines().any(|l| l == GITIGNORE_BEGIN) {
+        return content.to_string();
+    }
+    let mut in_section = false;
+    let mut result = vec![];
+    let content = content.lines().collect::<Vec<_>>();
+    for (i, &line) in content.iter().enumerate() {
+        if line == GITIGNORE_BEGIN {
+            in_section = begins_section(&content, i);
+        } else if line == GITIGNORE_END {
+            in_section = false;
+        } else if !in_section {
+            result.push(line);
+        }
+    }
+    if result.is_empty() {
+        return String::new();
+    }
+    result.join("\n") + "\n"
+}
+
+/// print changes `simulate` would make to `path`, as a unified diff with `diff`
+fn print_changes(path: &Path, content: &str, updated: &str, diff: bool) {
+    if diff {
+        print!("{}", unified(&path.to_string_lossy(), content, updated));
+        return;
+    }
+    let missing = |a: &str, b: &str| {
+        a.lines()
+            .filter(|l| !b.lines().any(|x| x == *l))
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    println!("{}", output::bold(path.display()));
+    for l in missing(updated, content) {
+        println!("{}", output::green(format!("+{}", l)));
+    }
+    for l in missing(content, updated) {
+        println!("{}", output::red(format!("-{}", l)));
+    }
+}
+
+/// replace the lkdots section of `path` with `lines`, or remove it if `lines` is `None`
+fn update_file(
+    fs: &dyn Fs,
+    path: &Path,
+    lines: Option<&[String]>,
+    simulate: bool,
+    diff: bool,
+) -> Result<()> {
+    let content = match fs.read_to_string(path) {
+        Ok(s) => s,
+        Err(err) if err.kind() == ErrorKind::NotFound && lines.is_none() => return Ok(()),
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let updated = match lines {
+        Some(lines) => update_section(&content, lines),
+        None => remove_section(&content),
+    };
+    if updated == content {
+        return Ok(());
+    }
+    if simulate {
+        print_changes(path, &content, &updated, diff);
+        return Ok(());
+    }
+    // .git/info may be missing, e.g. in repositories created by some tools
+    if let Some(dir) = path.parent() {
+        fs.create_dir_all(dir)?;
+    }
+    fs.write(path, &updated)
+        .context(format!("Fail to write {}", path.display()))
+}
+
+/// rebuild the lkdots section of every ignore file from the config, and remove sections
+/// written before to files no longer used, e.g. the `gitignore` of a removed entry.
+/// `simulate` only prints lines to be added or removed, or a unified diff with `diff`
+pub fn write_all(
+    fs: &dyn Fs,
+    cfg: &Config,
+    base_dir: &Path,
+    state: &mut State,
+    simulate: bool,
+    diff: bool,
+) -> Result<()> {
+    let patterns = match cfg.gitignore_mode {
+        GitignoreMode::None => BTreeMap::new(),
+        _ => patterns(cfg, base_dir)?,
+    };
+    let written = patterns
+        .keys()
+        .map(|f| pathbuf_to_str(&f.path).map(str::to_string))
+        .collect::<Result<Vec<_>>>()?;
+    for stale in state.gitignores.iter().filter(|p| !written.contains(p)) {
+        update_file(fs, Path::new(stale), None, simulate, diff)?;
+    }
+    for (file, lines) in patterns.iter() {
+        update_file(fs, &file.path, Some(lines), simulate, diff)?;
+    }
+    if !simulate {
+        state.gitignores = written;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_update_section() {
+    let lines = vec!["ssh/**".to_string(), "!ssh/**/*.enc".to_string()];
+    assert_eq!(
+        update_section("target\n", &lines),
+        "target\n# BEGIN lkdots\nssh/**\n!ssh/**/*.enc\n# END lkdots\n"
+    );
+    let content = "target\n# BEGIN lkdots\ngpg/*\n# END lkdots\n*.log\n";
+    assert_eq!(
+        update_section(content, &lines),
+        "target\n# BEGIN lkdots\nssh/**\n!ssh/**/*.enc\n# END lkdots\n*.log\n"
+    );
+    // written by the user before sections existed
+    assert_eq!(
+        update_section("ssh/**\n", &lines),
+        "ssh/**\n# BEGIN lkdots\n!ssh/**/*.enc\n# END lkdots\n"
+    );
+    // appended by older versions, including pairs of entries no longer encrypted
+    assert_eq!(
+        update_section("ssh/*\n!ssh/*.enc\n*.log\ngpg/*\n!gpg/*.enc\n", &lines),
+        "*.log\n# BEGIN lkdots\nssh/**\n!ssh/**/*.enc\n# END lkdots\n"
+    );
+    // the end marker was deleted by hand, lines after the begin marker are not lost
+    assert_eq!(
+        update_section("target\n# BEGIN lkdots\n*.log\n", &lines),
+        "target\n# BEGIN lkdots\nssh/**\n!ssh/**/*.enc\n# END lkdots\n*.log\n"
+    );
+}
+
+#[test]
+fn test_section_lines() {
+    let content = "target\n# BEGIN lkdots\nssh/**\n# END lkdots\n*.log\n";
+    assert_eq!(section_lines(content), ["ssh/**"]);
+    assert_eq!(update_section(content, &section_lines(content)), content);
+    let legacy = "ssh/*\n!ssh/*.enc\n*.log\n";
+    assert_eq!(
+        update_section(legacy, &section_lines(legacy)),
+        "*.log\n# BEGIN lkdots\nssh/*\n!ssh/*.enc\n# END lkdots\n"
+    );
+    assert!(section_lines("target\n").is_empty());
+}
+
+#[test]
+fn test_remove_section() {
+    let content = "target\n# BEGIN lkdots\nssh/*\n!ssh/*.enc\n# END lkdots\n*.log\n";
+    assert_eq!(remove_section(content), "target\n*.log\n");
+    assert_eq!(remove_section("# BEGIN lkdots\nssh/*\n# END lkdots\n"), "");
+    assert_eq!(remove_section("target"), "target");
+    assert_eq!(remove_section("# BEGIN lkdots\n*.log\n"), "*.log\n");
+}
+
+#[test]
+fn test_detect() {
+    let root = std::env::temp_dir().join("lkdots-test-detect");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join(".git")).unwrap();
+    std::fs::create_dir_all(root.join("home/dots")).unwrap();
+    assert_eq!(detect(&root.join("home/dots")), root.join(".gitignore"));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_update_file() {
+    use crate::fs_view::{FsView, MemFs};
+
+    let fs = MemFs::new();
+    fs.create_dir_all(Path::new("/dots/.git")).unwrap();
+    fs.write(Path::new("/dots/.gitignore"), "target\n").unwrap();
+    let exclude = Path::new("/dots/.git/info/exclude");
+    let lines = vec!["ssh/**".to_string()];
+    update_file(&fs, exclude, Some(&lines), true, false).unwrap();
+    assert!(!fs.exists(Path::new("/dots/.git/info")));
+    update_file(&fs, exclude, Some(&lines), false, false).unwrap();
+    assert_eq!(
+        fs.read_to_string(exclude).unwrap(),
+        "# BEGIN lkdots\nssh/**\n# END lkdots\n"
+    );
+    update_file(&fs, Path::new("/dots/.gitignore"), None, false, false).unwrap();
+    update_file(&fs, Path::new("/dots/missing"), None, false, false).unwrap();
+    assert_eq!(
+        fs.read_to_string(Path::new("/dots/.gitignore")).unwrap(),
+        "target\n"
+    );
+    assert!(!fs.exists(Path::new("/dots/missing")));
+}
+
+#[test]
+fn test_simulate_writes_nothing() {
+    use crate::fs_view::ReadOnlyFs;
+
+    let dir = std::env::temp_dir().join("lkdots-test-gitignore-simulate");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".gitignore"), "target\n").unwrap();
+    let lines = vec!["ssh/**".to_string()];
+    let exclude = dir.join(".git/info/exclude");
+    update_file(
+        &ReadOnlyFs,
+        &dir.join(".gitignore"),
+        Some(&lines),
+        true,
+        false,
+    )
+    .unwrap();
+    update_file(&ReadOnlyFs, &exclude, Some(&lines), true, false).unwrap();
+    assert!(update_file(&ReadOnlyFs, &exclude, Some(&lines), false, false).is_err());
+    assert_eq!(
+        std::fs::read_to_string(dir.join(".gitignore")).unwrap(),
+        "target\n"
+    );
+    assert!(!dir.join(".git").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,26 @@
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use std::process::Command;
+
+/// `cmd` run through the system shell
+pub(crate) fn shell(cmd: &str) -> Command {
+    let mut command = if cfg!(windows) {
+        Command::new("cmd")
+    } else {
+        Command::new("sh")
+    };
+    command.args([if cfg!(windows) { "/C" } else { "-c" }, cmd]);
+    command
+}
+
+/// run a hook command through the system shell
+pub fn run_hook(cmd: &str) -> Result<()> {
+    debug!("run hook: {}", cmd);
+    let status = shell(cmd)
+        .status()
+        .context(format!("Fail to run hook `{}`", cmd))?;
+    if !status.success() {
+        return Err(anyhow!("Hook `{}` failed with {}", cmd, status));
+    }
+    Ok(())
+}
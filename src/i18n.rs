@@ -0,0 +1,137 @@
+use std::{fmt::Display, sync::OnceLock};
+
+/// language of user-facing messages
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Lang {
+    #[default]
+    En,
+    ZhCn,
+}
+
+impl Lang {
+    /// `en`, `zh-CN` or a locale like `zh_CN.UTF-8`, `None` if unsupported
+    pub fn parse(s: &str) -> Option<Lang> {
+        let s = s.split('.').next().unwrap_or_default().to_lowercase();
+        match s.replace('_', "-").as_str() {
+            "zh" | "zh-cn" | "zh-sg" | "zh-hans" => Some(Lang::ZhCn),
+            "c" | "posix" => Some(Lang::En),
+            s if s == "en" || s.starts_with("en-") => Some(Lang::En),
+            _ => None,
+        }
+    }
+
+    /// language of `LC_ALL`, `LC_MESSAGES` or `LANG`, English if unset or unsupported
+    pub fn from_env() -> Lang {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|k| std::env::var(k).ok())
+            .find(|v| !v.is_empty())
+            .and_then(|v| Lang::parse(&v))
+            .unwrap_or_default()
+    }
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+pub fn set_lang(lang: Lang) {
+    let _ = LANG.set(lang);
+}
+
+/// language set by `set_lang`, English before it is set
+pub fn lang() -> Lang {
+    LANG.get().copied().unwrap_or_default()
+}
+
+/// key, English and Simplified Chinese of every message, `{}` are replaced in order
+const MESSAGES: &[(&str, &str, &str)] = &[
+    ("existed", "{} already exists", "{} 已存在"),
+    (
+        "conflict_with_config",
+        "{} already exists and conflicts with your configuration",
+        "{} 已存在，与配置冲突",
+    ),
+    ("backup_existed", "Backup {} already exists", "备份 {} 已存在"),
+    ("conflict_in_entry", "conflict in entry '{}'", "条目 '{}' 有冲突"),
+    (
+        "conflicts_found",
+        "{} conflicts found, nothing is changed",
+        "发现 {} 处冲突，没有做任何修改",
+    ),
+    (
+        "conflict_hint",
+        "hint: rerun with --backup to move them away, --force to replace symbol links, or run `lkdots diff` to compare them",
+        "提示：加上 --backup 重新运行以移走它们，加上 --force 替换符号链接，或运行 `lkdots diff` 对比差异",
+    ),
+    (
+        "ask_conflict",
+        "{} already exists, [s]kip, [b]ackup, [o]verwrite, [d]iff or [a]bort? ",
+        "{} 已存在，[s]跳过、[b]备份、[o]覆盖、[d]对比还是[a]中止？",
+    ),
+    (
+        "ask_drifted",
+        "{} was changed since it was copied, [s]kip, [b]ackup, [o]verwrite, [p]ull, [d]iff or [a]bort? ",
+        "{} 在复制后被修改过，[s]跳过、[b]备份、[o]覆盖、[p]拉回、[d]对比还是[a]中止？",
+    ),
+    ("aborted", "Aborted", "已中止"),
+    (
+        "summary",
+        "{} symlinks created, {} hard links created, {} files copied, {} already linked, {} dirs created, {} in {}s",
+        "创建了 {} 个符号链接，创建了 {} 个硬链接，复制了 {} 个文件，{} 个已链接，创建了 {} 个目录，{}，用时 {} 秒",
+    ),
+    (
+        "overlapped",
+        "target {} of entry '{}' is inside target {} of entry '{}'",
+        "目标 {}（条目 '{}'）位于目标 {}（条目 '{}'）之内",
+    ),
+    ("conflicts_skipped", "{} conflicts skipped", "跳过了 {} 处冲突"),
+    (
+        "status_summary",
+        "{} linked, {} missing, {} conflict, {} broken, {} drifted",
+        "{} 个已链接，{} 个缺失，{} 个冲突，{} 个失效，{} 个已改动",
+    ),
+    (
+        "requirements_installed",
+        "all required binaries are installed",
+        "所有依赖的程序都已安装",
+    ),
+    ("applied", "applied", "已应用"),
+];
+
+/// message `key` in the current language with `{}` replaced by `args`
+pub fn tr(key: &str, args: &[&dyn Display]) -> String {
+    let Some((_, en, zh_cn)) = MESSAGES.iter().find(|(k, _, _)| *k == key) else {
+        debug_assert!(false, "no message {}", key);
+        return key.to_string();
+    };
+    let template = match lang() {
+        Lang::En => en,
+        Lang::ZhCn => zh_cn,
+    };
+    let mut parts = template.split("{}");
+    let mut result = parts.next().unwrap_or_default().to_string();
+    for (i, part) in parts.enumerate() {
+        if let Some(arg) = args.get(i) {
+            result.push_str(&arg.to_string());
+        }
+        result.push_str(part);
+    }
+    result
+}
+
+#[test]
+fn test_messages() {
+    assert_eq!(Lang::parse("zh_CN.UTF-8"), Some(Lang::ZhCn));
+    assert_eq!(Lang::parse("en_US.UTF-8"), Some(Lang::En));
+    assert_eq!(Lang::parse("C"), Some(Lang::En));
+    assert_eq!(Lang::parse("fr_FR"), None);
+    for (key, en, zh_cn) in MESSAGES {
+        assert_eq!(
+            en.matches("{}").count(),
+            zh_cn.matches("{}").count(),
+            "{}",
+            key
+        );
+        assert_eq!(MESSAGES.iter().filter(|(k, _, _)| k == key).count(), 1);
+    }
+    assert_eq!(tr("existed", &[&"~/.zshrc"]), "~/.zshrc already exists");
+}
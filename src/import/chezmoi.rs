@@ -0,0 +1,180 @@
+use super::{Imported, SHARED};
+use crate::path_util::{pathbuf_to_str, sorted_dir};
+use anyhow::{Context, Result};
+use log::warn;
+use std::{fs::read_to_string, path::Path};
+
+/// prefixes of scripts and special files lkdots has no equivalent for
+const UNSUPPORTED: [&str; 6] = [
+    "run_",
+    "create_",
+    "modify_",
+    "remove_",
+    "symlink_",
+    "external_",
+];
+
+/// target name and permissions of a chezmoi source name
+#[derive(Debug, PartialEq)]
+struct Attrs {
+    name: String,
+    private: bool,
+    executable: bool,
+}
+
+/// `Err` with the reason when the source can't be imported
+fn parse(source: &str) -> std::result::Result<Attrs, String> {
+    if let Some(prefix) = UNSUPPORTED.iter().find(|p| source.starts_with(*p)) {
+        return Err(format!("`{}` is not supported", prefix));
+    }
+    if source.ends_with(".tmpl") {
+        return Err("templates are not supported".to_string());
+    }
+    if source.starts_with("encrypted_") {
+        return Err("decrypt it with `chezmoi decrypt` and encrypt it with lkdots".to_string());
+    }
+    let mut attrs = Attrs {
+        name: source.to_string(),
+        private: false,
+        executable: false,
+    };
+    loop {
+        let name = attrs.name.clone();
+        if let Some(rest) = name.strip_prefix("private_") {
+            attrs.private = true;
+            attrs.name = rest.to_string();
+        } else if let Some(rest) = name.strip_prefix("executable_") {
+            attrs.executable = true;
+            attrs.name = rest.to_string();
+        } else if let Some(rest) = ["readonly_", "empty_", "exact_"]
+            .iter()
+            .find_map(|p| name.strip_prefix(p))
+        {
+            attrs.name = rest.to_string();
+        } else {
+            break;
+        }
+    }
+    if let Some(rest) = attrs.name.strip_prefix("dot_") {
+        attrs.name = format!(".{}", rest);
+    }
+    if let Some(rest) = attrs.name.strip_prefix("literal_") {
+        attrs.name = rest.to_string();
+    }
+    Ok(attrs)
+}
+
+impl Attrs {
+    fn chmod(&self, is_dir: bool) -> Option<String> {
+        match (self.private, self.executable || is_dir) {
+            (true, true) => Some("700".to_string()),
+            (true, false) => Some("600".to_string()),
+            (false, true) if !is_dir => Some("755".to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// names in `dir` are the names of their targets, so it can be linked as a whole
+fn is_plain(dir: &Path) -> Result<bool> {
+    for e in sorted_dir(pathbuf_to_str(dir)?)? {
+        let name = e.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let plain =
+            matches!(parse(&name), Ok(attrs) if attrs.name == name && attrs.chmod(false).is_none());
+        if !plain || (e.path().is_dir() && !is_plain(&e.path())?) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn scan_dir(dir: &Path, from: &str, to: &str, entries: &mut Vec<Imported>) -> Result<()> {
+    for e in sorted_dir(pathbuf_to_str(dir)?)? {
+        let source = e.file_name().to_string_lossy().to_string();
+        // `.chezmoi*` files and `.git` are not managed
+        if source.starts_with('.') {
+            continue;
+        }
+        let from = format!("{}{}", from, source);
+        let attrs = match parse(&source) {
+            Ok(attrs) => attrs,
+            Err(reason) => {
+                warn!("skip {}: {}", from, reason);
+                continue;
+            }
+        };
+        let to = format!("{}{}", to, attrs.name);
+        let is_dir = e.path().is_dir();
+        if is_dir && (SHARED.contains(&to.as_str()) || !is_plain(&e.path())?) {
+            scan_dir(
+                &e.path(),
+                &format!("{}/", from),
+                &format!("{}/", to),
+                entries,
+            )?;
+            continue;
+        }
+        entries.push(Imported {
+            package: None,
+            from,
+            to: format!("~/{}", to),
+            chmod: attrs.chmod(is_dir),
+        });
+    }
+    Ok(())
+}
+
+/// source state of chezmoi in `dir`, `.chezmoiroot` is respected
+pub fn scan(dir: &Path) -> Result<Vec<Imported>> {
+    let root = match read_to_string(dir.join(".chezmoiroot")) {
+        Ok(root) => root.trim().to_string(),
+        Err(_) => String::new(),
+    };
+    let from = if root.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", root.trim_end_matches('/'))
+    };
+    let mut entries = vec![];
+    scan_dir(&dir.join(&root), &from, "", &mut entries)
+        .context(format!("Fail to read chezmoi source {}", dir.display()))?;
+    Ok(entries)
+}
+
+#[test]
+fn test_scan() {
+    let dir = std::env::temp_dir().join("lkdots-test-import-chezmoi");
+    let _ = std::fs::remove_dir_all(&dir);
+    let home = dir.join("home");
+    std::fs::create_dir_all(home.join("dot_config/nvim/lua")).unwrap();
+    std::fs::create_dir_all(home.join("private_dot_ssh")).unwrap();
+    std::fs::write(dir.join(".chezmoiroot"), "home\n").unwrap();
+    std::fs::write(home.join(".chezmoiignore"), "").unwrap();
+    std::fs::write(home.join("dot_config/nvim/init.lua"), "").unwrap();
+    std::fs::write(home.join("dot_config/nvim/lua/plugins.lua"), "").unwrap();
+    std::fs::write(home.join("private_dot_ssh/private_config"), "").unwrap();
+    std::fs::write(home.join("dot_gitconfig.tmpl"), "").unwrap();
+    std::fs::write(home.join("run_once_install.sh"), "").unwrap();
+    std::fs::write(home.join("executable_dot_profile"), "").unwrap();
+    let entries = scan(&dir).unwrap();
+    let found = entries
+        .iter()
+        .map(|e| (e.from.as_str(), e.to.as_str(), e.chmod.as_deref()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            ("home/dot_config/nvim", "~/.config/nvim", None),
+            ("home/executable_dot_profile", "~/.profile", Some("755")),
+            (
+                "home/private_dot_ssh/private_config",
+                "~/.ssh/config",
+                Some("600")
+            ),
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
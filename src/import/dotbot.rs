@@ -0,0 +1,152 @@
+use super::{relative_to, Imported};
+use crate::path_util::pathbuf_to_str;
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use serde_yaml::{Mapping, Value};
+use std::{fs::read_to_string, path::Path};
+
+/// config files dotbot's install script reads by default
+const CONFIGS: [&str; 3] = ["install.conf.yaml", "install.conf.yml", "install.conf.json"];
+
+fn config_path(dir: &Path) -> Result<(std::path::PathBuf, std::path::PathBuf)> {
+    if dir.is_file() {
+        let base = dir.parent().context("Fail to get dotbot directory")?;
+        return Ok((dir.to_path_buf(), base.to_path_buf()));
+    }
+    CONFIGS
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|p| p.is_file())
+        .map(|p| (p, dir.to_path_buf()))
+        .ok_or_else(|| anyhow!("no {} in {}", CONFIGS.join(" or "), dir.display()))
+}
+
+/// source dotbot links to `target` when none is given, `~/.vimrc` is linked from `vimrc`
+fn default_source(target: &str) -> String {
+    let name = target.rsplit('/').next().unwrap_or(target);
+    name.strip_prefix('.').unwrap_or(name).to_string()
+}
+
+fn link(target: &Value, source: &Value, dir: &Path, entries: &mut Vec<Imported>) -> Result<()> {
+    let target = target
+        .as_str()
+        .context("link target should be a string")?
+        .trim_end_matches('/');
+    let options = source.as_mapping().cloned().unwrap_or_else(Mapping::new);
+    let path = match source {
+        Value::Null => default_source(target),
+        Value::String(s) => s.clone(),
+        Value::Mapping(m) => match m.get("path").and_then(Value::as_str) {
+            Some(p) => p.to_string(),
+            None => default_source(target),
+        },
+        _ => return Err(anyhow!("unknown link of {}", target)),
+    };
+    if options.contains_key("if") {
+        warn!(
+            "condition of {} is not imported, it is always linked",
+            target
+        );
+    }
+    if options.get("create") == Some(&Value::Bool(true)) {
+        warn!(
+            "`create` of {} is not needed, lkdots creates parent directories",
+            target
+        );
+    }
+    if options.get("glob") != Some(&Value::Bool(true)) {
+        entries.push(Imported {
+            package: None,
+            from: path.trim_end_matches('/').to_string(),
+            to: target.to_string(),
+            chmod: None,
+        });
+        return Ok(());
+    }
+    let prefix = options.get("prefix").and_then(Value::as_str).unwrap_or("");
+    let pattern = dir.join(&path);
+    for p in glob::glob(pathbuf_to_str(&pattern)?)? {
+        let p = p?;
+        let name = p
+            .file_name()
+            .context("glob matched a path without name")?
+            .to_string_lossy();
+        entries.push(Imported {
+            package: None,
+            from: relative_to(&p, dir)?,
+            to: format!("{}/{}{}", target, prefix, name),
+            chmod: None,
+        });
+    }
+    Ok(())
+}
+
+/// `link` directives of the dotbot config in `dir`, or of `dir` itself when it is a file
+pub fn scan(dir: &Path) -> Result<Vec<Imported>> {
+    let (path, dir) = config_path(dir)?;
+    let src = read_to_string(&path).context(format!("Fail to read {}", path.display()))?;
+    let directives: Vec<Mapping> = serde_yaml::from_str(&src)
+        .context(format!("Fail to parse dotbot config {}", path.display()))?;
+    let mut entries = vec![];
+    for directive in directives {
+        for (name, value) in directive.iter() {
+            match name.as_str() {
+                Some("link") => {
+                    let links = value.as_mapping().context("`link` should be a map")?;
+                    for (target, source) in links.iter() {
+                        link(target, source, &dir, &mut entries)?;
+                    }
+                }
+                Some("defaults") => {}
+                Some(other) => warn!("`{}` directives are not imported", other),
+                None => return Err(anyhow!("unknown directive in {}", path.display())),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[test]
+fn test_scan() {
+    let dir = std::env::temp_dir().join("lkdots-test-import-dotbot");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("bin")).unwrap();
+    std::fs::write(dir.join("bin/a"), "").unwrap();
+    std::fs::write(dir.join("bin/b"), "").unwrap();
+    std::fs::write(
+        dir.join("install.conf.yaml"),
+        r#"- defaults:
+    link:
+      relink: true
+- clean: ['~']
+- link:
+    ~/.vimrc:
+    ~/.zshrc: zsh/zshrc
+    ~/.config/nvim/:
+      path: nvim
+      if: '[ `uname` = Linux ]'
+    ~/.local/bin:
+      glob: true
+      path: bin/*
+- shell:
+  - git submodule update --init
+"#,
+    )
+    .unwrap();
+    let entries = scan(&dir).unwrap();
+    let found = entries
+        .iter()
+        .map(|e| (e.from.as_str(), e.to.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            ("vimrc", "~/.vimrc"),
+            ("zsh/zshrc", "~/.zshrc"),
+            ("nvim", "~/.config/nvim"),
+            ("bin/a", "~/.local/bin/a"),
+            ("bin/b", "~/.local/bin/b"),
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
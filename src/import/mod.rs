@@ -0,0 +1,193 @@
+//! config for dotfiles managed by GNU stow, chezmoi or dotbot
+
+mod chezmoi;
+mod dotbot;
+mod stow;
+
+use crate::path_util::{pathbuf_to_str, relative_path};
+use anyhow::{anyhow, Context, Result};
+use std::{env::current_dir, fs::write, path::Path, str::FromStr};
+use toml_edit::{value, ArrayOfTables, DocumentMut, Item, Table};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    Stow,
+    Chezmoi,
+    Dotbot,
+}
+
+impl FromStr for Tool {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stow" => Ok(Tool::Stow),
+            "chezmoi" => Ok(Tool::Chezmoi),
+            "dotbot" => Ok(Tool::Dotbot),
+            _ => Err(anyhow!(
+                "unknown tool {}, expected stow, chezmoi or dotbot",
+                s
+            )),
+        }
+    }
+}
+
+/// an entry found in the layout of another tool
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Imported {
+    /// stow package the entry belongs to
+    pub package: Option<String>,
+    /// relative to the imported directory, or to the package directory
+    pub from: String,
+    pub to: String,
+    pub chmod: Option<String>,
+}
+
+/// directories shared by many programs in home, linking them as a whole would replace
+/// what is already in them, so entries are made for their children instead
+const SHARED: [&str; 7] = [
+    ".config",
+    ".local",
+    ".local/share",
+    ".local/state",
+    ".cache",
+    "Library",
+    "Library/Application Support",
+];
+
+/// entries of the dotfiles managed by `tool` in `dir`, things lkdots can't express are
+/// skipped with a warning
+pub fn scan(tool: Tool, dir: &Path) -> Result<Vec<Imported>> {
+    match tool {
+        Tool::Stow => stow::scan(dir),
+        Tool::Chezmoi => chezmoi::scan(dir),
+        Tool::Dotbot => dotbot::scan(dir),
+    }
+}
+
+/// `p` relative to `dir` with `/` separators
+fn relative_to(p: &Path, dir: &Path) -> Result<String> {
+    let rel = relative_path(pathbuf_to_str(p)?, pathbuf_to_str(dir)?)?
+        .to_string_lossy()
+        .replace('\\', "/");
+    Ok(if rel.is_empty() { ".".to_string() } else { rel })
+}
+
+fn entry_table(from: &str, e: &Imported) -> Table {
+    let mut t = Table::new();
+    t["from"] = value(from);
+    t["to"] = value(e.to.as_str());
+    if let Some(mode) = e.chmod.as_deref() {
+        t["chmod"] = value(mode);
+    }
+    t
+}
+
+/// lkdots.toml in `config_dir` for `entries` found in `dir`, both absolute
+pub fn to_config(entries: &[Imported], dir: &Path, config_dir: &Path) -> Result<String> {
+    let mut top = ArrayOfTables::new();
+    let mut packages: Vec<(String, ArrayOfTables)> = vec![];
+    for e in entries {
+        match e.package.as_ref() {
+            None => top.push(entry_table(
+                &relative_to(&dir.join(&e.from), config_dir)?,
+                e,
+            )),
+            Some(name) => {
+                let i = match packages.iter().position(|(n, _)| n == name) {
+                    Some(i) => i,
+                    None => {
+                        packages.push((name.clone(), ArrayOfTables::new()));
+                        packages.len() - 1
+                    }
+                };
+                packages[i].1.push(entry_table(&e.from, e));
+            }
+        }
+    }
+
+    let mut doc = DocumentMut::new();
+    if !top.is_empty() {
+        doc["entries"] = Item::ArrayOfTables(top);
+    }
+    if !packages.is_empty() {
+        let mut tables = ArrayOfTables::new();
+        for (name, entries) in packages {
+            let mut p = Table::new();
+            p["name"] = value(name.as_str());
+            let base_dir = relative_to(&dir.join(&name), config_dir)?;
+            if base_dir != name {
+                p["base_dir"] = value(base_dir);
+            }
+            p["entries"] = Item::ArrayOfTables(entries);
+            tables.push(p);
+        }
+        doc["packages"] = Item::ArrayOfTables(tables);
+    }
+    Ok(doc.to_string())
+}
+
+/// write config for the dotfiles managed by `tool` in `dir` to `config_path`,
+/// `simulate` only prints it
+pub fn import(tool: Tool, dir: &str, config_path: &Path, simulate: bool) -> Result<()> {
+    if config_path.exists() && !simulate {
+        return Err(anyhow!("{} already exists", config_path.display()));
+    }
+    let cwd = current_dir()?;
+    let dir = cwd.join(shellexpand::tilde(dir).as_ref());
+    let config_dir = cwd.join(
+        config_path
+            .parent()
+            .context("Fail to get config directory")?,
+    );
+    let entries = scan(tool, &dir)?;
+    if entries.is_empty() {
+        return Err(anyhow!("nothing to import from {}", dir.display()));
+    }
+    // sources of a dotbot config given as a file are relative to its directory
+    let dir = if dir.is_file() {
+        dir.parent().context("Fail to get directory")?.to_path_buf()
+    } else {
+        dir
+    };
+    let config = to_config(&entries, &dir, &config_dir)?;
+    if simulate {
+        print!("{}", config);
+        return Ok(());
+    }
+    write(config_path, config)?;
+    println!(
+        "created {} with {} entries",
+        config_path.display(),
+        entries.len()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_to_config() {
+    let entries = vec![
+        Imported {
+            from: "vimrc".to_string(),
+            to: "~/.vimrc".to_string(),
+            ..Default::default()
+        },
+        Imported {
+            package: Some("zsh".to_string()),
+            from: ".zshrc".to_string(),
+            to: "~/.zshrc".to_string(),
+            chmod: Some("600".to_string()),
+        },
+    ];
+    let config = to_config(
+        &entries,
+        Path::new("/home/a/dotfiles"),
+        Path::new("/home/a/dotfiles"),
+    )
+    .unwrap();
+    assert_eq!(
+        config,
+        "[[entries]]\nfrom = \"vimrc\"\nto = \"~/.vimrc\"\n\n[[packages]]\nname = \"zsh\"\n\n\
+         [[packages.entries]]\nfrom = \".zshrc\"\nto = \"~/.zshrc\"\nchmod = \"600\"\n"
+    );
+}
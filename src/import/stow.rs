@@ -0,0 +1,115 @@
+use super::{Imported, SHARED};
+use crate::path_util::{pathbuf_to_str, sorted_dir};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// files stow ignores by default
+fn is_ignored(name: &str) -> bool {
+    matches!(
+        name,
+        ".git"
+            | ".gitignore"
+            | ".gitmodules"
+            | ".hg"
+            | ".svn"
+            | "CVS"
+            | "RCS"
+            | "_darcs"
+            | ".stow-local-ignore"
+    ) || name.starts_with("README")
+        || name.starts_with("LICENSE")
+        || name.starts_with("COPYING")
+        || name.ends_with('~')
+}
+
+/// `dot-` prefix of `stow --dotfiles`
+fn target_name(name: &str) -> String {
+    match name.strip_prefix("dot-") {
+        Some(rest) => format!(".{}", rest),
+        None => name.to_string(),
+    }
+}
+
+/// entries for the children of `dir` in a package, `from` and `to` relative to the package
+/// and home
+fn scan_dir(
+    package: &str,
+    dir: &Path,
+    from: &str,
+    to: &str,
+    entries: &mut Vec<Imported>,
+) -> Result<()> {
+    for e in sorted_dir(pathbuf_to_str(dir)?)? {
+        let file = e.file_name().to_string_lossy().to_string();
+        if is_ignored(&file) {
+            continue;
+        }
+        let (from, to) = (
+            format!("{}{}", from, file),
+            format!("{}{}", to, target_name(&file)),
+        );
+        if e.path().is_dir() && SHARED.contains(&to.as_str()) {
+            scan_dir(
+                package,
+                &e.path(),
+                &format!("{}/", from),
+                &format!("{}/", to),
+                entries,
+            )?;
+            continue;
+        }
+        entries.push(Imported {
+            package: Some(package.to_string()),
+            from,
+            to: format!("~/{}", to),
+            chmod: None,
+        });
+    }
+    Ok(())
+}
+
+/// every directory in `dir` is a package stowed into home
+pub fn scan(dir: &Path) -> Result<Vec<Imported>> {
+    let mut entries = vec![];
+    let packages = sorted_dir(pathbuf_to_str(dir)?)
+        .context(format!("Fail to read stow directory {}", dir.display()))?;
+    for package in packages {
+        let name = package.file_name().to_string_lossy().to_string();
+        if is_ignored(&name) || !package.path().is_dir() {
+            continue;
+        }
+        scan_dir(&name, &package.path(), "", "", &mut entries)?;
+    }
+    Ok(entries)
+}
+
+#[test]
+fn test_scan() {
+    let dir = std::env::temp_dir().join("lkdots-test-import-stow");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("zsh")).unwrap();
+    std::fs::create_dir_all(dir.join("nvim/.config/nvim")).unwrap();
+    std::fs::create_dir_all(dir.join(".git")).unwrap();
+    std::fs::write(dir.join("zsh/dot-zshrc"), "").unwrap();
+    std::fs::write(dir.join("zsh/README.md"), "").unwrap();
+    std::fs::write(dir.join("README.md"), "").unwrap();
+    let entries = scan(&dir).unwrap();
+    let found = entries
+        .iter()
+        .map(|e| {
+            (
+                e.package.as_deref().unwrap(),
+                e.from.as_str(),
+                e.to.as_str(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            ("nvim", ".config/nvim", "~/.config/nvim"),
+            ("zsh", "dot-zshrc", "~/.zshrc")
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,98 @@
+use crate::{
+    gitignore::{section_lines, update_section},
+    interactive::confirm,
+    output,
+};
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::{
+    fs::{read_to_string, write},
+    io::ErrorKind,
+    path::Path,
+};
+
+pub const GITIGNORE_BEGIN: &str = "# BEGIN lkdots";
+pub const GITIGNORE_END: &str = "# END lkdots";
+
+/// common dotfiles in `$HOME` and where they are kept in the repository
+const CANDIDATES: &[(&str, &str)] = &[
+    (".bashrc", "bash/.bashrc"),
+    (".bash_profile", "bash/.bash_profile"),
+    (".zshrc", "zsh/.zshrc"),
+    (".profile", "sh/.profile"),
+    (".gitconfig", "git/.gitconfig"),
+    (".vimrc", "vim/.vimrc"),
+    (".tmux.conf", "tmux/.tmux.conf"),
+    (".config/nvim", "nvim"),
+];
+
+fn starter_config(dir: &Path, entries: &[(&str, &str)]) -> String {
+    let mut s = format!(
+        "# lkdots config, see https://github.com/fengkx/lkdots\ngitignore = \"{}\"\n",
+        dir.join(".gitignore").to_string_lossy()
+    );
+    for (to, from) in entries {
+        s.push_str(&format!(
+            "\n[[entries]]\nfrom = \"{}\"\nto = \"~/{}\"\n",
+            from, to
+        ));
+    }
+    s
+}
+
+/// scaffold a dotfiles repository in the directory of `config_path`, with `yes` every existing
+/// candidate is managed without asking. `simulate` only prints the files
+pub fn init(config_path: &str, git: bool, yes: bool, simulate: bool) -> Result<()> {
+    let config_path = Path::new(config_path);
+    if config_path.exists() {
+        return Err(anyhow!("{} already exists", config_path.display()));
+    }
+    let dir = config_path
+        .parent()
+        .context("Fail to get config directory")?;
+
+    let home = shellexpand::tilde("~").to_string();
+    let mut entries = vec![];
+    for (to, from) in CANDIDATES {
+        if Path::new(&home).join(to).exists() && (yes || confirm(&format!("Manage ~/{}?", to))?) {
+            entries.push((*to, *from));
+        }
+    }
+
+    // an existing section is kept as it is
+    let gitignore = dir.join(".gitignore");
+    let content = match read_to_string(&gitignore) {
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        content => content?,
+    };
+    let updated = update_section(&content, &section_lines(&content));
+    if simulate {
+        println!("{}", output::bold(config_path.display()));
+        print!("{}", starter_config(dir, &entries));
+        if updated != content {
+            println!("{}", output::bold(gitignore.display()));
+            println!("{}", output::green(format!("+{}", GITIGNORE_BEGIN)));
+            println!("{}", output::green(format!("+{}", GITIGNORE_END)));
+        }
+        if git && !dir.join(".git").exists() {
+            println!("git init {}", dir.display());
+        }
+        return Ok(());
+    }
+    write(config_path, starter_config(dir, &entries))?;
+    info!("created {}", config_path.display());
+
+    if updated != content {
+        write(&gitignore, updated)?;
+    }
+
+    if git && !dir.join(".git").exists() {
+        git2::Repository::init(dir).context(format!("Fail to init git in {}", dir.display()))?;
+    }
+
+    println!("created {}", config_path.display());
+    if !entries.is_empty() {
+        println!("run `lkdots adopt` to move the selected files into the repository");
+    }
+    Ok(())
+}
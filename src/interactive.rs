@@ -0,0 +1,118 @@
+use crate::{
+    config::{Entry, Mode},
+    diff::diff,
+    errors::Error,
+    i18n::tr,
+    operations::{resolve_conflict, LinkOptions, Op, Resolution},
+    output,
+    state::State,
+};
+use anyhow::{anyhow, Result};
+use log::info;
+use std::{
+    io::{stdin, stdout, Write},
+    path::Path,
+};
+
+/// conflicts are only resolved interactively when a user is at the terminal
+pub fn is_interactive() -> bool {
+    atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stdout)
+}
+
+/// `false` without asking when nobody is at the terminal
+pub fn confirm(question: &str) -> Result<bool> {
+    if !is_interactive() {
+        info!("{} no, not a terminal", question);
+        return Ok(false);
+    }
+    print!("{} [y/N] ", question);
+    stdout().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// fail instead of waiting on stdin for `what` in scripts and CI
+pub fn require_input(what: &str) -> Result<()> {
+    if is_interactive() {
+        return Ok(());
+    }
+    Err(Error::InputRequired(format!(
+        "{} is required, but stdin or stdout is not a terminal",
+        what
+    ))
+    .into())
+}
+
+fn prompt(to: &str, pull: bool) -> Result<String> {
+    let key = if pull { "ask_drifted" } else { "ask_conflict" };
+    print!("{}", tr(key, &[&output::bold(to)]));
+    stdout().flush()?;
+    let mut answer = String::new();
+    if stdin().read_line(&mut answer)? == 0 {
+        return Err(anyhow!(tr("aborted", &[])));
+    }
+    Ok(answer.trim().to_lowercase())
+}
+
+/// only drifted copies can be pulled back, see `State::mark_drifted`
+pub fn can_pull(state: &State, opts: &LinkOptions, from: &str, to: &str) -> bool {
+    opts.mode == Mode::Copy && Path::new(from).is_file() && state.is_drifted(to)
+}
+
+/// ops replacing `Op::Conflict(from, to)` of `entry` by `resolution`
+pub fn resolve(
+    entry: &Entry,
+    opts: &LinkOptions,
+    from: &str,
+    to: &str,
+    resolution: Resolution,
+) -> Result<Vec<Op>> {
+    let mut result = vec![];
+    resolve_conflict(from, to, resolution, opts, &mut result)?;
+    let permission_ops = entry.create_permission_ops(&result)?;
+    result.extend(permission_ops);
+    Ok(result)
+}
+
+/// ask how to resolve each conflict of an entry, instead of aborting the run,
+/// returns the resolved ops and the number of skipped conflicts
+pub fn resolve_conflicts(
+    entry: &Entry,
+    base_dir: &Path,
+    state: &State,
+    ops: Vec<Op>,
+) -> Result<(Vec<Op>, usize)> {
+    if !ops.iter().any(|op| matches!(op, Op::Conflict(_, _))) {
+        return Ok((ops, 0));
+    }
+    let opts = entry.link_options(base_dir)?;
+    let mut result = vec![];
+    let mut skipped = 0;
+    for op in ops {
+        let (from, to) = match &op {
+            Op::Conflict(from, to) => (from, to),
+            _ => {
+                result.push(op);
+                continue;
+            }
+        };
+        let pull = can_pull(state, &opts, from, to);
+        let resolution = loop {
+            match prompt(to, pull)?.as_str() {
+                "s" | "skip" => {
+                    skipped += 1;
+                    break Resolution::Skip;
+                }
+                "b" | "backup" => break Resolution::Backup,
+                "o" | "overwrite" => break Resolution::Overwrite,
+                "p" | "pull" if pull => break Resolution::Pull,
+                "d" | "diff" => print!("{}", diff(from, to)),
+                "a" | "abort" => return Err(anyhow!(tr("aborted", &[]))),
+                _ => {}
+            }
+        };
+        result.extend(resolve(entry, &opts, from, to, resolution)?);
+    }
+    Ok((result, skipped))
+}
@@ -0,0 +1,58 @@
+//! lkdots as a library, the `lkdots` binary is a thin CLI over it
+//!
+//! ```no_run
+//! use lkdots::{config::{Config, ConfigFileStruct}, operations};
+//! use std::path::Path;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let path = Path::new("lkdots.toml");
+//! let config: Config = ConfigFileStruct::load(path, &std::fs::read_to_string(path)?)?.into();
+//! for ops in operations::plan(&config.entries, Path::new("."))? {
+//!     operations::execute(&ops)?.commit()?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod add;
+pub mod archive;
+pub mod bootstrap;
+pub mod check;
+pub mod condition;
+pub mod config;
+mod copy_util;
+pub mod crypto;
+pub mod diff;
+pub mod doctor;
+pub mod errors;
+pub mod exclude;
+pub mod export;
+pub mod fs_view;
+pub mod git;
+pub mod gitignore;
+mod hook;
+pub mod i18n;
+pub mod import;
+pub mod init;
+pub mod interactive;
+pub mod list;
+pub mod operations;
+pub mod output;
+pub mod path_util;
+pub mod perm_util;
+pub mod planner;
+pub mod plugin;
+pub mod progress;
+pub mod requires;
+pub mod schedule;
+#[cfg(feature = "self-update")]
+pub mod self_update;
+pub mod state;
+pub mod status;
+pub mod symlink_util;
+pub mod transaction;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod validate;
+pub mod verify;
+pub mod watch;
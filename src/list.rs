@@ -0,0 +1,148 @@
+use crate::{
+    config::Entry,
+    output,
+    status::{status_from_ops, Status},
+};
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize)]
+struct EntryInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    from: String,
+    to: String,
+    platforms: Vec<String>,
+    encrypt: bool,
+    /// worst status of targets, none if the entry doesn't apply to this platform
+    status: Option<Status>,
+    /// why the paths or the status of the entry are not known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// expanded paths and status of an entry
+fn resolve(e: &Entry, base_dir: &Path) -> Result<(String, String, Option<Status>)> {
+    let (from, to) = e.expand_paths(base_dir)?;
+    let status = if e.match_platform() {
+        let mut plain = e.clone();
+        plain.backup = false;
+        plain.force = false;
+        status_from_ops(&plain.create_ops(base_dir)?)
+            .into_iter()
+            .map(|(_, status)| status)
+            .max_by_key(|status| *status as usize)
+    } else {
+        None
+    };
+    Ok((from, to, status))
+}
+
+/// an entry failing to resolve is listed with its paths as written and the error
+fn entry_info(e: &Entry, base_dir: &Path) -> EntryInfo {
+    let (from, to, status, error) = match resolve(e, base_dir) {
+        Ok((from, to, status)) => (from, to, status, None),
+        Err(err) => (
+            e.from.to_string(),
+            e.to.to_string(),
+            None,
+            Some(format!("{:#}", err)),
+        ),
+    };
+    EntryInfo {
+        name: e.name.clone(),
+        description: e.description.clone(),
+        from,
+        to,
+        platforms: e.platforms.iter().map(|p| p.name().to_string()).collect(),
+        encrypt: e.encrypt,
+        status,
+        error,
+    }
+}
+
+/// print every entry, including ones for other platforms
+pub fn print_list(
+    entries: &[Entry],
+    base_dir: &Path,
+    platform: Option<&str>,
+    encrypted_only: bool,
+    json: bool,
+) -> Result<()> {
+    let infos = entries
+        .par_iter()
+        .filter(|e| platform.is_none_or(|p| e.platforms.iter().any(|ep| ep == p)))
+        .filter(|e| !encrypted_only || e.encrypt)
+        .map(|e| entry_info(e, base_dir))
+        .collect::<Vec<_>>();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&infos)?);
+        return Ok(());
+    }
+    for info in infos {
+        let status = match (info.status, info.error.as_ref()) {
+            (_, Some(_)) => output::red("error"),
+            (Some(status), None) => status.to_string(),
+            (None, None) => output::yellow("skipped"),
+        };
+        let to = if info.to.is_empty() { "-" } else { &info.to };
+        match info.name.as_ref() {
+            Some(name) => println!(
+                "[{}] {}: {} -> {}",
+                status,
+                output::bold(name),
+                info.from,
+                to
+            ),
+            None => println!(
+                "[{}] {} -> {}",
+                status,
+                output::bold(&info.from),
+                output::bold(to)
+            ),
+        }
+        if let Some(description) = info.description.as_ref() {
+            println!("  {}", description);
+        }
+        if let Some(error) = info.error.as_ref() {
+            println!("  error: {}", error);
+        }
+        println!(
+            "  platforms: {}, encrypt: {}",
+            info.platforms.join(", "),
+            info.encrypt
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_entry_info_error() {
+    use crate::config::{Config, ConfigFileStruct};
+
+    let s = "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"zsh\"\nto = \"$LKDOTS_TEST_UNSET/.zshrc\"\n\
+        [[entries]]\nfrom = \"git\"\nto = \"/tmp/lkdots-test-list/.gitconfig\"\nplatforms = [\"windows\"]\n";
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    let infos = c
+        .entries
+        .iter()
+        .map(|e| entry_info(e, Path::new("/dots")))
+        .collect::<Vec<_>>();
+    assert_eq!(infos[0].to, "$LKDOTS_TEST_UNSET/.zshrc");
+    assert!(infos[0]
+        .error
+        .as_ref()
+        .unwrap()
+        .contains("LKDOTS_TEST_UNSET"));
+    // the entries after it are still listed
+    assert_eq!(infos[1].from, "/dots/git");
+    assert!(infos[1].error.is_none());
+    assert!(print_list(&c.entries, Path::new("/dots"), None, false, true).is_ok());
+}
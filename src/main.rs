@@ -1,156 +1,1179 @@
 mod cli;
-mod config;
-mod crypto;
-mod operations;
-mod path_util;
-mod symlink_util;
+mod completions;
 
+use age::x25519::Recipient;
 use anyhow::{anyhow, Context, Result};
-use config::ConfigFileStruct;
-use log::{debug, info};
-use operations::Op;
-use path_util::{get_dir, pathbuf_to_str, relative_path};
+use indicatif::ProgressBar;
+use lkdots::{
+    add,
+    archive::{self, archive_dir, archive_name},
+    config::{Config, ConfigFileStruct, Entry, GitignoreMode, Overlap, WindowsFallback},
+    crypto::{
+        decrypt_file_with_progress, encrypt_file_with_progress, hash_file, parse_recipients,
+        rekey_to_temp, remove_plaintext, Key, Naming,
+    },
+    diff, doctor,
+    errors::{self, Error},
+    export,
+    fs_view::{CachedFs, Fs, FsView, ReadOnlyFs, RealFs},
+    git, gitignore,
+    i18n::{self, tr, Lang},
+    import, init, interactive, list,
+    operations::{excute_in, Op, OpRecord, Plan},
+    output::{self, Summary},
+    path_util::{get_dir, glob_base, is_glob, pathbuf_to_str},
+    perm_util::parse_mode,
+    planner::{self, Graph},
+    plugin::{self, HookEntry, Stage},
+    progress, requires, schedule,
+    state::{self, State},
+    status, verify,
+};
+use log::{debug, info, warn};
 use rayon::prelude::*;
 use rpassword::prompt_password_stdout;
 use std::{
-    collections::HashMap,
-    fs::{read_to_string, OpenOptions},
-    io::{BufRead, ErrorKind, Write},
-    path::Path,
-};
-use walkdir::WalkDir;
-
-use crate::{
-    config::Config,
-    crypto::{decrypt_file, encrypt_file},
-    operations::excute,
+    collections::BTreeMap,
+    env::current_dir,
+    fs::{canonicalize, read_to_string, remove_file, rename},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-#[macro_use]
-extern crate lazy_static;
+use crate::cli::{Cli, GithookCmd, ScheduleCmd, SubCommand, CONFIG_FILE};
 
-fn main() -> Result<()> {
-    env_logger::init();
+fn main() {
+    if let Err(err) = try_main() {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(errors::exit_code(&err));
+    }
+}
 
+fn try_main() -> Result<()> {
     let cfg = cli::config()?;
-    let cfg_str = read_to_string(&cfg.config);
+    env_logger::Builder::new()
+        .filter_level(cfg.log_level())
+        .parse_default_env()
+        .init();
+    output::init(cfg.no_color, cfg.quiet);
+    i18n::set_lang(match cfg.lang.as_deref() {
+        Some(lang) => Lang::parse(lang)
+            .with_context(|| format!("Unsupported language {}, use en or zh-CN", lang))?,
+        None => Lang::from_env(),
+    });
+    if let Some(SubCommand::Init { git }) = cfg.cmd {
+        let config_path = match cfg.config.as_ref() {
+            Some(p) => PathBuf::from(shellexpand::tilde(p).as_ref()),
+            None => current_dir()?.join(CONFIG_FILE),
+        };
+        return init::init(pathbuf_to_str(&config_path)?, git, cfg.yes, cfg.simulate);
+    }
+    if let Some(SubCommand::Import { from, dir }) = &cfg.cmd {
+        let config_path = match cfg.config.as_ref() {
+            Some(p) => PathBuf::from(shellexpand::tilde(p).as_ref()),
+            None => current_dir()?.join(CONFIG_FILE),
+        };
+        return import::import(from.parse()?, dir, &config_path, cfg.simulate);
+    }
+    // an invalid config is reported as a finding
+    if let Some(SubCommand::Check) = cfg.cmd {
+        return lkdots::check::print_check(&cfg.config_path()?, cfg.is_json());
+    }
+    if let Some(SubCommand::External(args)) = &cfg.cmd {
+        let config_path = cfg.config_path()?;
+        let config_path = Some(config_path.as_path()).filter(|p| p.is_file());
+        std::process::exit(plugin::run_external(args, config_path)?);
+    }
+    if let Some(SubCommand::Schedule { cmd }) = &cfg.cmd {
+        return match cmd {
+            ScheduleCmd::Install { interval } => {
+                schedule::install(&cfg.config_path()?, interval, cfg.simulate)
+            }
+            ScheduleCmd::Uninstall => schedule::uninstall(cfg.simulate),
+        };
+    }
+    if let Some(SubCommand::Bootstrap { url, dest }) = &cfg.cmd {
+        return bootstrap(&cfg, url, dest);
+    }
+    #[cfg(feature = "self-update")]
+    if let Some(SubCommand::SelfUpdate { check }) = cfg.cmd {
+        // simulate only checks for a new version
+        return lkdots::self_update::self_update(check || cfg.simulate);
+    }
+    if let Some(SubCommand::Completions { shell }) = cfg.cmd {
+        return completions::write_completions(shell, &mut std::io::stdout());
+    }
+    if let Some(SubCommand::Watch { encrypt, debounce }) = cfg.cmd {
+        return watch(&cfg, encrypt, debounce);
+    }
+    run(&cfg, None)
+}
+
+fn load_config(config_path: &Path) -> Result<Config<'static>> {
+    let cfg_str = read_to_string(config_path);
     if let Err(err) = cfg_str {
         debug!("{}", err);
         if err.kind() == ErrorKind::NotFound {
-            return Err(anyhow!("Cannot found config toml (default: lkdots.toml)"));
+            return Err(Error::ConfigNotFound.into());
         }
         return Err(anyhow!(err));
     }
-    let config: Config = toml::from_str::<ConfigFileStruct>(&cfg_str?)?.into();
-    let base_dir = get_dir(Path::new(&cfg.config))?;
-    let entries = &config.entries;
+    Ok(ConfigFileStruct::load(config_path, &cfg_str?)?.into())
+}
+
+/// clone dotfiles from `url` into `dest` and link them, encrypted entries are decrypted first
+fn bootstrap(cfg: &Cli, url: &str, dest: &str) -> Result<()> {
+    let dest = current_dir()?.join(shellexpand::tilde(dest).as_ref());
+    let config_path = match lkdots::bootstrap::clone(url, &dest, cfg.simulate)? {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    println!("using config {}", config_path.display());
+    let link = Cli {
+        cmd: None,
+        config: Some(pathbuf_to_str(&config_path)?.to_owned()),
+        ..cfg.clone()
+    };
+    let config = load_config(&config_path)?;
+    if config
+        .entries
+        .iter()
+        .any(|e| e.encrypt && e.match_platform())
+    {
+        run(
+            &Cli {
+                cmd: Some(SubCommand::Decrypt { paths: vec![] }),
+                ..link.clone()
+            },
+            None,
+        )?;
+    }
+    run(&link, None)
+}
+
+/// link entries, and encrypt first with `encrypt`, whenever sources or config change
+fn watch(cfg: &Cli, encrypt: bool, debounce: u64) -> Result<()> {
+    let config_path = cfg.config_path()?;
+    let link = Cli {
+        cmd: None,
+        ..cfg.clone()
+    };
+    let encrypt_cfg = Cli {
+        cmd: Some(SubCommand::Encrypt {
+            remove_plaintext: false,
+            shred: false,
+            commit: false,
+            mode: None,
+            armor: false,
+            no_armor: false,
+            paths: vec![],
+        }),
+        ..cfg.clone()
+    };
+    let sources = || -> Result<(Vec<PathBuf>, Naming)> {
+        let config = load_config(&config_path)?;
+        let base_dir = get_dir(&config_path)?;
+        let paths = config
+            .entries
+            .iter()
+            .filter(|e| e.match_platform())
+            .map(|e| {
+                let (from, _) = e.expand_paths(base_dir)?;
+                Ok(if is_glob(&from) {
+                    glob_base(&from)
+                } else {
+                    PathBuf::from(from)
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok((paths, config.naming(base_dir)?))
+    };
+    // prompt once instead of on every change
+    let key = if encrypt {
+        Some(read_key(&encrypt_cfg, &load_config(&config_path)?)?)
+            .filter(|k| matches!(k, Key::Passphrase(_)))
+    } else {
+        None
+    };
+    let apply = || -> Result<()> {
+        if encrypt {
+            run(&encrypt_cfg, key.as_ref())?;
+        }
+        run(&link, key.as_ref())?;
+        println!("{}", output::green(tr("applied", &[])));
+        Ok(())
+    };
+    if let Err(err) = apply() {
+        warn!("{:#}", err);
+    }
+    lkdots::watch::watch(
+        &config_path,
+        sources,
+        Duration::from_millis(debounce),
+        apply,
+    )
+}
 
-    if cfg.is_encrypt_cmd() || cfg.is_decrypt_cmd() {
-        let phrase = prompt_password_stdout("Passphrase: ")?;
-        if cfg.is_encrypt_cmd() {
-            let again_phrase = prompt_password_stdout("Input passphrase again: ")?;
-            if again_phrase != phrase {
-                return Err(anyhow!("Two passphrase is different"));
+/// link entries, or run the subcommand of `cfg`, with `key` instead of reading it if given
+fn run(cfg: &Cli, key: Option<&Key>) -> Result<()> {
+    let started = Instant::now();
+    let config_path = cfg.config_path()?;
+    let mut config = load_config(&config_path)?;
+    for e in config.entries.iter_mut() {
+        e.backup |= cfg.backup;
+        e.force |= cfg.force;
+    }
+    let base_dir = get_dir(&config_path)?;
+    let naming = config.resolve_naming(base_dir)?;
+    if let Some(SubCommand::CompleteNames { kind }) = cfg.cmd.as_ref() {
+        let mut names = config
+            .entries
+            .iter()
+            .flat_map(|e| match kind.as_str() {
+                "packages" => e.package.iter().cloned().collect(),
+                _ => e.tags.to_vec(),
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        println!("{}", names.join("\n"));
+        return Ok(());
+    }
+    for p in cfg.packages() {
+        if !config.entries.iter().any(|e| e.package.as_ref() == Some(p)) {
+            return Err(anyhow!("No package named {}", p));
+        }
+    }
+    for e in config.entries.iter() {
+        for name in e.after.iter() {
+            if !config.entries.iter().any(|o| o.name.as_ref() == Some(name)) {
+                warn!("No entry named {} to link {} after", name, e.label());
             }
         }
-        return entries
-            .par_iter()
-            .filter(|e| e.encrypt)
-            .map(|e| {
-                let expanded_from = shellexpand::tilde(e.from.as_ref());
-                let walker = WalkDir::new(expanded_from.as_ref())
-                    .follow_links(false)
-                    .into_iter();
-                for entry in walker.filter_entry(|e| !e.path_is_symlink()) {
-                    let entry = entry?;
-                    if entry.metadata()?.is_file() {
-                        let path = entry.path().to_string_lossy();
-                        if cfg.is_encrypt_cmd() {
-                            if !path.as_ref().ends_with(".enc") {
-                                info!("encrypt: {}", path.as_ref());
-                                encrypt_file(path.as_ref(), &phrase)?;
-                            }
-                        } else if cfg.is_decrypt_cmd() && path.as_ref().ends_with(".enc") {
-                            info!("decrypt: {}", path.as_ref());
-                            decrypt_file(path.as_ref(), &phrase)?;
+    }
+    let mut state = State::load()?;
+    let disk = disk(cfg, config.windows_fallback);
+    // --force and unlink only touch links lkdots created
+    let owned_links = Arc::new(state.owned_links());
+    // paths shared by entries are looked up once
+    let fs: Arc<dyn FsView> = Arc::new(CachedFs::default());
+    let mut matched = vec![];
+    for e in config
+        .entries
+        .iter()
+        .filter(|e| e.match_tags(&cfg.tags, &cfg.skip_tags) && e.match_packages(cfg.packages()))
+    {
+        if e.match_condition()? {
+            let mut e = e.clone();
+            e.owned_links = Some(owned_links.clone());
+            e.fs = Some(fs.clone());
+            matched.push(e);
+        }
+    }
+    let matched = planner::order_entries(matched)?;
+    let entries = &matched;
+
+    if cfg.is_encrypt_cmd() || cfg.is_decrypt_cmd() || cfg.is_rekey_cmd() {
+        let (remove, shred, commit, armor) = match cfg.cmd {
+            Some(SubCommand::Encrypt {
+                remove_plaintext,
+                shred,
+                commit,
+                armor,
+                no_armor,
+                ..
+            }) => (
+                remove_plaintext || shred || config.remove_plaintext,
+                shred,
+                commit || config.git.auto_commit,
+                (armor || config.armor) && !no_armor,
+            ),
+            _ => (false, false, false, false),
+        };
+        let mode = match cfg.cmd.as_ref() {
+            Some(SubCommand::Encrypt {
+                mode: Some(mode), ..
+            }) => Some(parse_mode(mode)?),
+            _ => None,
+        };
+        let paths = cfg
+            .crypt_paths()
+            .iter()
+            .map(|p| {
+                let p = shellexpand::tilde(p);
+                canonicalize(p.as_ref()).context(format!("Fail to find {}", p))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let encrypted = entries.iter().filter(|e| e.encrypt).collect::<Vec<_>>();
+        for p in paths.iter() {
+            if !encrypted.iter().any(|e| is_in_entry(e, base_dir, p)) {
+                return Err(anyhow!("{} is not in any encrypted entry", p.display()));
+            }
+        }
+        let recipients = encrypted
+            .iter()
+            .map(|e| parse_recipients(&e.recipients))
+            .collect::<Result<Vec<_>>>()?;
+        // 0 is the number of CPUs
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(cfg.jobs.unwrap_or(0))
+            .build()?;
+        let mut files = vec![];
+        for (e, recipients) in encrypted.iter().zip(recipients.iter()) {
+            // walked with the same number of threads
+            for file in pool.install(|| e.crypt_files(base_dir, &paths))? {
+                files.push((*e, recipients, file));
+            }
+        }
+        if cfg.simulate {
+            let files = files.iter().map(|(e, _, f)| (*e, f));
+            return simulate_crypt(cfg, base_dir, &state, files, remove);
+        }
+        let key = key_or_read(key, cfg, &config)?;
+        let new_key = read_new_key(cfg, &config, &key)?;
+        let new_key = new_key.as_ref().unwrap_or(&key);
+        let multi = progress::multi();
+        let bar = progress::bar(&multi, "files", files.len());
+        // temporary files of rekeyed ciphertexts
+        let rekeyed = Mutex::new(vec![]);
+        // plaintext hash of an encrypted or decrypted file
+        let crypt = |e: &Entry,
+                     recipients: &[Recipient],
+                     file: &Path|
+         -> Result<Option<(String, String)>> {
+            let path = file.to_string_lossy();
+            let mut hash = None;
+            if e.encrypt_archive && cfg.is_encrypt_cmd() && file.is_dir() {
+                let files = e.archive_files(base_dir)?;
+                let archive = archive_name(&path);
+                let plain_hash = archive::hash_dir(file, &files)?;
+                if is_unchanged(&e.naming, &state, &archive, &plain_hash) && !cfg.force {
+                    debug!("unchanged: {}", path);
+                } else {
+                    info!("encrypt: {}", path);
+                    bar.set_message(format!("encrypt {}", path));
+                    let mode = match mode {
+                        Some(mode) => Some(mode),
+                        None => e.ciphertext_mode()?,
+                    };
+                    archive::encrypt_dir(&e.naming, file, &files, &key, recipients, mode, armor)?;
+                    output::success(&bar, format!("encrypt {}", path));
+                    hash = Some((archive, plain_hash));
+                }
+                if remove {
+                    info!("remove: {}", path);
+                    archive::remove_files(file, &files, shred)?;
+                }
+                return Ok(hash);
+            } else if e.encrypt_archive && cfg.is_decrypt_cmd() && e.naming.is_ciphertext(&path) {
+                info!("decrypt: {}", path);
+                bar.set_message(format!("decrypt {}", path));
+                let dir = archive::decrypt_dir(&e.naming, &path, &key)?;
+                output::success(&bar, format!("decrypt {}", path));
+                return Ok(Some(archive_plaintext(e, base_dir, &dir)?));
+            }
+            let size = file.metadata()?.len();
+            let file_bar = progress::file(&multi, &path, size);
+            if cfg.is_encrypt_cmd() {
+                if !e.naming.is_ciphertext(&path) {
+                    let plain_hash = hash_file(path.as_ref())?;
+                    if is_unchanged(&e.naming, &state, &path, &plain_hash) && !cfg.force {
+                        debug!("unchanged: {}", path.as_ref());
+                    } else {
+                        if size > config.max_file_size {
+                            warn!(
+                                "{} is {} bytes, larger than max_file_size {}, \
+                                is it meant to be in an encrypted entry?",
+                                path, size, config.max_file_size
+                            );
                         }
+                        info!("encrypt: {}", path.as_ref());
+                        bar.set_message(format!("encrypt {}", path));
+                        let mode = match mode {
+                            Some(mode) => Some(mode),
+                            None => e.ciphertext_mode()?,
+                        };
+                        encrypt_file_with_progress(
+                            &e.naming,
+                            path.as_ref(),
+                            &key,
+                            recipients,
+                            mode,
+                            armor,
+                            config.crypt_buffer_size,
+                            &file_bar,
+                        )?;
+                        output::success(&bar, format!("encrypt {}", path));
+                        hash = Some((path.to_string(), plain_hash));
+                    }
+                    if remove {
+                        info!("remove: {}", path.as_ref());
+                        remove_plaintext(path.as_ref(), shred)?;
                     }
                 }
-                Ok(())
-            })
-            .collect::<Result<()>>();
+            } else if cfg.is_decrypt_cmd() && e.naming.is_ciphertext(&path) {
+                info!("decrypt: {}", path.as_ref());
+                bar.set_message(format!("decrypt {}", path));
+                decrypt_file_with_progress(
+                    &e.naming,
+                    path.as_ref(),
+                    &key,
+                    config.crypt_buffer_size,
+                    &file_bar,
+                )?;
+                output::success(&bar, format!("decrypt {}", path));
+                let plain = e.naming.plaintext(&path).unwrap_or_default();
+                e.chmod_plaintext(&plain)?;
+                hash = Some((plain.clone(), hash_file(&plain)?));
+            } else if cfg.is_rekey_cmd() && e.naming.is_ciphertext(&path) {
+                info!("rekey: {}", path.as_ref());
+                bar.set_message(format!("rekey {}", path));
+                let tmp = rekey_to_temp(path.as_ref(), &key, new_key, recipients)?;
+                rekeyed.lock().unwrap().push((tmp, path.to_string()));
+                output::success(&bar, format!("rekey {}", path));
+            }
+            file_bar.finish_and_clear();
+            Ok(hash)
+        };
+        let results = pool.install(|| {
+            files
+                .par_iter()
+                .map(|(e, recipients, file)| {
+                    let res = crypt(e, recipients, file);
+                    if let Err(err) = res.as_ref() {
+                        output::failure(&bar, format!("{}: {}", file.display(), err));
+                    }
+                    bar.inc(1);
+                    (file, res)
+                })
+                .collect::<Vec<_>>()
+        });
+        bar.finish_with_message("done");
+        let mut failures = vec![];
+        let mut encrypted = vec![];
+        for (file, res) in results {
+            match res {
+                Ok(hash) => {
+                    if let Some((plain, _)) = hash.as_ref() {
+                        encrypted.push(naming.ciphertext(plain));
+                    }
+                    state.hashes.extend(hash)
+                }
+                Err(err) => failures.push(format!(
+                    "  {} {}: {:#}",
+                    output::red("✗"),
+                    file.display(),
+                    err
+                )),
+            }
+        }
+        // ciphertexts are replaced once all of them are rekeyed, so they share one key
+        for (tmp, path) in rekeyed.into_inner().unwrap() {
+            if failures.is_empty() {
+                rename(&tmp, &path).context(format!("Fail to rekey {}", path))?;
+            } else {
+                let _ = remove_file(&tmp);
+            }
+        }
+        // keep hashes of files which succeeded
+        state.save()?;
+        if !failures.is_empty() {
+            return Err(anyhow!(
+                "{} of {} files failed\n{}",
+                failures.len(),
+                files.len(),
+                failures.join("\n")
+            ));
+        }
+        if commit && cfg.is_encrypt_cmd() && !encrypted.is_empty() {
+            commit_encrypted(&config, base_dir, &mut state, &encrypted)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(SubCommand::Githook {
+        cmd: GithookCmd::Install { force },
+    }) = cfg.cmd
+    {
+        for file in gitignore::patterns(&config, base_dir)?.keys() {
+            let suffix = naming.suffix_in_sources();
+            let hook = git::install_pre_commit(&file.path, &file.dir, suffix, force, cfg.simulate)?;
+            match cfg.simulate {
+                true => println!("install {}", hook.display()),
+                false => println!("installed {}", hook.display()),
+            }
+        }
+        return Ok(());
+    }
+    if cfg.is_verify_cmd() {
+        let key = key_or_read(key, cfg, &config)?;
+        return verify::print_verify(entries, base_dir, &key, cfg.is_json());
+    }
+    if cfg.is_status_cmd() {
+        return status::print_status(entries, &state, base_dir, cfg.is_json());
+    }
+    if cfg.is_diff_cmd() {
+        return diff::print_diffs(entries, base_dir);
+    }
+    if let Some(SubCommand::List {
+        platform,
+        encrypted_only,
+        json,
+    }) = cfg.cmd.as_ref()
+    {
+        return list::print_list(
+            entries,
+            base_dir,
+            platform.as_deref(),
+            *encrypted_only,
+            *json || cfg.is_json(),
+        );
     }
 
-    let r = entries
-        .par_iter()
+    if let Some(SubCommand::Export { output, .. }) = cfg.cmd.as_ref() {
+        let out = match output {
+            Some(p) => current_dir()?.join(shellexpand::tilde(p).as_ref()),
+            None => base_dir.join(export::DOTBOT_CONFIG),
+        };
+        return export::export_dotbot(entries, base_dir, &out, cfg.force, cfg.simulate);
+    }
+    if let Some(SubCommand::Add { path, from }) = cfg.cmd.as_ref() {
+        let (from, ops) =
+            add::create_add_ops(path, from.as_deref(), base_dir, config.symlink_type)?;
+        if cfg.simulate {
+            ops.iter().for_each(|op| println!("{}", op));
+            return Ok(());
+        }
+        excute_in(&ops, disk)?.commit()?;
+        add::append_entry(pathbuf_to_str(&config_path)?, &from, path)?;
+        state.record(&ops);
+        return state.save();
+    }
+    if let Some(SubCommand::Apply {
+        plan: Some(plan), ..
+    }) = cfg.cmd.as_ref()
+    {
+        let ops = Plan::load(plan)?.ops;
+        if cfg.simulate {
+            ops.iter().for_each(|op| println!("{}", op));
+            return Ok(());
+        }
+        // a plan file may come from anywhere, its commands are shown before running them
+        let commands = ops
+            .iter()
+            .filter(|op| matches!(op, Op::Run(_)))
+            .collect::<Vec<_>>();
+        if !commands.is_empty() && !cfg.yes {
+            commands.iter().for_each(|op| println!("{}", op));
+            if !interactive::confirm("Run the commands above?")? {
+                return Err(anyhow!("{} runs commands, confirm them or use --yes", plan));
+            }
+        }
+        excute_in(&ops, disk)?.commit()?;
+        state.record(&ops);
+        return state.save();
+    }
+    if let Some(SubCommand::InstallMissing) = cfg.cmd {
+        return requires::install_missing(entries, cfg.yes, cfg.simulate);
+    }
+    if cfg.is_doctor_cmd() {
+        return doctor::print_doctor(entries, &state, base_dir, cfg.is_json());
+    }
+    if cfg.is_prune_cmd() {
+        let ops = state::create_prune_ops(&state, &config.entries, base_dir)?;
+        if cfg.simulate {
+            if cfg.is_json() {
+                println!("{}", serde_json::to_string_pretty(&ops)?);
+            } else {
+                ops.iter().for_each(|op| println!("{}", op));
+            }
+        } else {
+            excute_in(&ops, disk)?.commit()?;
+            state.record(&ops);
+            state.save()?;
+        }
+        return Ok(());
+    }
+
+    if cfg.is_link_cmd() && !cfg.simulate {
+        // fresh clone, decrypt before linking instead of requiring `lkdots decrypt` first
+        let missing = entries
+            .iter()
+            .filter(|e| e.encrypt && e.match_platform())
+            .map(|e| Ok((e, e.missing_plaintexts(base_dir)?)))
+            .collect::<Result<Vec<_>>>()?;
+        if missing.iter().any(|(_, paths)| !paths.is_empty()) {
+            let key = key_or_read(key, cfg, &config)?;
+            for (e, paths) in missing {
+                for path in paths {
+                    info!("decrypt: {}", path);
+                    if e.encrypt_archive {
+                        let dir = archive::decrypt_dir(&e.naming, &path, &key)?;
+                        state.hashes.extend([archive_plaintext(e, base_dir, &dir)?]);
+                        continue;
+                    }
+                    decrypt_file_with_progress(
+                        &e.naming,
+                        &path,
+                        &key,
+                        config.crypt_buffer_size,
+                        &ProgressBar::hidden(),
+                    )?;
+                    let plain = e.naming.plaintext(&path).unwrap_or_default();
+                    e.chmod_plaintext(&plain)?;
+                    state.hashes.insert(plain.clone(), hash_file(&plain)?);
+                }
+            }
+        }
+    }
+
+    if cfg.is_link_cmd() {
+        for (r, froms) in requires::missing(entries) {
+            warn!(
+                "{} required by {} is not installed, see `lkdots install-missing`",
+                r.binary,
+                froms.join(", ")
+            );
+        }
+    }
+    if cfg.is_link_cmd() || cfg.is_plan_cmd() {
+        check_overlaps(entries, config.overlap)?;
+    }
+    let hooks = cfg.is_link_cmd() && !cfg.simulate;
+    if let Some(cmd) = config.hooks.pre_plan.as_ref().filter(|_| hooks) {
+        let input = entries
+            .iter()
+            .filter(|e| e.match_platform())
+            .map(|e| HookEntry::new(e, base_dir))
+            .collect::<Result<Vec<_>>>()?;
+        plugin::run_stage(cmd, Stage::PrePlan, &config_path, &input)?;
+    }
+    let planned = entries
+        .iter()
         .filter(|e| e.match_platform())
-        .map(|cfg| cfg.create_ops(base_dir));
-    let opss = r.collect::<Result<Vec<Vec<Op>>>>().unwrap();
+        .collect::<Vec<_>>();
+    let after = planner::entry_deps(&planned);
+    let opss = if cfg.is_unlink_cmd() || cfg.is_adopt_cmd() || cfg.is_repair_cmd() {
+        let r = planned.par_iter().map(|e| {
+            if cfg.is_unlink_cmd() {
+                e.create_unlink_ops(base_dir)
+            } else if cfg.is_adopt_cmd() {
+                e.create_adopt_ops(base_dir)
+            } else {
+                e.create_repair_ops(base_dir)
+            }
+        });
+        r.collect::<Result<Vec<Vec<Op>>>>()?
+    } else {
+        // entries linked after others see what those link
+        planner::plan_after(&planned, &after, |e| {
+            state.mark_drifted(e, base_dir, e.create_ops(base_dir)?)
+        })?
+    };
+    let (opss, skipped) = if cfg.is_tui_cmd() {
+        review(entries, base_dir, &state, opss)?
+    } else if !cfg.simulate
+        && !cfg.is_plan_cmd()
+        && !cfg.is_json()
+        && !cfg.yes
+        && interactive::is_interactive()
+    {
+        let resolved = entries
+            .iter()
+            .filter(|e| e.match_platform())
+            .zip(opss)
+            .map(|(e, ops)| interactive::resolve_conflicts(e, base_dir, &state, ops))
+            .collect::<Result<Vec<_>>>()?;
+        let skipped = resolved.iter().map(|(_, skipped)| skipped).sum();
+        (resolved.into_iter().map(|(ops, _)| ops).collect(), skipped)
+    } else {
+        (opss, 0)
+    };
 
-    if cfg.simulate {
-        let output = opss
+    // global hooks only run around linking
+    let (pre_ops, post_ops): (Vec<Op>, Vec<Op>) = if cfg.is_link_cmd() || cfg.is_plan_cmd() {
+        (
+            config.pre_link.iter().cloned().map(Op::Run).collect(),
+            config.post_link.iter().cloned().map(Op::Run).collect(),
+        )
+    } else {
+        (vec![], vec![])
+    };
+
+    if let Some(SubCommand::Plan { out }) = cfg.cmd.as_ref() {
+        let ops: Vec<Op> = std::iter::once(&pre_ops)
+            .chain(opss.iter())
+            .chain(std::iter::once(&post_ops))
+            .flatten()
+            .cloned()
+            .collect();
+        if cfg.simulate {
+            println!("{}", output::bold(out));
+            ops.iter().for_each(|op| println!("{}", op));
+            return Ok(());
+        }
+        return Plan { ops }.save(out);
+    }
+    if cfg.simulate && cfg.is_json() {
+        let ops = std::iter::once(&pre_ops)
+            .chain(opss.iter())
+            .chain(std::iter::once(&post_ops))
+            .flatten()
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&ops)?);
+    } else if cfg.simulate {
+        // directories shared by entries are listed once before the entries in config order
+        let dirs = planner::hoist_dirs(&opss)
+            .into_iter()
+            .map(|(_, dir)| Op::Mkdirp(dir))
+            .collect::<Vec<_>>();
+        let entry_ops = opss
             .iter()
             .map(|ops| {
                 ops.iter()
-                    .map(|op| format!("{}", op))
+                    .filter(|op| !matches!(op, Op::Mkdirp(_)))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let headers = entries
+            .iter()
+            .filter(|e| e.match_platform())
+            .map(|e| e.label());
+        let output = std::iter::once(("pre_link".to_string(), &pre_ops))
+            .chain(std::iter::once(("directories".to_string(), &dirs)))
+            .chain(headers.zip(entry_ops.iter()))
+            .chain(std::iter::once(("post_link".to_string(), &post_ops)))
+            .filter(|(_, ops)| !ops.is_empty())
+            .map(|(header, ops)| {
+                let lines = ops
+                    .iter()
+                    .map(|op| match op {
+                        Op::Conflict(from, to) if cfg.diff => {
+                            format!("{}\n{}", output::red(op), diff::diff(from, to).trim_end())
+                        }
+                        Op::Conflict(_, _) => output::red(op),
+                        // copies replacing an existing file
+                        Op::Copy(from, to) if cfg.diff && Path::new(to).is_file() => {
+                            format!("{}\n{}", output::yellow(op), diff::diff(from, to))
+                                .trim_end()
+                                .to_string()
+                        }
+                        Op::Existed(_) => format!("{}", op),
+                        _ => output::yellow(op),
+                    })
                     .collect::<Vec<String>>()
-                    .join("\n")
+                    .join("\n");
+                format!("{}\n{}", output::bold(header), lines)
             })
             .collect::<Vec<String>>()
-            .join("\n");
+            .join("\n\n");
         println!("{}", output);
     } else {
-        opss.par_iter()
-            .map(|ops| -> Result<()> { excute(ops) })
-            .collect::<Result<()>>()?;
+        if cfg.diff {
+            opss.iter().for_each(|ops| diff::print_conflict_diffs(ops));
+        }
+        // report conflicts of all entries before any entry is linked
+        check_conflicts(entries, &opss)?;
+        // hooks see the final plan, after conflicts are resolved
+        let plan = Plan {
+            ops: std::iter::once(&pre_ops)
+                .chain(opss.iter())
+                .chain(std::iter::once(&post_ops))
+                .flatten()
+                .cloned()
+                .collect(),
+        };
+        if let Some(cmd) = config.hooks.pre_apply.as_ref().filter(|_| hooks) {
+            plugin::run_stage(cmd, Stage::PreApply, &config_path, &plan)?;
+        }
+        excute_in(&pre_ops, disk.clone())?.commit()?;
+        // all entries are rolled back if any op fails
+        let graph = Graph::new(&opss, &after);
+        let multi = progress::multi();
+        let bars = entries
+            .iter()
+            .filter(|e| e.match_platform())
+            .enumerate()
+            .map(|(i, e)| progress::bar(&multi, &e.from, graph.count(i)))
+            .collect::<Vec<_>>();
+        planner::excute(&graph, &bars, disk.clone())?.commit()?;
+        state.record(opss.iter().flatten());
+        state.save()?;
+        excute_in(&post_ops, disk.clone())?.commit()?;
+        if let Some(cmd) = config.hooks.post_apply.as_ref().filter(|_| hooks) {
+            plugin::run_stage(cmd, Stage::PostApply, &config_path, &plan)?;
+        }
+        if !output::is_quiet() {
+            let ops = opss.iter().flatten();
+            println!("{}", Summary::from_ops(ops, skipped, started.elapsed()));
+        }
+    }
+    if !cfg.is_unlink_cmd() {
+        gitignore::write_all(
+            disk.as_ref(),
+            &config,
+            base_dir,
+            &mut state,
+            cfg.simulate,
+            cfg.diff,
+        )?;
+        if !cfg.simulate {
+            state.save()?;
+        }
+    }
+    if skipped > 0 {
+        return Err(Error::Conflict(tr("conflicts_skipped", &[&skipped])).into());
     }
-    write_gitignore(&config, cfg.simulate)?;
     Ok(())
 }
 
-fn write_gitignore(cfg: &Config, simulate: bool) -> Result<()> {
-    let gitignore_path = shellexpand::tilde(&cfg.gitignore);
-    let dir = pathbuf_to_str(
-        Path::new(gitignore_path.as_ref())
-            .parent()
-            .context("Fail to get git repository root")?,
-    )?;
+/// the disk, read-only with `--simulate` so a simulated run fails instead of writing
+fn disk(cfg: &Cli, windows_fallback: WindowsFallback) -> Arc<dyn Fs> {
+    if cfg.simulate {
+        Arc::new(ReadOnlyFs)
+    } else {
+        Arc::new(RealFs { windows_fallback })
+    }
+}
+
+/// let the user toggle entries and resolve conflicts of `opss` in a terminal UI
+#[cfg(feature = "tui")]
+fn review(
+    entries: &[Entry],
+    base_dir: &Path,
+    state: &State,
+    opss: Vec<Vec<Op>>,
+) -> Result<(Vec<Vec<Op>>, usize)> {
+    let entries = entries
+        .iter()
+        .filter(|e| e.match_platform())
+        .collect::<Vec<_>>();
+    interactive::require_input("A terminal for the tui")?;
+    lkdots::tui::review(&entries, base_dir, state, opss)?.ok_or_else(|| anyhow!(tr("aborted", &[])))
+}
+
+#[cfg(not(feature = "tui"))]
+fn review(_: &[Entry], _: &Path, _: &State, opss: Vec<Vec<Op>>) -> Result<(Vec<Vec<Op>>, usize)> {
+    Ok((opss, 0))
+}
+
+/// age is nondeterministic, ciphertext of plaintexts unchanged since they were encrypted is kept
+fn is_unchanged(naming: &Naming, state: &State, path: &str, hash: &str) -> bool {
+    state.hashes.get(path).map(String::as_str) == Some(hash)
+        && Path::new(&naming.ciphertext(path)).exists()
+}
+
+/// chmod files of the archive of `e` unpacked to `dir`, returning the hash of the archive
+fn archive_plaintext(e: &Entry, base_dir: &Path, dir: &str) -> Result<(String, String)> {
+    let files = e.archive_files(base_dir)?;
+    for f in files.iter() {
+        e.chmod_plaintext(pathbuf_to_str(f)?)?;
+    }
+    Ok((
+        archive_name(dir),
+        archive::hash_dir(Path::new(dir), &files)?,
+    ))
+}
+
+/// print what encrypt, decrypt or rekey would do, without reading the key
+fn simulate_crypt<'a>(
+    cfg: &Cli,
+    base_dir: &Path,
+    state: &State,
+    files: impl Iterator<Item = (&'a Entry<'a>, &'a PathBuf)>,
+    remove: bool,
+) -> Result<()> {
+    let mut records = vec![];
+    for (e, file) in files {
+        let path = pathbuf_to_str(file)?.to_owned();
+        let record = |op: &str, from: &str, to: &str| OpRecord {
+            op: op.to_string(),
+            from: Some(from.to_string()),
+            to: Some(to.to_string()),
+            ..Default::default()
+        };
+        if cfg.is_encrypt_cmd() && !e.naming.is_ciphertext(&path) {
+            // the source directory of an archive entry
+            let (plain, hash) = if e.encrypt_archive {
+                let files = e.archive_files(base_dir)?;
+                (archive_name(&path), archive::hash_dir(file, &files)?)
+            } else {
+                (path.clone(), hash_file(&path)?)
+            };
+            if cfg.force || !is_unchanged(&e.naming, state, &plain, &hash) {
+                records.push(record("encrypt", &path, &e.naming.ciphertext(&plain)));
+            } else {
+                debug!("unchanged: {}", path);
+            }
+            if remove {
+                records.push(OpRecord {
+                    op: "remove".to_string(),
+                    path: Some(path),
+                    ..Default::default()
+                });
+            }
+        } else if cfg.is_decrypt_cmd() && e.naming.is_ciphertext(&path) {
+            let plain = if e.encrypt_archive {
+                archive_dir(&e.naming, &path)
+            } else {
+                e.naming.plaintext(&path)
+            };
+            let plain = plain.unwrap_or_default();
+            records.push(record("decrypt", &path, &plain));
+        } else if cfg.is_rekey_cmd() && e.naming.is_ciphertext(&path) {
+            records.push(record("rekey", &path, &path));
+        }
+    }
+    if cfg.is_json() {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+    for r in records {
+        match (r.from, r.to, r.path) {
+            (Some(from), Some(to), _) if from == to => println!("{} {}", r.op, from),
+            (Some(from), Some(to), _) => println!("{} {} to {}", r.op, from, to),
+            (_, _, Some(path)) => println!("{} {}", r.op, path),
+            _ => {}
+        }
+    }
+    Ok(())
+}
 
-    let mut has_written = HashMap::new();
-    let mut f = OpenOptions::new()
-        .create(true)
-        .read(true)
-        .write(true)
-        .open(gitignore_path.as_ref())?;
-    let reader = std::io::BufReader::new(&f);
-    let lines = reader.lines();
-    for line in lines.flatten() {
-        has_written.insert(line, true);
+/// fail with conflicts of all entries grouped by entry
+fn check_conflicts(entries: &[Entry], opss: &[Vec<Op>]) -> Result<()> {
+    let mut report = vec![];
+    let mut count = 0;
+    for (e, ops) in entries.iter().filter(|e| e.match_platform()).zip(opss) {
+        let conflicts = ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Conflict(_, to) => {
+                    Some(format!("  {} {}", output::red("✗"), tr("existed", &[to])))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !conflicts.is_empty() {
+            count += conflicts.len();
+            report.push(output::bold(tr("conflict_in_entry", &[&e.label()])));
+            report.extend(conflicts);
+        }
     }
+    if count == 0 {
+        return Ok(());
+    }
+    Err(Error::Conflict(format!(
+        "{}\n{}\n{}",
+        tr("conflicts_found", &[&count]),
+        report.join("\n"),
+        tr("conflict_hint", &[])
+    ))
+    .into())
+}
 
-    cfg.entries
+/// warn about or fail on entries whose target is inside the target of another one
+fn check_overlaps(entries: &[Entry], overlap: Overlap) -> Result<()> {
+    if overlap == Overlap::Allow {
+        return Ok(());
+    }
+    let entries = entries
         .iter()
-        .filter(|&e| e.encrypt)
-        .map(|e| {
-            format!(
-                "{}",
-                relative_path(shellexpand::tilde(e.from.as_ref()).as_ref(), dir)
-                    .unwrap()
-                    .to_string_lossy()
+        .filter(|e| e.match_platform())
+        .collect::<Vec<_>>();
+    let messages = planner::overlaps(&entries)?
+        .into_iter()
+        .map(|(outer, inner)| {
+            let (outer, inner) = (entries[outer], entries[inner]);
+            tr(
+                "overlapped",
+                &[&inner.to, &inner.label(), &outer.to, &outer.label()],
             )
         })
-        .flat_map(|p| vec![format!("{}/*", p), format!("!{}/*.enc", p)])
-        .for_each(|s| {
-            if has_written.get(&s).is_none() {
-                if simulate {
-                    println!("{}", s);
-                } else {
-                    writeln!(f, "{}", s)
-                        .context("Fail to write gitignore")
-                        .unwrap();
-                }
-            }
-        });
+        .collect::<Vec<_>>();
+    match overlap {
+        Overlap::Error if !messages.is_empty() => Err(Error::Conflict(messages.join("\n")).into()),
+        _ => {
+            messages.iter().for_each(|m| warn!("{}", m));
+            Ok(())
+        }
+    }
+}
+
+/// `p` is the source of entry or inside it
+fn is_in_entry(e: &Entry, base_dir: &Path, p: &Path) -> bool {
+    e.expand_paths(base_dir)
+        .ok()
+        .and_then(|(from, _)| canonicalize(from).ok())
+        .map(|from| p.starts_with(from))
+        .unwrap_or(false)
+}
+
+fn read_passphrase_file(path: &str) -> Result<String> {
+    let path = shellexpand::tilde(path);
+    let phrase =
+        read_to_string(path.as_ref()).context(format!("Fail to read passphrase file {}", path))?;
+    Ok(phrase.trim_end_matches(&['\r', '\n'][..]).to_owned())
+}
+
+const PASSPHRASE_ENV: &str = "LKDOTS_PASSPHRASE";
+
+/// key of identities, passphrase file or `env` in this order, `None` if it has to be prompted
+fn key_without_prompt(cfg: &Cli, config: &Config, env: Option<String>) -> Result<Option<Key>> {
+    let identities = if cfg.identities.is_empty() {
+        &config.identities
+    } else {
+        &cfg.identities
+    };
+    let recipients = parse_recipients(&config.recipients)?;
+    if !identities.is_empty() || (cfg.is_encrypt_cmd() && !recipients.is_empty()) {
+        return Key::from_identity_files(identities, recipients).map(Some);
+    }
+
+    if let Some(path) = cfg.passphrase_file.as_ref() {
+        return Ok(Some(Key::Passphrase(read_passphrase_file(path)?)));
+    }
+    Ok(env.map(Key::Passphrase))
+}
+
+fn read_key(cfg: &Cli, config: &Config) -> Result<Key> {
+    if let Some(key) = key_without_prompt(cfg, config, std::env::var(PASSPHRASE_ENV).ok())? {
+        return Ok(key);
+    }
+
+    interactive::require_input(&format!(
+        "Passphrase ({}, --passphrase-file or identities are used without a terminal)",
+        PASSPHRASE_ENV
+    ))?;
+    let phrase = prompt_password_stdout("Passphrase: ")?;
+    if cfg.is_encrypt_cmd() {
+        let again_phrase = prompt_password_stdout("Input passphrase again: ")?;
+        if again_phrase != phrase {
+            return Err(anyhow!("Two passphrase is different"));
+        }
+    }
+    Ok(Key::Passphrase(phrase))
+}
 
+/// `key` read once by the caller, e.g. by `watch`, or the one `read_key` reads
+fn key_or_read(key: Option<&Key>, cfg: &Cli, config: &Config) -> Result<Key> {
+    match key {
+        Some(key) => Ok(key.clone()),
+        None => read_key(cfg, config),
+    }
+}
+
+/// key `rekey` re-encrypts with, `None` to keep the current identities
+fn read_new_key(cfg: &Cli, config: &Config, key: &Key) -> Result<Option<Key>> {
+    let (new_identities, new_passphrase_file) = match cfg.cmd.as_ref() {
+        Some(SubCommand::Rekey {
+            new_identities,
+            new_passphrase_file,
+        }) => (new_identities, new_passphrase_file),
+        _ => return Ok(None),
+    };
+    if !new_identities.is_empty() {
+        let recipients = parse_recipients(&config.recipients)?;
+        return Ok(Some(Key::from_identity_files(new_identities, recipients)?));
+    }
+    if let Some(path) = new_passphrase_file.as_ref() {
+        return Ok(Some(Key::Passphrase(read_passphrase_file(path)?)));
+    }
+    if let Key::Identity { .. } = key {
+        return Ok(None);
+    }
+    interactive::require_input("New passphrase (use --new-passphrase-file without a terminal)")?;
+    let phrase = prompt_password_stdout("New passphrase: ")?;
+    let again_phrase = prompt_password_stdout("Input new passphrase again: ")?;
+    if again_phrase != phrase {
+        return Err(anyhow!("Two passphrase is different"));
+    }
+    Ok(Some(Key::Passphrase(phrase)))
+}
+
+/// commit newly encrypted files with the up-to-date .gitignore, once in each repository
+fn commit_encrypted(
+    cfg: &Config,
+    base_dir: &Path,
+    state: &mut State,
+    encrypted: &[String],
+) -> Result<()> {
+    gitignore::write_all(&RealFs::default(), cfg, base_dir, state, false, false)?;
+    state.save()?;
+    let mut ignore_files = gitignore::patterns(cfg, base_dir)?
+        .into_keys()
+        .map(|f| -> Result<_> { Ok((canonicalize(&f.dir)?, f.path)) })
+        .collect::<Result<Vec<_>>>()?;
+    // a file goes to the closest repository above it
+    ignore_files.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.components().count()));
+    let mut commits: BTreeMap<&Path, Vec<String>> = BTreeMap::new();
+    for f in encrypted {
+        let f = canonicalize(f)?;
+        let (dir, file) = ignore_files
+            .iter()
+            .find_map(|(dir, _)| Some((dir.as_path(), f.strip_prefix(dir).ok()?)))
+            .context(format!("{} is not in any git repository", f.display()))?;
+        commits
+            .entry(dir)
+            .or_default()
+            .push(file.to_string_lossy().to_string());
+    }
+    for (dir, files) in commits {
+        let message = git::commit_message(&cfg.git.commit_message, &files);
+        let mut paths = files;
+        // info/exclude is not committed, and `none` leaves .gitignore to the user
+        if cfg.gitignore_mode == GitignoreMode::Gitignore {
+            for (_, g) in ignore_files.iter().filter(|(d, _)| d == dir) {
+                paths.push(pathbuf_to_str(canonicalize(g)?.strip_prefix(dir)?)?.to_string());
+            }
+        }
+        git::commit(dir, &paths, &message)?;
+        println!("{}", output::green(format!("committed: {}", message)));
+    }
     Ok(())
 }
+
+#[test]
+fn test_key_without_prompt() {
+    use clap::Parser;
+
+    let dir = std::env::temp_dir().join("lkdots-test-key-source");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let identity = dir.join("key.txt").to_string_lossy().to_string();
+    std::fs::write(
+        &identity,
+        age::secrecy::ExposeSecret::expose_secret(&age::x25519::Identity::generate().to_string()),
+    )
+    .unwrap();
+    let phrase = dir.join("phrase").to_string_lossy().to_string();
+    std::fs::write(&phrase, "from-file\n").unwrap();
+    let config: Config = ConfigFileStruct::load(&dir.join(CONFIG_FILE), "")
+        .unwrap()
+        .into();
+    let passphrase = |args: &[&str], env: Option<&str>| {
+        let cli = Cli::parse_from(args);
+        match key_without_prompt(&cli, &config, env.map(str::to_string)).unwrap() {
+            Some(Key::Passphrase(p)) => Some(p),
+            Some(Key::Identity { .. }) => Some("identity".to_string()),
+            None => None,
+        }
+    };
+    let env = Some("from-env");
+    assert_eq!(
+        passphrase(&["lkdots", "--identity", &identity, "decrypt"], env).as_deref(),
+        Some("identity")
+    );
+    assert_eq!(
+        passphrase(&["lkdots", "--passphrase-file", &phrase, "decrypt"], env).as_deref(),
+        Some("from-file")
+    );
+    assert_eq!(
+        passphrase(&["lkdots", "decrypt"], env).as_deref(),
+        Some("from-env")
+    );
+    assert_eq!(passphrase(&["lkdots", "decrypt"], None), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,23 +1,44 @@
 use crate::{
-    path_util::{pathbuf_to_str, relative_path},
-    symlink_util::create_symlink,
+    config::{Entry, LinkStrategy, Mode, SymlinkType},
+    copy_util::{is_same_device, is_same_file, is_up_to_date},
+    crypto::Naming,
+    exclude::Exclude,
+    fs_view::{CachedFs, Fs, FsView, Kind},
+    hook::run_hook,
+    i18n::tr,
+    output,
+    path_util::{is_within, pathbuf_to_str, relative_path},
+    perm_util::{needs_chmod, needs_chown, parse_mode},
+    planner,
+    symlink_util::is_broken_symlink,
+    transaction::Transaction,
 };
 use anyhow::{anyhow, Context, Result};
+use indicatif::ProgressBar;
 use log::info;
-use std::{
-    borrow::Cow,
-    fs::{create_dir_all, read_dir},
-    io::ErrorKind,
-    path::Path,
-};
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, collections::HashSet, io::ErrorKind, path::Path, sync::Arc};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "OpRecord", try_from = "OpRecord")]
 pub enum Op {
     Mkdirp(String),
     Symlink(String, String, String),
+    Unlink(String),
+    Backup(String, String),
+    Move(String, String),
+    Copy(String, String),
+    Hardlink(String, String),
+    Run(String),
+    Remove(String),
+    /// mode of a file, or of every file below a directory
+    Chmod(String, u32),
+    /// owner like `user:group` of a file, or of every file below a directory
+    Chown(String, String),
 
     Existed(String),
-    Conflict(String),
+    /// source and the existing target conflicting with it
+    Conflict(String, String),
 }
 
 impl std::fmt::Display for Op {
@@ -29,122 +50,676 @@ impl std::fmt::Display for Op {
                 "create symbol link {} -> {} relative: {}",
                 from, to, relative
             ),
-            Op::Existed(p) => write!(f, "{} is existed", p),
-            Op::Conflict(p) => write!(f, "{} is existed and conflicted", p),
+            Op::Unlink(p) => write!(f, "remove symbol link {}", p),
+            Op::Backup(from, to) => write!(f, "backup {} to {}", from, to),
+            Op::Move(from, to) => write!(f, "move {} to {}", from, to),
+            Op::Copy(from, to) => write!(f, "copy {} to {}", from, to),
+            Op::Hardlink(from, to) => write!(f, "create hard link {} -> {}", from, to),
+            Op::Run(cmd) => write!(f, "run {}", cmd),
+            Op::Remove(p) => write!(f, "remove {}", p),
+            Op::Chmod(p, mode) => write!(f, "chmod {:o} {}", mode, p),
+            Op::Chown(p, owner) => write!(f, "chown {} {}", owner, p),
+            Op::Existed(p) => write!(f, "{} already exists", p),
+            Op::Conflict(_, p) => write!(f, "{} already exists and conflicts", p),
+        }
+    }
+}
+
+/// flat serialized form of `Op`, e.g. `{"op": "symlink", "from": .., "to": .., "relative": ..}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OpRecord {
+    pub op: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relative: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// octal, e.g. `"600"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+impl OpRecord {
+    fn path(op: &str, path: String) -> Self {
+        OpRecord {
+            op: op.to_string(),
+            path: Some(path),
+            ..Default::default()
+        }
+    }
+    fn from_to(op: &str, from: String, to: String) -> Self {
+        OpRecord {
+            op: op.to_string(),
+            from: Some(from),
+            to: Some(to),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Op> for OpRecord {
+    fn from(op: Op) -> Self {
+        match op {
+            Op::Mkdirp(p) => OpRecord::path("mkdirp", p),
+            Op::Symlink(from, to, relative) => OpRecord {
+                relative: Some(relative),
+                ..OpRecord::from_to("symlink", from, to)
+            },
+            Op::Unlink(p) => OpRecord::path("unlink", p),
+            Op::Backup(from, to) => OpRecord::from_to("backup", from, to),
+            Op::Move(from, to) => OpRecord::from_to("move", from, to),
+            Op::Copy(from, to) => OpRecord::from_to("copy", from, to),
+            Op::Hardlink(from, to) => OpRecord::from_to("hardlink", from, to),
+            Op::Run(cmd) => OpRecord {
+                op: "run".to_string(),
+                command: Some(cmd),
+                ..Default::default()
+            },
+            Op::Remove(p) => OpRecord::path("remove", p),
+            Op::Chmod(p, mode) => OpRecord {
+                mode: Some(format!("{:o}", mode)),
+                ..OpRecord::path("chmod", p)
+            },
+            Op::Chown(p, owner) => OpRecord {
+                owner: Some(owner),
+                ..OpRecord::path("chown", p)
+            },
+            Op::Existed(p) => OpRecord::path("existed", p),
+            Op::Conflict(from, p) => OpRecord {
+                from: Some(from),
+                ..OpRecord::path("conflict", p)
+            },
+        }
+    }
+}
+
+fn required(v: Option<String>, op: &str, field: &str) -> Result<String, String> {
+    v.ok_or_else(|| format!("`{}` op requires `{}`", op, field))
+}
+
+impl TryFrom<OpRecord> for Op {
+    type Error = String;
+
+    fn try_from(r: OpRecord) -> Result<Self, Self::Error> {
+        let op = r.op.as_str();
+        let path = || required(r.path.clone(), op, "path");
+        let from = || required(r.from.clone(), op, "from");
+        let to = || required(r.to.clone(), op, "to");
+        Ok(match op {
+            "mkdirp" => Op::Mkdirp(path()?),
+            "symlink" => Op::Symlink(
+                from()?,
+                to()?,
+                required(r.relative.clone(), op, "relative")?,
+            ),
+            "unlink" => Op::Unlink(path()?),
+            "backup" => Op::Backup(from()?, to()?),
+            "move" => Op::Move(from()?, to()?),
+            "copy" => Op::Copy(from()?, to()?),
+            "hardlink" => Op::Hardlink(from()?, to()?),
+            "run" => Op::Run(required(r.command.clone(), op, "command")?),
+            "remove" => Op::Remove(path()?),
+            "chmod" => Op::Chmod(
+                path()?,
+                parse_mode(&required(r.mode.clone(), op, "mode")?).map_err(|e| e.to_string())?,
+            ),
+            "chown" => Op::Chown(path()?, required(r.owner.clone(), op, "owner")?),
+            "existed" => Op::Existed(path()?),
+            "conflict" => Op::Conflict(from()?, path()?),
+            _ => return Err(format!("unknown op `{}`", op)),
+        })
+    }
+}
+
+/// ops planned by `lkdots plan`, executed as they are by `lkdots apply --plan`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Plan {
+    pub ops: Vec<Op>,
+}
+
+impl Plan {
+    fn is_toml(path: &str) -> bool {
+        path.ends_with(".toml")
+    }
+
+    /// JSON, or TOML if `path` ends with `.toml`
+    pub fn save(&self, path: &str) -> Result<()> {
+        let s = if Plan::is_toml(path) {
+            toml::to_string(self)?
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        std::fs::write(path, s).context(format!("Fail to write plan {}", path))
+    }
+
+    pub fn load(path: &str) -> Result<Plan> {
+        let s = std::fs::read_to_string(path).context(format!("Fail to read plan {}", path))?;
+        let plan = if Plan::is_toml(path) {
+            toml::from_str(&s)?
+        } else {
+            serde_json::from_str(&s)?
+        };
+        Ok(plan)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OnConflict {
+    Abort,
+    /// move the existing target away, into the given directory or to `<to>.lkdots.bak`
+    Backup(Option<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkOptions {
+    pub exclude: Exclude,
+    pub on_conflict: OnConflict,
+    /// replace conflicting symbol links
+    pub force: bool,
+    pub mode: Mode,
+    pub strategy: LinkStrategy,
+    pub symlink_type: SymlinkType,
+    /// links lkdots created, `force` only replaces these, any link when unknown
+    pub owned_links: Option<Arc<HashSet<String>>>,
+    /// paths are looked up through it while planning
+    pub fs: Arc<dyn FsView>,
+    /// where ciphertexts are, they are never linked
+    pub naming: Arc<Naming>,
+}
+
+impl LinkOptions {
+    /// `to` is a link lkdots created, or ownership is not known
+    pub fn owns_link(&self, to: &str) -> bool {
+        self.owned_links
+            .as_ref()
+            .is_none_or(|links| links.contains(to))
+    }
+}
+
+fn backup_path(to: &str, backup_dir: Option<&str>) -> String {
+    match backup_dir {
+        Some(dir) => Path::new(shellexpand::tilde(dir).as_ref())
+            .join(to.trim_start_matches('/'))
+            .to_string_lossy()
+            .to_string(),
+        None => format!("{}.lkdots.bak", to),
+    }
+}
+
+fn conflict(from: Cow<str>, to: Cow<str>, opts: &LinkOptions, result: &mut Vec<Op>) -> Result<()> {
+    let is_symlink = opts.fs.kind(Path::new(to.as_ref())) == Some(Kind::Symlink);
+    // links made by the user are conflicts even with force
+    if opts.force && is_symlink && opts.owns_link(&to) {
+        result.push(Op::Unlink(to.to_string()));
+        return place_new(from, to, opts, result);
+    }
+    match &opts.on_conflict {
+        OnConflict::Abort => result.push(Op::Conflict(from.to_string(), to.to_string())),
+        OnConflict::Backup(dir) => {
+            let backup = backup_path(&to, dir.as_deref());
+            if opts.fs.kind(Path::new(&backup)).is_some() {
+                // never overwrite an older backup
+                result.push(Op::Conflict(from.to_string(), to.to_string()));
+            } else {
+                result.push(Op::Backup(to.to_string(), backup));
+                place_new(from, to, opts, result)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// how a conflict is resolved interactively
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Skip,
+    Backup,
+    Overwrite,
+    /// copy the changed target back to the source
+    Pull,
+}
+
+/// plan ops replacing `Op::Conflict(from, to)` by the chosen resolution
+pub fn resolve_conflict(
+    from: &str,
+    to: &str,
+    resolution: Resolution,
+    opts: &LinkOptions,
+    result: &mut Vec<Op>,
+) -> Result<()> {
+    match resolution {
+        Resolution::Skip => return Ok(()),
+        Resolution::Backup => {
+            let dir = match &opts.on_conflict {
+                OnConflict::Backup(dir) => dir.as_deref(),
+                OnConflict::Abort => None,
+            };
+            let backup = backup_path(to, dir);
+            if opts.fs.kind(Path::new(&backup)).is_some() {
+                return Err(anyhow!(tr("backup_existed", &[&backup])));
+            }
+            result.push(Op::Backup(to.to_string(), backup));
+        }
+        Resolution::Overwrite => result.push(Op::Remove(to.to_string())),
+        Resolution::Pull => {
+            result.push(Op::Copy(to.to_string(), from.to_string()));
+            return Ok(());
+        }
+    }
+    place_new(Cow::Borrowed(from), Cow::Borrowed(to), opts, result)
+}
+
+/// place source at a target which will not exist by then
+fn place_new(from: Cow<str>, to: Cow<str>, opts: &LinkOptions, result: &mut Vec<Op>) -> Result<()> {
+    match opts.mode {
+        Mode::Symlink => link_file(from, to, opts.symlink_type, opts.fs.as_ref(), result),
+        Mode::Copy | Mode::Hardlink => copy_new(from, to, opts, result),
+    }
+}
+
+/// ciphertext and excluded files are never placed at targets, also when merging directories
+fn is_skipped(from: &str, opts: &LinkOptions) -> bool {
+    opts.exclude.is_excluded(from)
+        || (opts.naming.is_ciphertext(from) && opts.fs.is_file(Path::new(from)))
+}
+
+/// paths in directory `dir` by file name
+fn sorted_dir(dir: &str, fs: &dyn FsView) -> Result<Vec<(String, String)>> {
+    fs.read_dir(Path::new(dir))?
+        .into_iter()
+        .map(|name| {
+            let path = Path::new(dir).join(&name);
+            let name = name
+                .into_string()
+                .map_err(|_| anyhow!("path is not valid str"))?;
+            Ok((pathbuf_to_str(&path)?.to_owned(), name))
+        })
+        .collect()
+}
+
+fn copy_new(from: Cow<str>, to: Cow<str>, opts: &LinkOptions, result: &mut Vec<Op>) -> Result<()> {
+    if opts.fs.is_dir(Path::new(from.as_ref())) {
+        result.push(Op::Mkdirp(to.to_string()));
+        for (from_str, name) in sorted_dir(&from, opts.fs.as_ref())? {
+            if is_skipped(&from_str, opts) {
+                continue;
+            }
+            let to_path = Path::new(to.as_ref()).join(name);
+            let to_str = pathbuf_to_str(&to_path)?;
+            copy_new(
+                Cow::Borrowed(&from_str),
+                Cow::Borrowed(to_str),
+                opts,
+                result,
+            )?;
+        }
+    } else {
+        if opts.mode == Mode::Hardlink {
+            if !is_same_device(&from, &to)? {
+                return Err(anyhow!(
+                    "Cannot create hard link {} -> {}, they are on different devices",
+                    from,
+                    to
+                ));
+            }
+            result.push(Op::Hardlink(from.to_string(), to.to_string()));
+        } else {
+            result.push(Op::Copy(from.to_string(), to.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// copy or hard link files, directories are created and merged
+fn copy_file_or_dir(
+    from: Cow<str>,
+    to: Cow<str>,
+    opts: &LinkOptions,
+    result: &mut Vec<Op>,
+) -> Result<()> {
+    let from_meta = Path::new(from.as_ref()).metadata()?;
+    let to_meta = match Path::new(to.as_ref()).symlink_metadata() {
+        Ok(to_meta) => to_meta,
+        Err(_) => {
+            let parent_dir = Path::new(to.as_ref()).parent().context("Not parent dir")?;
+            if !opts.fs.exists(parent_dir) {
+                result.push(Op::Mkdirp(pathbuf_to_str(parent_dir)?.into()));
+            }
+            return copy_new(from, to, opts, result);
+        }
+    };
+    if from_meta.is_dir() && to_meta.is_dir() {
+        for (from_str, name) in sorted_dir(&from, opts.fs.as_ref())? {
+            if is_skipped(&from_str, opts) {
+                continue;
+            }
+            let to_path = Path::new(to.as_ref()).join(name);
+            let to_str = pathbuf_to_str(&to_path)?;
+            copy_file_or_dir(
+                Cow::Borrowed(&from_str),
+                Cow::Borrowed(to_str),
+                opts,
+                result,
+            )?;
+        }
+    } else if from_meta.is_file() && to_meta.is_file() {
+        if opts.mode == Mode::Hardlink {
+            if is_same_file(&from_meta, &to_meta) {
+                result.push(Op::Existed(to.to_string()));
+            } else {
+                conflict(from, to, opts, result)?;
+            }
+        } else if is_up_to_date(&from_meta, &to_meta) {
+            result.push(Op::Existed(to.to_string()));
+        } else {
+            result.push(Op::Copy(from.to_string(), to.to_string()));
         }
+    } else {
+        conflict(from, to, opts, result)?;
+    }
+    Ok(())
+}
+
+pub fn link_file_or_dir(
+    from: Cow<str>,
+    to: Cow<str>,
+    opts: &LinkOptions,
+    result: &mut Vec<Op>,
+) -> Result<()> {
+    if is_within(&to, &from) {
+        return Err(anyhow!(
+            "target {} is inside its source {}, linking it would create a loop",
+            to,
+            from
+        ));
     }
+    // merging into an existing directory is fine, replacing it would move the source away
+    if is_within(&from, &to) && !opts.fs.is_dir(Path::new(to.as_ref())) {
+        return Err(anyhow!(
+            "source {} is inside its target {}, linking it would create a loop",
+            from,
+            to
+        ));
+    }
+    link_path(from, to, opts, result)
 }
 
-pub fn link_file_or_dir(from: Cow<str>, to: Cow<str>, result: &mut Vec<Op>) -> Result<()> {
-    let metadata = Path::new(to.as_ref()).symlink_metadata();
-    if let Ok(metadata) = metadata {
+/// `link_file_or_dir` without the loop checks, children of a checked source can't loop
+fn link_path(from: Cow<str>, to: Cow<str>, opts: &LinkOptions, result: &mut Vec<Op>) -> Result<()> {
+    if opts.naming.is_ciphertext(&from) && opts.fs.is_file(Path::new(from.as_ref())) {
+        return Ok(());
+    }
+    if opts.mode != Mode::Symlink {
+        return copy_file_or_dir(from, to, opts, result);
+    }
+    match opts.fs.kind(Path::new(to.as_ref())) {
         // file existed
-        if metadata.is_symlink() {
-            let sym_target = std::fs::canonicalize(to.as_ref());
+        Some(Kind::Symlink) => {
+            let sym_target = opts.fs.canonicalize(Path::new(to.as_ref()));
             if let Err(err) = sym_target.as_ref() {
                 if err.kind() == ErrorKind::NotFound {
-                    result.push(Op::Conflict(to.to_string()));
-                    return Ok(());
+                    return conflict(from, to, opts, result);
                 }
             }
             let sym_target = sym_target?;
-            let sym_target = sym_target.to_str().context("Fail to get str path")?;
-            let abs_from = std::fs::canonicalize(from.as_ref())?;
-            let abs_from = abs_from.to_str().context("Fail to get str path")?;
+            let abs_from = opts.fs.canonicalize(Path::new(from.as_ref()))?;
             if sym_target != abs_from {
-                result.push(Op::Conflict(to.to_string()));
+                conflict(from, to, opts, result)?;
             } else {
                 result.push(Op::Existed(to.to_string()));
             }
-        } else if metadata.is_dir() {
-            link_dir(from, to, result)?;
-        } else {
-            result.push(Op::Conflict(to.to_string()));
         }
-    } else {
-        let from_path = Path::new(from.as_ref());
-        if from_path.symlink_metadata()?.is_dir() {
-            link_dir(from, to, result)?;
-        } else {
-            link_file(from, to, result)?;
-        };
+        Some(Kind::Dir) => link_dir(from, to, opts, result)?,
+        Some(_) => conflict(from, to, opts, result)?,
+        None => match opts.fs.kind(Path::new(from.as_ref())) {
+            Some(Kind::Dir) => link_dir(from, to, opts, result)?,
+            Some(_) => link_file(from, to, opts.symlink_type, opts.fs.as_ref(), result)?,
+            None => {
+                return Err(std::io::Error::from(ErrorKind::NotFound))
+                    .context(format!("Fail to read {}", from))
+            }
+        },
     }
     Ok(())
 }
 
-fn link_file(from: Cow<str>, to: Cow<str>, res: &mut Vec<Op>) -> Result<()> {
-    if from.ends_with(".enc") {
+pub fn unlink_file_or_dir(
+    from: Cow<str>,
+    to: Cow<str>,
+    opts: &LinkOptions,
+    result: &mut Vec<Op>,
+) -> Result<()> {
+    let fs = opts.fs.as_ref();
+    match fs.kind(Path::new(to.as_ref())) {
+        Some(Kind::Symlink) => {
+            // only remove links pointing back to the source
+            if let (Ok(sym_target), Ok(abs_from)) = (
+                fs.canonicalize(Path::new(to.as_ref())),
+                fs.canonicalize(Path::new(from.as_ref())),
+            ) {
+                if sym_target == abs_from {
+                    result.push(Op::Unlink(to.to_string()));
+                }
+            }
+        }
+        Some(Kind::Dir) if fs.is_dir(Path::new(from.as_ref())) => {
+            // directory merged file by file, unlink files in directory
+            for (from_str, name) in sorted_dir(&from, fs)? {
+                if opts.exclude.is_excluded(&from_str) {
+                    continue;
+                }
+
+                let to_path = Path::new(to.as_ref()).join(name);
+                let to_str = to_path.to_str().context("Fail to get str path")?;
+
+                unlink_file_or_dir(
+                    Cow::Borrowed(&from_str),
+                    Cow::Borrowed(to_str),
+                    opts,
+                    result,
+                )?;
+            }
+        }
+        // nothing to unlink
+        _ => {}
+    }
+    Ok(())
+}
+
+/// replace dangling symbol links planned as conflicts by links or copies like the `mode`
+/// of `opts`, other ops are dropped
+pub fn create_repair_ops(ops: &[Op], opts: &LinkOptions) -> Result<Vec<Op>> {
+    let mut result = vec![];
+    for op in ops {
+        if let Op::Conflict(from, to) = op {
+            if is_broken_symlink(to) {
+                result.push(Op::Unlink(to.clone()));
+                let (from, to) = (Cow::Borrowed(from.as_str()), Cow::Borrowed(to.as_str()));
+                match opts.mode {
+                    Mode::Symlink => {
+                        link_file(from, to, opts.symlink_type, opts.fs.as_ref(), &mut result)?
+                    }
+                    Mode::Copy | Mode::Hardlink => copy_new(from, to, opts, &mut result)?,
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// chmod and chown targets placed or kept by `ops`, files already having the mode or the
+/// owner are skipped
+pub fn create_permission_ops(ops: &[Op], mode: Option<u32>, owner: Option<&str>) -> Vec<Op> {
+    let mut result = vec![];
+    for op in ops {
+        // links share the mode of their source, copies get it from the source
+        let (src, to) = match op {
+            Op::Symlink(from, to, _) | Op::Copy(from, to) | Op::Hardlink(from, to) => (from, to),
+            Op::Existed(to) => (to, to),
+            _ => continue,
+        };
+        if let Some(mode) = mode {
+            if needs_chmod(src, mode) {
+                result.push(Op::Chmod(to.clone(), mode));
+            }
+        }
+        if let Some(owner) = owner.filter(|owner| needs_chown(src, owner)) {
+            result.push(Op::Chown(to.clone(), owner.to_string()));
+        }
+    }
+    result
+}
+
+/// move an existing target into the missing source, then link it back
+pub fn adopt_file_or_dir(
+    from: Cow<str>,
+    to: Cow<str>,
+    symlink_type: SymlinkType,
+    result: &mut Vec<Op>,
+) -> Result<()> {
+    if Path::new(from.as_ref()).symlink_metadata().is_ok() {
+        // source existed, nothing to adopt
+        return Ok(());
+    }
+    let metadata = match Path::new(to.as_ref()).symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+    if metadata.is_symlink() {
         return Ok(());
     }
-    let parent_dir = Path::new(to.as_ref())
+    let parent_dir = Path::new(from.as_ref())
         .parent()
         .context("Not parent dir")?;
-    let to_dir = 
-        parent_dir
-        .to_str()
-        .context("Fail to get str path")?;
-    
     if !parent_dir.exists() {
+        result.push(Op::Mkdirp(pathbuf_to_str(parent_dir)?.into()));
+    }
+    result.push(Op::Move(to.to_string(), from.to_string()));
+    link_file(from, to, symlink_type, &CachedFs::default(), result)
+}
+
+/// content of the symbol link at `to` pointing to `from`
+/// relative targets are from the real directory of `to`, which may be inside a linked one
+fn link_target(from: &str, to: &str, symlink_type: SymlinkType, fs: &dyn FsView) -> Result<String> {
+    match symlink_type {
+        SymlinkType::Relative => {
+            let to_dir = Path::new(to).parent().context("Not parent dir")?;
+            let to_dir = fs
+                .canonicalize(to_dir)
+                .unwrap_or_else(|_| to_dir.to_path_buf());
+            Ok(relative_path(from, pathbuf_to_str(&to_dir)?)?
+                .to_string_lossy()
+                .to_string())
+        }
+        SymlinkType::Absolute if Path::new(from).is_absolute() => Ok(from.to_string()),
+        SymlinkType::Absolute => {
+            Ok(pathbuf_to_str(&std::env::current_dir()?.join(from))?.to_owned())
+        }
+    }
+}
+
+fn link_file(
+    from: Cow<str>,
+    to: Cow<str>,
+    symlink_type: SymlinkType,
+    fs: &dyn FsView,
+    res: &mut Vec<Op>,
+) -> Result<()> {
+    let parent_dir = Path::new(to.as_ref()).parent().context("Not parent dir")?;
+    let to_dir = parent_dir.to_str().context("Fail to get str path")?;
+
+    if !fs.exists(parent_dir) && !res.contains(&Op::Mkdirp(to_dir.into())) {
         res.push(Op::Mkdirp(to_dir.into()));
     }
-    let relative = relative_path(from.as_ref(), to_dir)?;
+    let target = link_target(&from, &to, symlink_type, fs)?;
 
-    res.push(Op::Symlink(
-        from.to_string(),
-        to.to_string(),
-        relative.to_string_lossy().to_string(),
-    ));
+    res.push(Op::Symlink(from.to_string(), to.to_string(), target));
     Ok(())
 }
 
-fn link_dir(from: Cow<str>, to: Cow<str>, result: &mut Vec<Op>) -> Result<()> {
-    let relative = {
-        let to_path = Path::new(to.as_ref());
-        let to_dir = to_path
-            .parent()
-            .context("Not parent dir")?
-            .to_str()
-            .context("Fail to get str path")?;
-
-        relative_path(from.as_ref(), to_dir)?
-    };
+fn link_dir(from: Cow<str>, to: Cow<str>, opts: &LinkOptions, result: &mut Vec<Op>) -> Result<()> {
     let to_path = Path::new(to.as_ref());
-    if !to_path.exists() {
+    let to_exists = opts.fs.exists(to_path);
+    if !to_exists && opts.strategy == LinkStrategy::Dir {
         // create_dir_all(to_path.parent().unwrap_or(Path::new("/")))?;
         let parent_path = to_path.parent().unwrap_or_else(|| Path::new("/"));
-        if !parent_path.exists() {
+        if !opts.fs.exists(parent_path) {
             result.push(Op::Mkdirp(parent_path.to_str().unwrap().into()));
         }
-        result.push(Op::Symlink(
-            from.into(),
-            to.into(),
-            relative.to_str().context("Fail to get str path")?.into(),
-        ));
+        let target = link_target(&from, &to, opts.symlink_type, opts.fs.as_ref())?;
+        result.push(Op::Symlink(from.into(), to.into(), target));
     } else {
+        if !to_exists {
+            result.push(Op::Mkdirp(to.to_string()));
+        }
         // directory existed, link files in directory
-        for f in read_dir(from.as_ref())? {
-            let f = f?;
-            let from_path = f.path().to_path_buf();
-            let from_str = pathbuf_to_str(&from_path)?;
-
-            let fname = f.file_name();
-            let fname = fname.to_str().context("Fail to get str path")?;
+        let child_opts = match opts.strategy {
+            // only the first level is unfolded
+            LinkStrategy::Contents => Cow::Owned(LinkOptions {
+                strategy: LinkStrategy::Dir,
+                ..opts.clone()
+            }),
+            _ => Cow::Borrowed(opts),
+        };
+        for (from_str, fname) in sorted_dir(&from, opts.fs.as_ref())? {
+            if is_skipped(&from_str, opts) {
+                continue;
+            }
 
             let to_path = Path::new(to.as_ref()).join(fname);
 
             let to_str = to_path.to_str().context("Fail to get str path")?;
 
             // println!("{:?} {:?}", from_path, to_str);
-            link_file_or_dir(Cow::Borrowed(from_str), Cow::Borrowed(to_str), result)?;
+            link_path(
+                Cow::Borrowed(&from_str),
+                Cow::Borrowed(to_str),
+                &child_opts,
+                result,
+            )?;
         }
     }
     Ok(())
 }
 
-pub fn excute(ops: &[Op]) -> Result<()> {
+/// plan ops of entries matching this platform, grouped by entry
+pub fn plan(entries: &[Entry], base_dir: &Path) -> Result<Vec<Vec<Op>>> {
+    let entries = entries
+        .iter()
+        .filter(|e| e.match_platform())
+        .collect::<Vec<_>>();
+    let after = planner::entry_deps(&entries);
+    planner::plan_after(&entries, &after, |e| e.create_ops(base_dir))
+}
+
+pub use self::excute as execute;
+
+/// execute ops, changes are rolled back if any op fails
+pub fn excute(ops: &[Op]) -> Result<Transaction> {
+    excute_with_progress(ops, &ProgressBar::hidden())
+}
+
+/// `excute`, making the changes to `fs` instead of the disk
+pub fn excute_in(ops: &[Op], fs: Arc<dyn Fs>) -> Result<Transaction> {
+    excute_into(ops, &ProgressBar::hidden(), Transaction::with_fs(fs))
+}
+
+/// `excute`, advancing `bar` for every op
+pub fn excute_with_progress(ops: &[Op], bar: &ProgressBar) -> Result<Transaction> {
+    excute_into(ops, bar, Transaction::new())
+}
+
+fn excute_into(ops: &[Op], bar: &ProgressBar, mut tx: Transaction) -> Result<Transaction> {
     let mut conflicts = vec![];
     for op in ops {
-        if let Op::Conflict(p) = op {
+        if let Op::Conflict(_, p) = op {
             conflicts.push(p);
         }
     }
@@ -152,33 +727,419 @@ pub fn excute(ops: &[Op]) -> Result<()> {
     if !conflicts.is_empty() {
         let err_log = conflicts
             .iter()
-            .map(|&p| format!("{} is existed and conlict to your configuration", p))
+            .map(|&p| tr("conflict_with_config", &[p]))
             .collect::<Vec<_>>()
             .join("\n");
         return Err(anyhow!(err_log));
     }
 
     for op in ops {
-        match op {
-            Op::Existed(p) => {
-                info!("existed: {}", p);
-            }
-            Op::Conflict(p) => {
-                info!("conflict: {}", p);
-                return Err(anyhow!(
-                    "{} is existed and conlict to your configuration",
-                    p
-                ));
-            }
-            Op::Mkdirp(p) => {
-                create_dir_all(p)?;
-                info!("mkdirp: {}", p);
+        bar.set_message(op.to_string());
+        if let Err(err) = excute_op(op, &mut tx) {
+            output::failure(bar, op);
+            bar.abandon();
+            tx.rollback();
+            return Err(err);
+        }
+        if !matches!(op, Op::Existed(_)) {
+            output::success(bar, op);
+        }
+        bar.inc(1);
+    }
+    bar.finish_with_message("done");
+    Ok(tx)
+}
+
+pub(crate) fn excute_op(op: &Op, tx: &mut Transaction) -> Result<()> {
+    match op {
+        Op::Existed(p) => {
+            info!("existed: {}", p);
+        }
+        Op::Conflict(_, p) => {
+            info!("conflict: {}", p);
+            return Err(anyhow!(tr("conflict_with_config", &[p])));
+        }
+        Op::Mkdirp(p) => {
+            tx.mkdirp(p)?;
+            info!("mkdirp: {}", p);
+        }
+        Op::Symlink(from, to, relative) => {
+            info!("symbol link: {} -> {} [{}]", from, to, relative);
+            tx.symlink(from, to, relative)?;
+        }
+        Op::Unlink(p) => {
+            info!("unlink: {}", p);
+            tx.unlink(p)?;
+        }
+        Op::Backup(from, to) => {
+            info!("backup: {} -> {}", from, to);
+            if let Some(parent) = Path::new(to).parent() {
+                tx.mkdirp(pathbuf_to_str(parent)?)?;
             }
-            Op::Symlink(from, to, relative) => {
-                info!("symbol link: {} -> {} [{}]", from, to, relative);
-                create_symlink(from, to, relative)?;
+            tx.rename(from, to)
+                .context(format!("Fail to backup {} to {}", from, to))?;
+        }
+        Op::Copy(from, to) => {
+            if tx.fs().needs_copy(Path::new(from), Path::new(to))? {
+                info!("copy: {} -> {}", from, to);
+                tx.copy(from, to)?;
+            } else {
+                info!("up to date: {}", to);
             }
         }
+        Op::Hardlink(from, to) => {
+            info!("hard link: {} -> {}", from, to);
+            tx.hard_link(from, to)?;
+        }
+        Op::Run(cmd) => {
+            info!("run: {}", cmd);
+            run_hook(cmd)?;
+        }
+        Op::Move(from, to) => {
+            info!("move: {} -> {}", from, to);
+            tx.rename(from, to)?;
+        }
+        Op::Remove(p) => {
+            info!("remove: {}", p);
+            tx.remove(p)?;
+        }
+        Op::Chmod(p, mode) => {
+            info!("chmod: {:o} {}", mode, p);
+            tx.chmod(p, *mode)?;
+        }
+        Op::Chown(p, owner) => {
+            info!("chown: {} {}", owner, p);
+            tx.chown(p, owner)?;
+        }
     }
     Ok(())
 }
+
+#[test]
+fn test_link_strategy_recursive() {
+    let root = std::env::temp_dir().join("lkdots-test-link-strategy");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("from/nvim/lua")).unwrap();
+    std::fs::write(root.join("from/nvim/lua/init.lua"), "").unwrap();
+    let from = pathbuf_to_str(&root.join("from/nvim")).unwrap().to_owned();
+    let to = pathbuf_to_str(&root.join("to/nvim")).unwrap().to_owned();
+    let opts = LinkOptions {
+        exclude: Exclude::new(&from, &[]).unwrap(),
+        on_conflict: OnConflict::Abort,
+        force: false,
+        mode: Mode::Symlink,
+        strategy: LinkStrategy::Recursive,
+        symlink_type: SymlinkType::Relative,
+        owned_links: None,
+        fs: Arc::new(CachedFs::default()),
+        naming: Arc::new(Naming::default()),
+    };
+    let mut ops = vec![];
+    link_file_or_dir(Cow::Borrowed(&from), Cow::Borrowed(&to), &opts, &mut ops).unwrap();
+    let links = ops
+        .iter()
+        .filter_map(|op| match op {
+            Op::Symlink(_, to, _) => Some(to.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(links, vec![format!("{}/lua/init.lua", to)]);
+    assert!(ops.contains(&Op::Mkdirp(format!("{}/lua", to))));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_permission_ops() {
+    use crate::perm_util::{owner_of, set_mode};
+
+    let root = std::env::temp_dir().join("lkdots-test-permission-ops");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let from = root.join("zshrc");
+    std::fs::write(&from, "").unwrap();
+    set_mode(&from, 0o600).unwrap();
+    let (uid, gid) = owner_of(&from).unwrap();
+    let from = pathbuf_to_str(&from).unwrap().to_owned();
+    let ops = vec![Op::Symlink(
+        from.clone(),
+        "/home/a/.zshrc".to_string(),
+        from,
+    )];
+    // the source already has the mode and the owner
+    let owner = format!("{}:{}", uid, gid);
+    assert!(create_permission_ops(&ops, Some(0o600), Some(&owner)).is_empty());
+    let other = format!(":{}", gid + 1);
+    assert_eq!(
+        create_permission_ops(&ops, Some(0o644), Some(&other)),
+        vec![
+            Op::Chmod("/home/a/.zshrc".to_string(), 0o644),
+            Op::Chown("/home/a/.zshrc".to_string(), other.clone()),
+        ]
+    );
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_repair_mode() {
+    let root = std::env::temp_dir().join("lkdots-test-repair-mode");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("zshrc"), "").unwrap();
+    std::os::unix::fs::symlink(root.join("missing"), root.join(".zshrc")).unwrap();
+    let from = pathbuf_to_str(&root.join("zshrc")).unwrap().to_owned();
+    let to = pathbuf_to_str(&root.join(".zshrc")).unwrap().to_owned();
+    let ops = vec![Op::Conflict(from.clone(), to.clone())];
+    let repair = |mode| {
+        let opts = LinkOptions {
+            exclude: Exclude::new(&from, &[]).unwrap(),
+            on_conflict: OnConflict::Abort,
+            force: false,
+            mode,
+            strategy: LinkStrategy::Dir,
+            symlink_type: SymlinkType::Absolute,
+            owned_links: None,
+            fs: Arc::new(CachedFs::default()),
+            naming: Arc::new(Naming::default()),
+        };
+        create_repair_ops(&ops, &opts).unwrap()
+    };
+    let unlink = Op::Unlink(to.clone());
+    assert_eq!(
+        repair(Mode::Symlink),
+        vec![
+            unlink.clone(),
+            Op::Symlink(from.clone(), to.clone(), from.clone())
+        ]
+    );
+    assert_eq!(
+        repair(Mode::Copy),
+        vec![unlink.clone(), Op::Copy(from.clone(), to.clone())]
+    );
+    assert_eq!(
+        repair(Mode::Hardlink),
+        vec![unlink, Op::Hardlink(from.clone(), to.clone())]
+    );
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_merge_skips_ciphertext() {
+    let root = std::env::temp_dir().join("lkdots-test-merge-enc");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("from/ssh")).unwrap();
+    std::fs::create_dir_all(root.join("to/ssh")).unwrap();
+    std::fs::write(root.join("from/ssh/config"), "").unwrap();
+    std::fs::write(root.join("from/ssh/id_ed25519.enc"), "").unwrap();
+    std::fs::write(root.join("to/ssh/id_ed25519.enc"), "").unwrap();
+    let from = pathbuf_to_str(&root.join("from/ssh")).unwrap().to_owned();
+    let to = pathbuf_to_str(&root.join("to/ssh")).unwrap().to_owned();
+    let opts = LinkOptions {
+        exclude: Exclude::new(&from, &[]).unwrap(),
+        on_conflict: OnConflict::Abort,
+        force: false,
+        mode: Mode::Symlink,
+        strategy: LinkStrategy::Dir,
+        symlink_type: SymlinkType::Relative,
+        owned_links: None,
+        fs: Arc::new(CachedFs::default()),
+        naming: Arc::new(Naming::default()),
+    };
+    let mut ops = vec![];
+    link_file_or_dir(Cow::Borrowed(&from), Cow::Borrowed(&to), &opts, &mut ops).unwrap();
+    let targets = ops
+        .iter()
+        .filter_map(|op| match op {
+            Op::Symlink(_, to, _) | Op::Existed(to) | Op::Conflict(_, to) => Some(to.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(targets, vec![format!("{}/config", to)]);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_force_owned_links() {
+    let root = std::env::temp_dir().join("lkdots-test-force-owned");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("zshrc"), "").unwrap();
+    std::os::unix::fs::symlink("elsewhere", root.join(".zshrc")).unwrap();
+    let from = pathbuf_to_str(&root.join("zshrc")).unwrap().to_owned();
+    let to = pathbuf_to_str(&root.join(".zshrc")).unwrap().to_owned();
+    let mut opts = LinkOptions {
+        exclude: Exclude::new(&from, &[]).unwrap(),
+        on_conflict: OnConflict::Abort,
+        force: true,
+        mode: Mode::Symlink,
+        strategy: LinkStrategy::Dir,
+        symlink_type: SymlinkType::Relative,
+        owned_links: Some(Arc::new(HashSet::new())),
+        fs: Arc::new(CachedFs::default()),
+        naming: Arc::new(Naming::default()),
+    };
+    let mut ops = vec![];
+    conflict(Cow::Borrowed(&from), Cow::Borrowed(&to), &opts, &mut ops).unwrap();
+    assert_eq!(ops, vec![Op::Conflict(from.clone(), to.clone())]);
+    opts.owned_links = Some(Arc::new(HashSet::from([to.clone()])));
+    ops.clear();
+    conflict(Cow::Borrowed(&from), Cow::Borrowed(&to), &opts, &mut ops).unwrap();
+    assert_eq!(ops[0], Op::Unlink(to));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_plan_roundtrip() {
+    let plan = Plan {
+        ops: vec![
+            Op::Mkdirp("/home/a/.config".to_string()),
+            Op::Symlink(
+                "/dotfiles/nvim".to_string(),
+                "/home/a/.config/nvim".to_string(),
+                "../../../dotfiles/nvim".to_string(),
+            ),
+            Op::Chmod("/home/a/.ssh/config".to_string(), 0o600),
+            Op::Run("echo linked".to_string()),
+        ],
+    };
+    let json = serde_json::to_string(&plan).unwrap();
+    assert_eq!(serde_json::from_str::<Plan>(&json).unwrap().ops, plan.ops);
+    let toml = toml::to_string(&plan).unwrap();
+    assert_eq!(toml::from_str::<Plan>(&toml).unwrap().ops, plan.ops);
+    assert!(serde_json::from_str::<Plan>(r#"{"ops": [{"op": "copy"}]}"#).is_err());
+}
+
+#[test]
+fn test_plan_in_memory() {
+    use crate::fs_view::MemFs;
+
+    let fs = Arc::new(MemFs::new());
+    for dir in [
+        "/dots/nvim/lua",
+        "/dots/nvim/ftplugin",
+        "/home/a/.config/nvim",
+    ] {
+        fs.create_dir_all(Path::new(dir)).unwrap();
+    }
+    for file in [
+        "/dots/nvim/init.lua",
+        "/dots/nvim/lua/plugins.lua",
+        "/home/a/.config/nvim/ftplugin",
+    ] {
+        fs.write(Path::new(file), "").unwrap();
+    }
+    fs.restore_symlink(
+        Path::new("/dots/nvim/init.lua"),
+        Path::new("/home/a/.config/nvim/init.lua"),
+    )
+    .unwrap();
+    let opts = LinkOptions {
+        exclude: Exclude::new("/dots/nvim", &[]).unwrap(),
+        on_conflict: OnConflict::Abort,
+        force: false,
+        mode: Mode::Symlink,
+        strategy: LinkStrategy::Dir,
+        symlink_type: SymlinkType::Absolute,
+        owned_links: None,
+        fs: fs.clone(),
+        naming: Arc::new(Naming::default()),
+    };
+    let mut ops = vec![];
+    link_file_or_dir(
+        Cow::Borrowed("/dots/nvim"),
+        Cow::Borrowed("/home/a/.config/nvim"),
+        &opts,
+        &mut ops,
+    )
+    .unwrap();
+    assert_eq!(
+        ops,
+        vec![
+            Op::Conflict(
+                "/dots/nvim/ftplugin".to_string(),
+                "/home/a/.config/nvim/ftplugin".to_string()
+            ),
+            Op::Existed("/home/a/.config/nvim/init.lua".to_string()),
+            Op::Symlink(
+                "/dots/nvim/lua".to_string(),
+                "/home/a/.config/nvim/lua".to_string(),
+                "/dots/nvim/lua".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_excute_in_memory() {
+    use crate::fs_view::MemFs;
+
+    let fs = Arc::new(MemFs::new());
+    fs.create_dir_all(Path::new("/dots/nvim")).unwrap();
+    fs.write(Path::new("/dots/zshrc"), "zsh").unwrap();
+    fs.create_dir_all(Path::new("/home/a/.config/nvim"))
+        .unwrap();
+    let ops = vec![
+        Op::Mkdirp("/home/a/.config".to_string()),
+        Op::Backup(
+            "/home/a/.config/nvim".to_string(),
+            "/home/a/.config/nvim.bak".to_string(),
+        ),
+        Op::Symlink(
+            "/dots/nvim".to_string(),
+            "/home/a/.config/nvim".to_string(),
+            "../../../dots/nvim".to_string(),
+        ),
+        Op::Copy("/dots/zshrc".to_string(), "/home/a/.zshrc".to_string()),
+    ];
+    excute_in(&ops, fs.clone()).unwrap().commit().unwrap();
+    let nvim = Path::new("/home/a/.config/nvim");
+    assert_eq!(fs.canonicalize(nvim).unwrap(), Path::new("/dots/nvim"));
+    assert!(fs.is_dir(Path::new("/home/a/.config/nvim.bak")));
+    assert!(!fs
+        .needs_copy(Path::new("/dots/zshrc"), Path::new("/home/a/.zshrc"))
+        .unwrap());
+
+    // a failing op rolls back the ones before it
+    let ops = vec![
+        Op::Unlink("/home/a/.config/nvim".to_string()),
+        Op::Remove("/home/a/.config/missing".to_string()),
+    ];
+    assert!(excute_in(&ops, fs.clone()).is_err());
+    assert_eq!(fs.kind(nvim), Some(Kind::Symlink));
+    assert_eq!(fs.canonicalize(nvim).unwrap(), Path::new("/dots/nvim"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_rollback_after_failure() {
+    let dir = std::env::temp_dir().join("lkdots-test-rollback");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("dots")).unwrap();
+    std::fs::create_dir_all(dir.join("home")).unwrap();
+    std::fs::write(dir.join("dots/zshrc"), "new").unwrap();
+    std::fs::write(dir.join("home/.zshrc"), "old").unwrap();
+    let p = |f: &str| pathbuf_to_str(&dir.join(f)).unwrap().to_string();
+    let ops = vec![
+        Op::Mkdirp(p("home/.config/nvim")),
+        Op::Backup(p("home/.zshrc"), p("backup/.zshrc")),
+        Op::Symlink(
+            p("dots/zshrc"),
+            p("home/.zshrc"),
+            "../dots/zshrc".to_string(),
+        ),
+        Op::Remove(p("home/missing")),
+    ];
+    assert!(excute(&ops).is_err());
+    let zshrc = dir.join("home/.zshrc");
+    assert!(!zshrc.is_symlink());
+    assert_eq!(std::fs::read_to_string(&zshrc).unwrap(), "old");
+    assert!(!dir.join("backup").exists());
+    assert!(!dir.join("home/.config").exists());
+    assert_eq!(
+        std::fs::read_to_string(dir.join("dots/zshrc")).unwrap(),
+        "new"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,152 @@
+use crate::{i18n::tr, operations::Op};
+use indicatif::ProgressBar;
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+static COLORED: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// colors are used in a terminal unless `--no-color` or `NO_COLOR` is set
+pub fn init(no_color: bool, quiet: bool) {
+    let colored =
+        !no_color && std::env::var_os("NO_COLOR").is_none() && atty::is(atty::Stream::Stdout);
+    COLORED.store(colored, Ordering::Relaxed);
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+fn paint(code: &str, s: impl Display) -> String {
+    if COLORED.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", code, s)
+    } else {
+        format!("{}", s)
+    }
+}
+
+pub fn green(s: impl Display) -> String {
+    paint("32", s)
+}
+
+pub fn red(s: impl Display) -> String {
+    paint("31", s)
+}
+
+pub fn yellow(s: impl Display) -> String {
+    paint("33", s)
+}
+
+pub fn bold(s: impl Display) -> String {
+    paint("1", s)
+}
+
+/// line of a finished change, e.g. `✓ create dir ~/.config`
+pub fn success(bar: &ProgressBar, s: impl Display) {
+    print_line(bar, format!("{} {}", green("✓"), s));
+}
+
+/// line of a failed change
+pub fn failure(bar: &ProgressBar, s: impl Display) {
+    print_line(bar, format!("{} {}", red("✗"), s));
+}
+
+/// print above progress bars, nothing is printed with `--quiet`
+fn print_line(bar: &ProgressBar, s: String) {
+    if is_quiet() {
+        return;
+    }
+    if bar.is_hidden() {
+        println!("{}", s);
+    } else {
+        bar.println(s);
+    }
+}
+
+/// what a run changed, printed at its end
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    pub symlinks: usize,
+    pub hardlinks: usize,
+    /// files placed by `mode = "copy"`
+    pub copies: usize,
+    pub existed: usize,
+    pub dirs: usize,
+    /// conflicts skipped when asked how to resolve them
+    pub skipped: usize,
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    pub fn from_ops<'a>(
+        ops: impl Iterator<Item = &'a Op>,
+        skipped: usize,
+        elapsed: Duration,
+    ) -> Self {
+        let mut summary = Summary {
+            skipped,
+            elapsed,
+            ..Default::default()
+        };
+        for op in ops {
+            match op {
+                Op::Symlink(..) => summary.symlinks += 1,
+                Op::Hardlink(..) => summary.hardlinks += 1,
+                Op::Copy(..) => summary.copies += 1,
+                Op::Existed(_) => summary.existed += 1,
+                Op::Mkdirp(_) => summary.dirs += 1,
+                _ => {}
+            }
+        }
+        summary
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let skipped = tr("conflicts_skipped", &[&self.skipped]);
+        let summary = tr(
+            "summary",
+            &[
+                &green(self.symlinks),
+                &green(self.hardlinks),
+                &green(self.copies),
+                &self.existed,
+                &self.dirs,
+                &if self.skipped > 0 {
+                    yellow(skipped)
+                } else {
+                    skipped
+                },
+                &format!("{:.2}", self.elapsed.as_secs_f64()),
+            ],
+        );
+        write!(f, "{}", summary)
+    }
+}
+
+#[test]
+fn test_summary() {
+    let ops = [
+        Op::Mkdirp("/home/a/.config".to_string()),
+        Op::Symlink(
+            "/dotfiles/nvim".to_string(),
+            "/home/a/.config/nvim".to_string(),
+            "../../dotfiles/nvim".to_string(),
+        ),
+        Op::Existed("/home/a/.zshrc".to_string()),
+        Op::Copy(
+            "/dotfiles/gitconfig".to_string(),
+            "/home/a/.gitconfig".to_string(),
+        ),
+    ];
+    let summary = Summary::from_ops(ops.iter(), 1, Duration::from_millis(120));
+    assert_eq!(
+        summary.to_string(),
+        "1 symlinks created, 0 hard links created, 1 files copied, 1 already linked, 1 dirs created, 1 conflicts skipped in 0.12s"
+    );
+}
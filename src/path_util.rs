@@ -1,5 +1,6 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use pathdiff::diff_paths;
+use std::fs::{read_dir, DirEntry};
 use std::io::{self, Error, ErrorKind};
 use std::path::{Path, PathBuf};
 
@@ -18,6 +19,13 @@ pub fn get_dir(p: &Path) -> io::Result<&Path> {
     }
 }
 
+/// entries of directory `p` by file name, so plans are the same on every run
+pub fn sorted_dir(p: &str) -> io::Result<Vec<DirEntry>> {
+    let mut entries = read_dir(p)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    Ok(entries)
+}
+
 #[inline]
 pub fn relative_path(from: &str, to: &str) -> anyhow::Result<PathBuf> {
     diff_paths(from, to).context(format!(
@@ -30,3 +38,141 @@ pub fn relative_path(from: &str, to: &str) -> anyhow::Result<PathBuf> {
 pub fn pathbuf_to_str(pb: &Path) -> Result<&str> {
     pb.to_str().context("path is not valid str")
 }
+
+/// expand `~`, `{config}` like shorthands and environment variables like `$XDG_CONFIG_HOME`,
+/// `%APPDATA%` on Windows
+pub fn expand_path(p: &str) -> Result<String> {
+    let p = known_dirs(p)?;
+    let p = if cfg!(windows) { windows_vars(&p) } else { p };
+    shellexpand::full(&p)
+        .map(|s| s.to_string())
+        .map_err(|e| anyhow!("Fail to expand {}: {}", p, e))
+}
+
+/// platform specific directories, e.g. `{config}` is `~/.config` on Linux,
+/// `~/Library/Application Support` on macOS and `%APPDATA%` on Windows
+fn known_dirs(p: &str) -> Result<String> {
+    let mut p = p.to_string();
+    for (name, dir) in [
+        ("{home}", dirs::home_dir()),
+        ("{config}", dirs::config_dir()),
+        ("{data}", dirs::data_dir()),
+        ("{cache}", dirs::cache_dir()),
+    ] {
+        if p.contains(name) {
+            let dir = dir.context(format!("Fail to find {} directory", name))?;
+            p = p.replace(name, pathbuf_to_str(&dir)?);
+        }
+    }
+    Ok(p)
+}
+
+/// `%APPDATA%` to `${APPDATA}`
+fn windows_vars(p: &str) -> String {
+    let mut result = String::new();
+    let mut rest = p;
+    while let Some(start) = rest.find('%') {
+        match rest[start + 1..].find('%') {
+            Some(len) if len > 0 => {
+                result.push_str(&rest[..start]);
+                result.push_str(&format!("${{{}}}", &rest[start + 1..start + 1 + len]));
+                rest = &rest[start + len + 2..];
+            }
+            _ => break,
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[inline]
+pub fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// leading components of a glob pattern without any wildcard
+pub fn glob_base(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|c| !is_glob(&c.as_os_str().to_string_lossy()))
+        .collect()
+}
+
+/// absolute `p` with symbol links of its existing parents resolved, `p` itself may be a
+/// symbol link or missing
+fn resolve_parent(p: &Path) -> PathBuf {
+    let p = std::env::current_dir()
+        .map(|cwd| cwd.join(p))
+        .unwrap_or_else(|_| p.to_path_buf());
+    let (dir, name) = match (p.parent(), p.file_name()) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => return p,
+    };
+    let existing = dir.ancestors().find(|a| a.exists()).unwrap_or(dir);
+    let rest = dir.strip_prefix(existing).unwrap_or_else(|_| Path::new(""));
+    existing
+        .canonicalize()
+        .map(|e| e.join(rest).join(name))
+        .unwrap_or_else(|_| p.clone())
+}
+
+/// `inner` is `outer` or inside it
+pub fn is_within(inner: &str, outer: &str) -> bool {
+    resolve_parent(Path::new(inner)).starts_with(resolve_parent(Path::new(outer)))
+}
+
+#[test]
+fn test_glob_base() {
+    assert!(is_glob("config/nvim/**/*.lua"));
+    assert!(!is_glob("config/nvim"));
+    assert_eq!(
+        glob_base("/home/a/config/nvim/**/*.lua"),
+        PathBuf::from("/home/a/config/nvim")
+    );
+    assert_eq!(glob_base("/home/a/.*rc"), PathBuf::from("/home/a"));
+}
+
+#[test]
+fn test_expand_path() {
+    assert_eq!(windows_vars("%APPDATA%\\Code"), "${APPDATA}\\Code");
+    assert_eq!(windows_vars("100%"), "100%");
+    std::env::set_var("LKDOTS_TEST_CONFIG_HOME", "/tmp/config");
+    assert_eq!(
+        expand_path("$LKDOTS_TEST_CONFIG_HOME/nvim").unwrap(),
+        "/tmp/config/nvim"
+    );
+    assert!(expand_path("$LKDOTS_TEST_UNDEFINED/nvim").is_err());
+    assert_eq!(
+        expand_path("{home}/.zshrc").unwrap(),
+        expand_path("~/.zshrc").unwrap()
+    );
+}
+
+#[test]
+fn test_is_within() {
+    let dir = std::env::temp_dir().join("lkdots-test-is-within");
+    std::fs::create_dir_all(dir.join("dots/nvim")).unwrap();
+    let path = |p: &str| pathbuf_to_str(&dir.join(p)).unwrap().to_owned();
+    assert!(is_within(&path("dots/nvim/lua"), &path("dots/nvim")));
+    assert!(is_within(&path("dots/nvim/./"), &path("dots/nvim")));
+    assert!(!is_within(&path("dots/nvim-bak"), &path("dots/nvim")));
+    assert!(!is_within(&path("dots"), &path("dots/nvim")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_sorted_dir() {
+    let dir = std::env::temp_dir().join("lkdots-test-sorted-dir");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["c", "a", "b"] {
+        std::fs::write(dir.join(name), "").unwrap();
+    }
+    let names = sorted_dir(pathbuf_to_str(&dir).unwrap())
+        .unwrap()
+        .iter()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["a", "b", "c"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
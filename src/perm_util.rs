@@ -0,0 +1,200 @@
+use crate::errors::Error;
+use anyhow::{anyhow, Context, Result};
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use walkdir::WalkDir;
+
+/// octal mode like `"600"` or `"0644"`
+pub fn parse_mode(s: &str) -> Result<u32> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(anyhow!("Invalid chmod mode {}", s)),
+    }
+}
+
+/// `p` itself if it is a file, otherwise every file below it, following symbol links
+pub fn files(p: &str) -> Vec<PathBuf> {
+    WalkDir::new(p)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect()
+}
+
+#[cfg(unix)]
+pub fn mode_of(p: &Path) -> io::Result<u32> {
+    Ok(p.metadata()?.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn mode_of(_p: &Path) -> io::Result<u32> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "chmod is only supported on Unix",
+    ))
+}
+
+#[cfg(unix)]
+pub fn set_mode(p: &Path, mode: u32) -> io::Result<()> {
+    std::fs::set_permissions(p, std::fs::Permissions::from_mode(mode))
+}
+
+/// Windows has no modes, nothing to do
+#[cfg(not(unix))]
+pub fn set_mode(_p: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// some file of `p` doesn't have `mode` yet
+pub fn needs_chmod(p: &str, mode: u32) -> bool {
+    cfg!(unix)
+        && files(p)
+            .iter()
+            .any(|f| mode_of(f).map(|m| m != mode).unwrap_or(true))
+}
+
+/// uid and login gid of a user in `/etc/passwd`, by name or id
+#[cfg(unix)]
+fn passwd(user: &str) -> Option<(u32, u32)> {
+    std::fs::read_to_string("/etc/passwd")
+        .ok()?
+        .lines()
+        .map(|l| l.split(':').collect::<Vec<_>>())
+        .find(|f| f.len() > 3 && (f[0] == user || f[2] == user))
+        .and_then(|f| Some((f[2].parse().ok()?, f[3].parse().ok()?)))
+}
+
+/// gid of a group in `/etc/group`, by name or id
+#[cfg(unix)]
+fn group_id(group: &str) -> Option<u32> {
+    std::fs::read_to_string("/etc/group")
+        .ok()?
+        .lines()
+        .map(|l| l.split(':').collect::<Vec<_>>())
+        .find(|f| f.len() > 2 && f[0] == group)
+        .and_then(|f| f[2].parse().ok())
+        .or_else(|| group.parse().ok())
+}
+
+/// uid and gid of `owner` like the `chown` command, `None` for the one not given. `None` if
+/// a name is not found, e.g. users from a directory service
+#[cfg(unix)]
+fn owner_ids(owner: &str) -> Option<(Option<u32>, Option<u32>)> {
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+    let uid = match user {
+        "" => None,
+        user => Some(
+            passwd(user)
+                .map(|(uid, _)| uid)
+                .or_else(|| user.parse().ok())?,
+        ),
+    };
+    let gid = match group {
+        // `user:` is the login group of user
+        Some("") => Some(passwd(user)?.1),
+        Some(group) => Some(group_id(group)?),
+        None => None,
+    };
+    Some((uid, gid))
+}
+
+/// some file of `p` isn't owned by `owner` yet
+#[cfg(unix)]
+pub fn needs_chown(p: &str, owner: &str) -> bool {
+    match owner_ids(owner) {
+        Some((uid, gid)) => files(p).iter().any(|f| match owner_of(f) {
+            Ok((u, g)) => uid.is_some_and(|uid| uid != u) || gid.is_some_and(|gid| gid != g),
+            Err(_) => true,
+        }),
+        None => true,
+    }
+}
+
+/// Windows has no owners to change
+#[cfg(not(unix))]
+pub fn needs_chown(_p: &str, _owner: &str) -> bool {
+    false
+}
+
+#[cfg(unix)]
+pub fn owner_of(p: &Path) -> io::Result<(u32, u32)> {
+    let meta = p.metadata()?;
+    Ok((meta.uid(), meta.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn owner_of(_p: &Path) -> io::Result<(u32, u32)> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "chown is only supported on Unix",
+    ))
+}
+
+/// `owner` is `user`, `user:group` or `:group`, by name or id like the `chown` command
+pub fn chown(p: &Path, owner: &str) -> Result<()> {
+    let output = Command::new("chown")
+        .arg(owner)
+        .arg(p)
+        .output()
+        .context("Fail to run chown")?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = format!("Fail to chown {} {}: {}", owner, p.display(), stderr.trim());
+    // e.g. an unknown user is not about permissions
+    if stderr.contains("not permitted") {
+        return Err(Error::PermissionDenied(message).into());
+    }
+    Err(anyhow!(message))
+}
+
+#[cfg(unix)]
+pub fn restore_owner(p: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    std::os::unix::fs::chown(p, Some(uid), Some(gid))
+}
+
+#[cfg(not(unix))]
+pub fn restore_owner(_p: &Path, _uid: u32, _gid: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[test]
+fn test_parse_mode() {
+    assert_eq!(parse_mode("600").unwrap(), 0o600);
+    assert_eq!(parse_mode("0755").unwrap(), 0o755);
+    assert!(parse_mode("rw-").is_err());
+    assert!(parse_mode("17777").is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_owner_ids() {
+    assert_eq!(owner_ids("0:0"), Some((Some(0), Some(0))));
+    assert_eq!(owner_ids(":0"), Some((None, Some(0))));
+    assert_eq!(owner_ids("root:"), Some((Some(0), Some(0))));
+    assert_eq!(owner_ids("lkdots-test-missing-user"), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_chown_error() {
+    let p = std::env::temp_dir().join("lkdots-test-chown");
+    std::fs::write(&p, "").unwrap();
+    let err = chown(&p, "lkdots-test-missing-user").unwrap_err();
+    assert!(err.downcast_ref::<Error>().is_none());
+    // the message of chown is kept
+    assert!(!err.to_string().trim_end().ends_with(':'));
+    std::fs::remove_file(&p).unwrap();
+}
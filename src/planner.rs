@@ -0,0 +1,498 @@
+use crate::{
+    config::Entry,
+    errors::Error,
+    fs_view::{CachedFs, Fs, PlannedFs},
+    i18n::tr,
+    operations::{excute_op, Op},
+    output,
+    path_util::expand_path,
+    transaction::Transaction,
+};
+use anyhow::{anyhow, Result};
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+/// ops of all entries with the ops each of them has to wait for, so independent ops run in
+/// parallel and parent directories shared by entries are created once
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    pub ops: Vec<Op>,
+    /// index of the entry each op belongs to
+    pub entries: Vec<usize>,
+    /// indexes of ops each op depends on
+    pub deps: Vec<Vec<usize>>,
+}
+
+/// path an op creates or changes
+fn target(op: &Op) -> Option<&str> {
+    match op {
+        Op::Mkdirp(p)
+        | Op::Symlink(_, p, _)
+        | Op::Unlink(p)
+        | Op::Move(_, p)
+        | Op::Copy(_, p)
+        | Op::Hardlink(_, p)
+        | Op::Remove(p)
+        | Op::Chmod(p, _)
+        | Op::Chown(p, _) => Some(p),
+        Op::Backup(_, to) => Some(to),
+        Op::Run(_) | Op::Existed(_) | Op::Conflict(_, _) => None,
+    }
+}
+
+/// path an op moves or removes, which has to happen before anything is placed there
+fn cleared(op: &Op) -> Option<&str> {
+    match op {
+        Op::Backup(p, _) | Op::Move(p, _) | Op::Remove(p) | Op::Unlink(p) => Some(p),
+        _ => None,
+    }
+}
+
+/// `op` creates a directory `other` is placed in
+fn is_dir_of(op: &Op, other: &Op) -> bool {
+    match (op, target(other)) {
+        (Op::Mkdirp(dir), Some(p)) => p != dir && Path::new(p).starts_with(dir),
+        _ => false,
+    }
+}
+
+/// ops depending on everything before them in the entry, and everything after them on them
+fn is_barrier(op: &Op) -> bool {
+    matches!(op, Op::Run(_) | Op::Chmod(_, _) | Op::Chown(_, _))
+}
+
+/// directories created by `opss`, each once with the first entry creating it, parents first
+pub fn hoist_dirs(opss: &[Vec<Op>]) -> Vec<(usize, String)> {
+    let mut dirs: Vec<(usize, String)> = vec![];
+    for (entry, ops) in opss.iter().enumerate() {
+        for op in ops {
+            if let Op::Mkdirp(p) = op {
+                if !dirs.iter().any(|(_, d)| d == p) {
+                    dirs.push((entry, p.clone()));
+                }
+            }
+        }
+    }
+    dirs.sort_by_key(|(_, d)| Path::new(d).components().count());
+    dirs
+}
+
+/// pairs of `(outer, inner)` indexes of `entries` whose `inner` target is inside the `outer` one,
+/// equal targets are left out as directories can be merged into the same target
+pub fn overlaps(entries: &[&Entry]) -> Result<Vec<(usize, usize)>> {
+    let targets = entries
+        .iter()
+        .map(|e| expand_path(&e.to))
+        .collect::<Result<Vec<_>>>()?;
+    let mut result = vec![];
+    for (i, outer) in targets.iter().enumerate() {
+        for (j, inner) in targets.iter().enumerate() {
+            if i != j && inner != outer && Path::new(inner).starts_with(outer) {
+                result.push((i, j));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// indexes of `entries` each one is linked after, by the names in its `after`,
+/// names of entries not planned, e.g. for other platforms, are ignored
+pub fn entry_deps(entries: &[&Entry]) -> Vec<Vec<usize>> {
+    entries
+        .iter()
+        .map(|e| {
+            entries
+                .iter()
+                .enumerate()
+                .filter(|(_, other)| other.name.as_ref().is_some_and(|n| e.after.contains(n)))
+                .map(|(i, _)| i)
+                .collect()
+        })
+        .collect()
+}
+
+/// `entries` in config order, except each one comes after the entries named in its `after`
+pub fn order_entries<'a>(entries: Vec<Entry<'a>>) -> Result<Vec<Entry<'a>>> {
+    let deps = entry_deps(&entries.iter().collect::<Vec<_>>());
+    let mut placed = vec![false; entries.len()];
+    let mut order = vec![];
+    while let Some(i) =
+        (0..entries.len()).find(|&i| !placed[i] && deps[i].iter().all(|&d| placed[d]))
+    {
+        placed[i] = true;
+        order.push(i);
+    }
+    if order.len() < entries.len() {
+        let waiting = entries
+            .iter()
+            .zip(placed)
+            .filter(|(_, placed)| !placed)
+            .map(|(e, _)| e.label())
+            .collect::<Vec<_>>();
+        return Err(Error::ParseError(format!(
+            "Entries are linked after each other by `after`: {}",
+            waiting.join(", ")
+        ))
+        .into());
+    }
+    let mut entries = entries.into_iter().map(Some).collect::<Vec<_>>();
+    Ok(order
+        .into_iter()
+        .filter_map(|i| entries[i].take())
+        .collect())
+}
+
+/// ops of `entries` by `plan`, entries linked after others by `after`, see `entry_deps`,
+/// are planned against the results of those, see `PlannedFs`, the others in parallel
+pub fn plan_after(
+    entries: &[&Entry],
+    after: &[Vec<usize>],
+    plan: impl Fn(&Entry) -> Result<Vec<Op>> + Sync,
+) -> Result<Vec<Vec<Op>>> {
+    let mut opss = entries
+        .par_iter()
+        .zip(after)
+        .map(|(e, deps)| match deps.is_empty() {
+            true => plan(e).map(Some),
+            false => Ok(None),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    while let Some(i) = (0..entries.len())
+        .find(|&i| opss[i].is_none() && after[i].iter().all(|&d| opss[d].is_some()))
+    {
+        // and the entries those are linked after
+        let mut before = vec![false; entries.len()];
+        let mut pending = after[i].clone();
+        while let Some(d) = pending.pop() {
+            if !std::mem::replace(&mut before[d], true) {
+                pending.extend(&after[d]);
+            }
+        }
+        let ops = (0..entries.len())
+            .filter(|&d| before[d])
+            .flat_map(|d| opss[d].iter().flatten())
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut e = entries[i].clone();
+        let base =
+            e.fs.clone()
+                .unwrap_or_else(|| Arc::new(CachedFs::default()));
+        e.fs = Some(Arc::new(PlannedFs::new(base, &ops)));
+        opss[i] = Some(plan(&e)?);
+    }
+    if opss.iter().any(Option::is_none) {
+        return Err(
+            Error::ParseError("Entries are linked after each other by `after`".into()).into(),
+        );
+    }
+    Ok(opss.into_iter().flatten().collect())
+}
+
+impl Graph {
+    /// graph of `opss`, directories hoisted before the ops of each entry in order,
+    /// ops of an entry wait for the ops of the entries in `after` it, see `entry_deps`
+    pub fn new(opss: &[Vec<Op>], after: &[Vec<usize>]) -> Graph {
+        let mut graph = Graph::default();
+        let mut dirs: HashMap<String, usize> = HashMap::new();
+        for (entry, dir) in hoist_dirs(opss) {
+            dirs.insert(dir.clone(), graph.ops.len());
+            graph.ops.push(Op::Mkdirp(dir));
+            graph.entries.push(entry);
+            graph.deps.push(vec![]);
+        }
+        for (entry, ops) in opss.iter().enumerate() {
+            let start = graph.ops.len();
+            let mut barrier = None;
+            for op in ops.iter().filter(|op| !matches!(op, Op::Mkdirp(_))) {
+                let i = graph.ops.len();
+                let mut deps: Vec<usize> = if is_barrier(op) {
+                    (start..i).collect()
+                } else {
+                    barrier.into_iter().collect()
+                };
+                if let Some(p) = target(op) {
+                    deps.extend((start..i).filter(|&j| cleared(&graph.ops[j]) == Some(p)));
+                }
+                if is_barrier(op) {
+                    barrier = Some(i);
+                }
+                graph.ops.push(op.clone());
+                graph.entries.push(entry);
+                graph.deps.push(deps);
+            }
+        }
+        // a shared directory waits for the entries every entry creating it is linked after,
+        // except for what is placed in it
+        let mut waits = vec![vec![]; graph.ops.len()];
+        for (i, op) in graph.ops.iter().enumerate() {
+            let owners: Vec<usize> = match op {
+                Op::Mkdirp(_) => (0..opss.len()).filter(|&e| opss[e].contains(op)).collect(),
+                _ => vec![graph.entries[i]],
+            };
+            for &before in owners
+                .iter()
+                .flat_map(|&e| after.get(e).into_iter().flatten())
+            {
+                waits[i].extend((0..graph.ops.len()).filter(|&j| {
+                    j != i && graph.entries[j] == before && !is_dir_of(op, &graph.ops[j])
+                }));
+            }
+        }
+        for (deps, waits) in graph.deps.iter_mut().zip(waits) {
+            deps.extend(waits);
+        }
+        // a directory is created after whatever was in its place is moved away,
+        // and before anything in it
+        for (i, op) in graph.ops.iter().enumerate() {
+            if let Op::Mkdirp(p) = op {
+                let ops = &graph.ops;
+                graph.deps[i].extend((0..ops.len()).filter(|&j| cleared(&ops[j]) == Some(p)));
+            }
+            let ancestors = target(op)
+                .into_iter()
+                .flat_map(|p| Path::new(p).ancestors().skip(1));
+            for dir in ancestors {
+                if let Some(&j) = dir.to_str().and_then(|d| dirs.get(d)) {
+                    graph.deps[i].push(j);
+                }
+            }
+        }
+        for deps in graph.deps.iter_mut() {
+            deps.sort_unstable();
+            deps.dedup();
+        }
+        graph
+    }
+
+    /// number of ops of `entry`
+    pub fn count(&self, entry: usize) -> usize {
+        self.entries.iter().filter(|&&e| e == entry).count()
+    }
+
+    /// ops grouped into levels, each level only depends on the ones before it
+    pub fn levels(&self) -> Result<Vec<Vec<usize>>> {
+        let mut level: Vec<Option<usize>> = vec![None; self.ops.len()];
+        let mut levels: Vec<Vec<usize>> = vec![];
+        let mut done = 0;
+        while done < self.ops.len() {
+            let ready = (0..self.ops.len())
+                .filter(|&i| level[i].is_none())
+                .filter(|&i| {
+                    self.deps[i]
+                        .iter()
+                        .all(|&d| level[d].is_some_and(|l| l < levels.len()))
+                })
+                .collect::<Vec<_>>();
+            if ready.is_empty() {
+                return Err(anyhow!("ops depend on each other"));
+            }
+            for &i in ready.iter() {
+                level[i] = Some(levels.len());
+            }
+            done += ready.len();
+            levels.push(ready);
+        }
+        Ok(levels)
+    }
+}
+
+/// execute `graph` level by level, ops of a level in parallel, advancing the bar of the
+/// entry of every op, making the changes to `fs`. Changes are rolled back if any op fails
+pub fn excute(graph: &Graph, bars: &[ProgressBar], fs: Arc<dyn Fs>) -> Result<Transaction> {
+    let conflicts = graph
+        .ops
+        .iter()
+        .filter_map(|op| match op {
+            Op::Conflict(_, p) => Some(tr("conflict_with_config", &[p])),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if !conflicts.is_empty() {
+        return Err(anyhow!(conflicts.join("\n")));
+    }
+    let hidden = ProgressBar::hidden();
+    let bar = |i: usize| bars.get(graph.entries[i]).unwrap_or(&hidden);
+
+    let mut tx = Transaction::with_fs(fs.clone());
+    for level in graph.levels()? {
+        let results = level
+            .par_iter()
+            .map(|&i| {
+                let op = &graph.ops[i];
+                bar(i).set_message(op.to_string());
+                let mut op_tx = Transaction::with_fs(fs.clone());
+                let res = excute_op(op, &mut op_tx);
+                (i, op_tx, res)
+            })
+            .collect::<Vec<_>>();
+        let mut err = None;
+        for (i, op_tx, res) in results {
+            tx.append(op_tx);
+            let op = &graph.ops[i];
+            match res {
+                Ok(()) if !matches!(op, Op::Existed(_)) => output::success(bar(i), op),
+                Ok(()) => {}
+                Err(e) => {
+                    output::failure(bar(i), op);
+                    err.get_or_insert(e);
+                }
+            }
+            bar(i).inc(1);
+        }
+        if let Some(err) = err {
+            bars.iter().for_each(ProgressBar::abandon);
+            tx.rollback();
+            return Err(err);
+        }
+    }
+    bars.iter().for_each(|bar| bar.finish_with_message("done"));
+    Ok(tx)
+}
+
+#[test]
+fn test_graph() {
+    let s = String::from;
+    let opss = vec![
+        vec![
+            Op::Mkdirp(s("/home/a/.config")),
+            Op::Backup(s("/home/a/.config/nvim"), s("/home/a/.config/nvim.bak")),
+            Op::Symlink(
+                s("/dots/nvim"),
+                s("/home/a/.config/nvim"),
+                s("../../dots/nvim"),
+            ),
+        ],
+        vec![
+            Op::Mkdirp(s("/home/a/.config")),
+            Op::Symlink(
+                s("/dots/fish"),
+                s("/home/a/.config/fish"),
+                s("../../dots/fish"),
+            ),
+            Op::Chmod(s("/home/a/.config/fish"), 0o700),
+        ],
+    ];
+    let graph = Graph::new(&opss, &[]);
+    // the shared directory is created once
+    assert_eq!(graph.ops.len(), 5);
+    assert_eq!(graph.count(1), 2);
+    assert_eq!(graph.deps[2], vec![0, 1]);
+    assert_eq!(graph.deps[3], vec![0]);
+    assert_eq!(graph.deps[4], vec![0, 3]);
+    assert_eq!(
+        graph.levels().unwrap(),
+        vec![vec![0], vec![1, 3], vec![2, 4]]
+    );
+}
+
+#[test]
+fn test_overlaps() {
+    use crate::config::{Config, ConfigFileStruct};
+
+    let s =
+        "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"nvim\"\nto = \"/home/a/.config/nvim\"\n\
+        [[entries]]\nfrom = \"config\"\nto = \"/home/a/.config\"\n\
+        [[entries]]\nfrom = \"nvim-local\"\nto = \"/home/a/.config/nvim\"\n\
+        [[entries]]\nfrom = \"nvimrc\"\nto = \"/home/a/.config/nvimrc\"\n";
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    let entries = c.entries.iter().collect::<Vec<_>>();
+    assert_eq!(overlaps(&entries).unwrap(), vec![(1, 0), (1, 2), (1, 3)]);
+}
+
+#[test]
+fn test_graph_after() {
+    let s = String::from;
+    // the second entry links into the directory linked by the first one
+    let opss = vec![
+        vec![
+            Op::Mkdirp(s("/home/a/.config")),
+            Op::Symlink(
+                s("/dots/nvim"),
+                s("/home/a/.config/nvim"),
+                s("../../dots/nvim"),
+            ),
+        ],
+        vec![Op::Symlink(
+            s("/dots/local.lua"),
+            s("/home/a/.config/nvim/local.lua"),
+            s("../local.lua"),
+        )],
+    ];
+    let graph = Graph::new(&opss, &[vec![], vec![0]]);
+    assert_eq!(graph.deps[2], vec![0, 1]);
+    assert_eq!(graph.levels().unwrap(), vec![vec![0], vec![1], vec![2]]);
+
+    use crate::config::{Config, ConfigFileStruct};
+    let s = "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"local.lua\"\n\
+        to = \"~/.config/nvim/local.lua\"\nafter = [\"nvim\"]\n\
+        [[entries]]\nname = \"nvim\"\nfrom = \"nvim\"\nto = \"~/.config/nvim\"\n";
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    let ordered = order_entries(c.entries.clone()).unwrap();
+    assert_eq!(ordered[0].label(), "nvim");
+    let mut cycle = c.entries;
+    cycle[1].after = std::borrow::Cow::Owned(vec!["nvim".to_string()]);
+    assert!(order_entries(cycle).is_err());
+}
+
+#[test]
+fn test_plan_after() {
+    use crate::{
+        config::{Config, ConfigFileStruct},
+        fs_view::{FsView, Kind, MemFs},
+    };
+
+    let fs = Arc::new(MemFs::new());
+    fs.create_dir_all(Path::new("/home/a/dotfiles/config/nvim"))
+        .unwrap();
+    fs.write(Path::new("/home/a/dotfiles/config/nvim/init.lua"), "")
+        .unwrap();
+    fs.write(Path::new("/home/a/dotfiles/local.lua"), "")
+        .unwrap();
+    let s = "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"local.lua\"\n\
+        to = \"/home/a/.config/nvim/local.lua\"\nafter = [\"nvim\"]\n\
+        [[entries]]\nname = \"nvim\"\nfrom = \"config/nvim\"\nto = \"/home/a/.config/nvim\"\n";
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    let mut entries = order_entries(c.entries).unwrap();
+    for e in entries.iter_mut() {
+        e.fs = Some(fs.clone());
+    }
+    let entries = entries.iter().collect::<Vec<_>>();
+    let after = entry_deps(&entries);
+    let base_dir = Path::new("/home/a/dotfiles");
+    let opss = plan_after(&entries, &after, |e| e.create_ops(base_dir)).unwrap();
+    let s = String::from;
+    // planned as if the directory of nvim is linked, not created
+    assert_eq!(
+        opss,
+        vec![
+            vec![
+                Op::Mkdirp(s("/home/a/.config")),
+                Op::Symlink(
+                    s("/home/a/dotfiles/config/nvim"),
+                    s("/home/a/.config/nvim"),
+                    s("../dotfiles/config/nvim"),
+                ),
+            ],
+            vec![Op::Symlink(
+                s("/home/a/dotfiles/local.lua"),
+                s("/home/a/.config/nvim/local.lua"),
+                s("../../local.lua"),
+            )],
+        ]
+    );
+    let graph = Graph::new(&opss, &after);
+    excute(&graph, &[], fs.clone()).unwrap().commit().unwrap();
+    let local = Path::new("/home/a/.config/nvim/local.lua");
+    assert_eq!(fs.kind(local), Some(Kind::Symlink));
+    assert_eq!(
+        fs.canonicalize(local).unwrap(),
+        Path::new("/home/a/dotfiles/local.lua")
+    );
+}
@@ -0,0 +1,179 @@
+use crate::{config::Entry, hook::shell};
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use serde::Serialize;
+use std::{
+    env,
+    ffi::OsStr,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// points of a link run `[hooks]` commands are called at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    PrePlan,
+    PreApply,
+    PostApply,
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Stage::PrePlan => "pre-plan",
+            Stage::PreApply => "pre-apply",
+            Stage::PostApply => "post-apply",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// entry given to the pre-plan hook, with `from` and `to` expanded
+#[derive(Debug, Serialize)]
+pub struct HookEntry {
+    pub package: Option<String>,
+    pub from: String,
+    pub to: String,
+    pub tags: Vec<String>,
+    pub encrypt: bool,
+}
+
+impl HookEntry {
+    pub fn new(e: &Entry, base_dir: &Path) -> Result<HookEntry> {
+        let (from, to) = e.expand_paths(base_dir)?;
+        Ok(HookEntry {
+            package: e.package.clone(),
+            from,
+            to,
+            tags: e.tags.to_vec(),
+            encrypt: e.encrypt,
+        })
+    }
+}
+
+/// run hook `cmd` of `stage` through the shell with `input` as JSON on stdin, the stage and
+/// the config file are in `LKDOTS_HOOK` and `LKDOTS_CONFIG`
+pub fn run_stage(
+    cmd: &str,
+    stage: Stage,
+    config_path: &Path,
+    input: &impl Serialize,
+) -> Result<()> {
+    debug!("run {} hook: {}", stage, cmd);
+    let json = serde_json::to_vec_pretty(input)?;
+    let mut child = shell(cmd)
+        .env("LKDOTS_HOOK", stage.to_string())
+        .env("LKDOTS_CONFIG", config_path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context(format!("Fail to run {} hook `{}`", stage, cmd))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // hooks not reading stdin close it early
+        if let Err(err) = stdin.write_all(&json) {
+            debug!("{} hook stopped reading stdin: {}", stage, err);
+        }
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("{} hook `{}` failed with {}", stage, cmd, status));
+    }
+    Ok(())
+}
+
+/// `lkdots-<name>` executable in `PATH`
+pub fn find_external(name: &str) -> Option<PathBuf> {
+    find_in(name, &env::var_os("PATH")?)
+}
+
+/// `lkdots-<name>` executable in one of `paths`, the extension is appended so dots in
+/// `name` are kept
+fn find_in(name: &str, paths: &OsStr) -> Option<PathBuf> {
+    let file = format!("lkdots-{}", name);
+    let extensions: &[&str] = if cfg!(windows) {
+        &[".exe", ".cmd", ".bat"]
+    } else {
+        &[""]
+    };
+    env::split_paths(paths).find_map(|dir| {
+        extensions
+            .iter()
+            .map(|ext| dir.join(format!("{}{}", file, ext)))
+            .find(|p| p.is_file())
+    })
+}
+
+/// run `lkdots-<name>` with the rest of `args`, returning its exit code. The config file,
+/// if any, is in `LKDOTS_CONFIG` and this executable in `LKDOTS_BIN`, so it can call back
+pub fn run_external(args: &[String], config_path: Option<&Path>) -> Result<i32> {
+    let (name, rest) = args.split_first().context("No subcommand given")?;
+    let exe = find_external(name).ok_or_else(|| {
+        anyhow!(
+            "unrecognized subcommand '{}', and no lkdots-{} in PATH",
+            name,
+            name
+        )
+    })?;
+    debug!("run external subcommand {}", exe.display());
+    let mut command = Command::new(&exe);
+    command.args(rest).env("LKDOTS_BIN", env::current_exe()?);
+    if let Some(p) = config_path {
+        command.env("LKDOTS_CONFIG", p);
+    }
+    let status = command
+        .status()
+        .context(format!("Fail to run {}", exe.display()))?;
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(unix)]
+#[test]
+fn test_run_stage() {
+    let dir = std::env::temp_dir().join("lkdots-test-plugin");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let out = dir.join("out.json");
+    let cmd = format!(
+        "cat > {} && [ \"$LKDOTS_HOOK\" = pre-apply ]",
+        out.display()
+    );
+    run_stage(&cmd, Stage::PreApply, &dir, &vec!["a"]).unwrap();
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "[\n  \"a\"\n]");
+    assert!(run_stage(&cmd, Stage::PostApply, &dir, &vec!["a"]).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_run_external() {
+    use crate::perm_util::set_mode;
+    let dir = std::env::temp_dir().join("lkdots-test-external");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let exe = dir.join("lkdots-sync.v2");
+    let out = dir.join("out");
+    std::fs::write(
+        &exe,
+        format!(
+            "#!/bin/sh\necho \"$@ $LKDOTS_CONFIG\" > {}\nexit 3\n",
+            out.display()
+        ),
+    )
+    .unwrap();
+    set_mode(&exe, 0o755).unwrap();
+    assert_eq!(find_in("sync.v2", dir.as_os_str()), Some(exe));
+    assert_eq!(find_in("sync", dir.as_os_str()), None);
+
+    let mut paths = vec![dir.clone()];
+    paths.extend(env::split_paths(&env::var_os("PATH").unwrap_or_default()));
+    env::set_var("PATH", env::join_paths(paths).unwrap());
+    let args = ["sync.v2".to_string(), "--all".to_string()];
+    let code = run_external(&args, Some(Path::new("/tmp/lkdots.toml"))).unwrap();
+    assert_eq!(code, 3);
+    assert_eq!(
+        std::fs::read_to_string(&out).unwrap(),
+        "--all /tmp/lkdots.toml\n"
+    );
+    assert!(run_external(&["missing".to_string()], None).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,54 @@
+use crate::output;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// progress bars are drawn to stderr, and hidden when it is not a terminal or with `--quiet`
+pub fn multi() -> MultiProgress {
+    if output::is_quiet() {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    }
+}
+
+/// bar of ops of an entry
+pub fn bar(multi: &MultiProgress, label: &str, len: usize) -> ProgressBar {
+    let bar = multi.add(ProgressBar::new(len as u64));
+    bar.set_style(
+        ProgressStyle::with_template("{prefix:.bold} [{bar:30}] {pos}/{len} {wide_msg}")
+            .expect("valid progress template")
+            .progress_chars("=> "),
+    );
+    bar.set_prefix(label.to_string());
+    bar
+}
+
+/// counter of files of an entry, when the number of files is unknown
+pub fn spinner(multi: &MultiProgress, label: &str) -> ProgressBar {
+    let bar = multi.add(ProgressBar::new_spinner());
+    bar.set_style(
+        ProgressStyle::with_template("{prefix:.bold} {spinner} {pos} files {wide_msg}")
+            .expect("valid progress template"),
+    );
+    bar.set_prefix(label.to_string());
+    bar
+}
+
+/// files larger than this get their own byte progress bar when encrypting or decrypting
+pub const LARGE_FILE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// bytes of a file being encrypted or decrypted, hidden unless it is larger than `LARGE_FILE_SIZE`
+pub fn file(multi: &MultiProgress, label: &str, len: u64) -> ProgressBar {
+    if len <= LARGE_FILE_SIZE {
+        return ProgressBar::hidden();
+    }
+    let bar = multi.add(ProgressBar::new(len));
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{prefix:.bold} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec}",
+        )
+        .expect("valid progress template")
+        .progress_chars("=> "),
+    );
+    bar.set_prefix(label.to_string());
+    bar
+}
@@ -0,0 +1,155 @@
+use crate::{condition::which, config::Entry, i18n::tr, interactive::confirm};
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::process::Command;
+
+/// `package` or `package:binary` of `requires`, the binary defaults to the package name
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Requirement {
+    pub package: String,
+    pub binary: String,
+}
+
+impl Requirement {
+    pub fn parse(s: &str) -> Requirement {
+        let (package, binary) = s.split_once(':').unwrap_or((s, s));
+        Requirement {
+            package: package.to_string(),
+            binary: binary.to_string(),
+        }
+    }
+}
+
+/// requirements of `entries` of this platform whose binary is not in `PATH`, with the
+/// `from` of entries requiring them
+pub fn missing<'a, 'b: 'a>(
+    entries: impl IntoIterator<Item = &'a Entry<'b>>,
+) -> Vec<(Requirement, Vec<String>)> {
+    let mut result: Vec<(Requirement, Vec<String>)> = vec![];
+    for e in entries.into_iter().filter(|e| e.match_platform()) {
+        for r in e.requires.iter().map(|r| Requirement::parse(r)) {
+            if which(&r.binary) {
+                continue;
+            }
+            match result.iter_mut().find(|(m, _)| *m == r) {
+                Some((_, froms)) => froms.push(e.from.to_string()),
+                None => result.push((r, vec![e.from.to_string()])),
+            }
+        }
+    }
+    result.sort();
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PackageManager {
+    Apt,
+    Brew,
+    Pacman,
+    Winget,
+}
+
+impl PackageManager {
+    /// the first package manager of this platform found in `PATH`
+    pub fn detect() -> Option<PackageManager> {
+        let candidates: &[(&str, PackageManager)] = if cfg!(windows) {
+            &[("winget", PackageManager::Winget)]
+        } else {
+            &[
+                ("brew", PackageManager::Brew),
+                ("apt-get", PackageManager::Apt),
+                ("pacman", PackageManager::Pacman),
+            ]
+        };
+        candidates
+            .iter()
+            .find(|(bin, _)| which(bin))
+            .map(|(_, pm)| *pm)
+    }
+
+    /// commands installing `packages`, winget takes one package at a time
+    pub fn commands(&self, packages: &[&str]) -> Vec<Vec<String>> {
+        let command = |args: &[&str]| {
+            args.iter()
+                .chain(packages)
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        match self {
+            PackageManager::Apt => vec![command(&["sudo", "apt-get", "install", "-y"])],
+            PackageManager::Brew => vec![command(&["brew", "install"])],
+            PackageManager::Pacman => {
+                vec![command(&[
+                    "sudo",
+                    "pacman",
+                    "-S",
+                    "--needed",
+                    "--noconfirm",
+                ])]
+            }
+            PackageManager::Winget => packages
+                .iter()
+                .map(|p| {
+                    ["winget", "install", "--exact", "--id", p]
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// install missing requirements of `entries` with the native package manager, asking first
+/// unless `yes`. `simulate` only prints the commands
+pub fn install_missing(entries: &[Entry], yes: bool, simulate: bool) -> Result<()> {
+    let missing = missing(entries);
+    if missing.is_empty() {
+        println!("{}", tr("requirements_installed", &[]));
+        return Ok(());
+    }
+    let pm = PackageManager::detect()
+        .context("No supported package manager found, install apt, brew, pacman or winget")?;
+    let mut packages = missing
+        .iter()
+        .map(|(r, _)| r.package.as_str())
+        .collect::<Vec<_>>();
+    packages.dedup();
+    let commands = pm.commands(&packages);
+    for c in commands.iter() {
+        println!("{}", c.join(" "));
+    }
+    if simulate {
+        return Ok(());
+    }
+    if !yes && !confirm("Run the commands above?")? {
+        info!("nothing installed");
+        return Ok(());
+    }
+    for c in commands {
+        let status = Command::new(&c[0])
+            .args(&c[1..])
+            .status()
+            .context(format!("Fail to run {}", c[0]))?;
+        if !status.success() {
+            return Err(anyhow!("{} failed with {}", c.join(" "), status));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_requires() {
+    assert_eq!(Requirement::parse("tmux").binary, "tmux");
+    let r = Requirement::parse("neovim:nvim");
+    assert_eq!((r.package.as_str(), r.binary.as_str()), ("neovim", "nvim"));
+    assert_eq!(
+        PackageManager::Winget.commands(&["a", "b"]).len(),
+        2,
+        "winget installs one package at a time"
+    );
+    assert_eq!(
+        PackageManager::Brew.commands(&["a", "b"]),
+        vec![vec!["brew", "install", "a", "b"]]
+    );
+}
@@ -0,0 +1,241 @@
+use anyhow::{anyhow, Context, Result};
+use std::{
+    env::{consts, current_exe},
+    fs::{create_dir_all, remove_file, write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// name of the systemd units and the launchd agent
+const NAME: &str = "lkdots-apply";
+const LAUNCHD_LABEL: &str = "com.github.fengkx.lkdots.apply";
+
+/// seconds between runs for launchd, systemd takes the name as `OnCalendar`
+fn interval_secs(interval: &str) -> Result<u64> {
+    match interval {
+        "hourly" => Ok(60 * 60),
+        "daily" => Ok(24 * 60 * 60),
+        "weekly" => Ok(7 * 24 * 60 * 60),
+        _ => Err(anyhow!("unknown interval {}", interval)),
+    }
+}
+
+/// arguments of the scheduled run, conflicts fail it instead of prompting
+fn apply_args(exe: &Path, config_path: &Path) -> Vec<String> {
+    vec![
+        exe.to_string_lossy().to_string(),
+        "--config".to_string(),
+        config_path.to_string_lossy().to_string(),
+        "--yes".to_string(),
+        "apply".to_string(),
+    ]
+}
+
+/// quote an argument of `ExecStart`, `%` specifiers and `$` variables are escaped too
+fn systemd_quote(arg: &str) -> String {
+    let arg = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{}\"", arg)
+}
+
+/// service and timer units running apply every `interval`
+pub fn systemd_units(exe: &Path, config_path: &Path, interval: &str) -> (String, String) {
+    let exec = apply_args(exe, config_path)
+        .iter()
+        .map(|a| systemd_quote(a))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let service = format!(
+        "[Unit]\nDescription=Link dotfiles with lkdots\n\n\
+         [Service]\nType=oneshot\nExecStart={}\n",
+        exec
+    );
+    let timer = format!(
+        "[Unit]\nDescription=Link dotfiles with lkdots {}\n\n\
+         [Timer]\nOnCalendar={}\nPersistent=true\n\n\
+         [Install]\nWantedBy=timers.target\n",
+        interval, interval
+    );
+    (service, timer)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// launchd agent running apply every `interval`, output goes to `log`
+pub fn launchd_plist(exe: &Path, config_path: &Path, interval: &str, log: &Path) -> Result<String> {
+    let args = apply_args(exe, config_path)
+        .iter()
+        .map(|a| format!("        <string>{}</string>\n", xml_escape(a)))
+        .collect::<String>();
+    let log = xml_escape(&log.to_string_lossy());
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>StartInterval</key>
+    <integer>{}</integer>
+    <key>StandardOutPath</key>
+    <string>{}</string>
+    <key>StandardErrorPath</key>
+    <string>{}</string>
+</dict>
+</plist>
+"#,
+        LAUNCHD_LABEL,
+        args,
+        interval_secs(interval)?,
+        log,
+        log
+    ))
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .context(format!("Fail to run {}", program))?;
+    if !status.success() {
+        return Err(anyhow!(
+            "{} {} failed with {}",
+            program,
+            args.join(" "),
+            status
+        ));
+    }
+    Ok(())
+}
+
+fn systemd_dir() -> PathBuf {
+    let config_home = std::env::var("XDG_CONFIG_HOME")
+        .unwrap_or_else(|_| shellexpand::tilde("~/.config").to_string());
+    Path::new(&config_home).join("systemd").join("user")
+}
+
+fn launchd_plist_path() -> PathBuf {
+    PathBuf::from(shellexpand::tilde("~/Library/LaunchAgents").as_ref())
+        .join(format!("{}.plist", LAUNCHD_LABEL))
+}
+
+/// install a systemd user timer or a launchd agent running `lkdots apply` with
+/// `config_path` every `interval`, `simulate` only prints the files
+pub fn install(config_path: &Path, interval: &str, simulate: bool) -> Result<()> {
+    let exe = current_exe()?;
+    let config_path = config_path
+        .canonicalize()
+        .context(format!("Fail to find {}", config_path.display()))?;
+    match consts::OS {
+        "linux" => {
+            let dir = systemd_dir();
+            let (service, timer) = systemd_units(&exe, &config_path, interval);
+            let files = [
+                (dir.join(format!("{}.service", NAME)), service),
+                (dir.join(format!("{}.timer", NAME)), timer),
+            ];
+            for (path, content) in files.iter() {
+                if simulate {
+                    println!("# {}\n{}", path.display(), content);
+                } else {
+                    create_dir_all(&dir)?;
+                    write(path, content).context(format!("Fail to write {}", path.display()))?;
+                    println!("installed {}", path.display());
+                }
+            }
+            if !simulate {
+                run("systemctl", &["--user", "daemon-reload"])?;
+                run(
+                    "systemctl",
+                    &["--user", "enable", "--now", &format!("{}.timer", NAME)],
+                )?;
+            }
+        }
+        "macos" => {
+            let path = launchd_plist_path();
+            let log = PathBuf::from(shellexpand::tilde("~/Library/Logs/lkdots.log").as_ref());
+            let plist = launchd_plist(&exe, &config_path, interval, &log)?;
+            if simulate {
+                println!("# {}\n{}", path.display(), plist);
+                return Ok(());
+            }
+            let path_str = path.to_string_lossy();
+            if path.exists() {
+                // reloaded to pick up a new interval
+                let _ = run("launchctl", &["unload", &path_str]);
+            }
+            create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+            write(&path, plist).context(format!("Fail to write {}", path.display()))?;
+            run("launchctl", &["load", "-w", &path_str])?;
+            println!("installed {}", path.display());
+        }
+        os => return Err(anyhow!("scheduling is not supported on {}", os)),
+    }
+    Ok(())
+}
+
+/// stop and remove what `install` installed
+pub fn uninstall(simulate: bool) -> Result<()> {
+    let files = match consts::OS {
+        "linux" => {
+            if !simulate {
+                // not installed
+                let _ = run(
+                    "systemctl",
+                    &["--user", "disable", "--now", &format!("{}.timer", NAME)],
+                );
+            }
+            let dir = systemd_dir();
+            vec![
+                dir.join(format!("{}.timer", NAME)),
+                dir.join(format!("{}.service", NAME)),
+            ]
+        }
+        "macos" => {
+            let path = launchd_plist_path();
+            if !simulate && path.exists() {
+                run("launchctl", &["unload", "-w", &path.to_string_lossy()])?;
+            }
+            vec![path]
+        }
+        os => return Err(anyhow!("scheduling is not supported on {}", os)),
+    };
+    for f in files.iter().filter(|f| f.exists()) {
+        if simulate {
+            println!("would remove {}", f.display());
+        } else {
+            remove_file(f)?;
+            println!("removed {}", f.display());
+        }
+    }
+    if !simulate && consts::OS == "linux" {
+        run("systemctl", &["--user", "daemon-reload"])?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_schedule() {
+    let exe = Path::new("/usr/bin/lkdots");
+    let config = Path::new("/home/a/my dots/lkdots.toml");
+    let (service, timer) = systemd_units(exe, config, "daily");
+    assert!(service.contains(
+        "ExecStart=\"/usr/bin/lkdots\" \"--config\" \"/home/a/my dots/lkdots.toml\" \"--yes\" \"apply\""
+    ));
+    assert!(timer.contains("OnCalendar=daily"));
+    assert_eq!(systemd_quote("/a/100%$HOME"), "\"/a/100%%$$HOME\"");
+    let plist = launchd_plist(exe, config, "hourly", Path::new("/tmp/lkdots.log")).unwrap();
+    assert!(plist.contains("<integer>3600</integer>"));
+    assert!(plist.contains("<string>/home/a/my dots/lkdots.toml</string>"));
+    assert!(launchd_plist(exe, config, "monthly", Path::new("/tmp/lkdots.log")).is_err());
+}
@@ -0,0 +1,129 @@
+use crate::{crypto::hash_file, path_util::pathbuf_to_str, perm_util::set_mode};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    env::{consts, current_exe},
+    fs::{copy, rename},
+    path::Path,
+    process::Command,
+};
+
+/// latest release of lkdots on GitHub
+const LATEST_RELEASE: &str = "https://api.github.com/repos/fengkx/lkdots/releases/latest";
+const DOWNLOAD: &str = "https://github.com/fengkx/lkdots/releases/download";
+
+/// target triple of the archives CI publishes for this platform
+fn target() -> Result<String> {
+    let os = match consts::OS {
+        "linux" if cfg!(target_env = "musl") => "unknown-linux-musl",
+        "linux" if consts::ARCH == "arm" => "unknown-linux-gnueabihf",
+        "linux" => "unknown-linux-gnu",
+        "macos" => "apple-darwin",
+        os => return Err(anyhow!("no release is published for {}", os)),
+    };
+    let arch = match consts::ARCH {
+        "x86" => "i686",
+        arch => arch,
+    };
+    Ok(format!("{}-{}", arch, os))
+}
+
+/// `1.2.3` of `v1.2.3`, compared numerically
+fn parse_version(v: &str) -> Vec<u64> {
+    v.trim_start_matches('v')
+        .split('.')
+        .map(|n| n.parse().unwrap_or(0))
+        .collect()
+}
+
+fn curl(args: &[&str]) -> Result<String> {
+    let output = Command::new("curl")
+        .args(["-fsSL", "--proto", "=https"])
+        .args(args)
+        .output()
+        .context("Fail to run curl")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "curl {} failed: {}",
+            args.last().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// tag of the latest release
+fn latest_tag() -> Result<String> {
+    let release: serde_json::Value = serde_json::from_str(&curl(&[LATEST_RELEASE])?)?;
+    release["tag_name"]
+        .as_str()
+        .map(str::to_string)
+        .context("Fail to read tag of the latest release")
+}
+
+/// download the archive of `tag` to `dir`, check it against its published sha256 and
+/// extract it, returning the path of the binary in it
+fn download(tag: &str, dir: &Path) -> Result<std::path::PathBuf> {
+    let name = format!("lkdots-{}-{}", tag, target()?);
+    let archive = dir.join(format!("{}.tar.gz", name));
+    let archive_str = pathbuf_to_str(&archive)?;
+    let url = format!("{}/{}/{}.tar.gz", DOWNLOAD, tag, name);
+    curl(&["-o", archive_str, &url])?;
+    let expected = curl(&[&format!("{}.sha256", url)])?;
+    let expected = expected.split_whitespace().next().unwrap_or_default();
+    let actual = hash_file(archive_str)?;
+    if expected != actual {
+        return Err(anyhow!(
+            "checksum of {}.tar.gz is {}, expected {}",
+            name,
+            actual,
+            expected
+        ));
+    }
+    let status = Command::new("tar")
+        .args(["xzf", archive_str])
+        .current_dir(dir)
+        .status()
+        .context("Fail to run tar")?;
+    if !status.success() {
+        return Err(anyhow!("Fail to extract {}", archive.display()));
+    }
+    Ok(dir.join(name).join("lkdots"))
+}
+
+/// replace the running executable with the latest release, or only report it with `check`
+pub fn self_update(check: bool) -> Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let tag = latest_tag()?;
+    if parse_version(&tag) <= parse_version(current) {
+        println!("lkdots {} is up to date", current);
+        return Ok(());
+    }
+    println!(
+        "lkdots {} is available, current version is {}",
+        tag, current
+    );
+    if check {
+        return Ok(());
+    }
+    let exe = current_exe()?;
+    // created private with a random name, nobody can plant a link to be extracted over
+    let dir = tempfile::Builder::new()
+        .prefix("lkdots-update-")
+        .tempdir()?;
+    let binary = download(&tag, dir.path())?;
+    // copied next to the executable first, so replacing it is a rename on the same filesystem
+    let staged = exe.with_extension("new");
+    copy(&binary, &staged).context(format!("Fail to write {}", staged.display()))?;
+    set_mode(&staged, 0o755)?;
+    rename(&staged, &exe).context(format!("Fail to replace {}", exe.display()))?;
+    dir.close()?;
+    println!("updated {} to {}", exe.display(), tag);
+    Ok(())
+}
+
+#[test]
+fn test_parse_version() {
+    assert!(parse_version("v0.1.0") > parse_version("0.0.6"));
+    assert!(parse_version("v0.0.10") > parse_version("0.0.9"));
+    assert_eq!(parse_version("v0.0.6"), parse_version("0.0.6"));
+}
@@ -0,0 +1,304 @@
+use crate::{
+    config::Entry,
+    crypto::hash_file,
+    operations::{resolve_conflict, OnConflict, Op, Resolution},
+    path_util::relative_path,
+};
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{create_dir_all, read_link, read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+/// a symbol link created by lkdots
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    pub from: String,
+    pub to: String,
+}
+
+/// links created in previous runs, persisted in `$XDG_STATE_HOME/lkdots/state.toml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    /// ignore files lkdots wrote its section to, so sections no longer used are removed,
+    /// plain values are written before the tables below
+    #[serde(default)]
+    pub gitignores: Vec<String>,
+    #[serde(default)]
+    pub links: Vec<Link>,
+    /// content hash of plaintext files when they were last encrypted or decrypted
+    #[serde(default)]
+    pub hashes: BTreeMap<String, String>,
+    /// content hash of copied targets when they were last copied
+    #[serde(default)]
+    pub copies: BTreeMap<String, String>,
+}
+
+impl State {
+    pub fn path() -> PathBuf {
+        let state_home = std::env::var("XDG_STATE_HOME")
+            .unwrap_or_else(|_| shellexpand::tilde("~/.local/state").to_string());
+        Path::new(&state_home).join("lkdots").join("state.toml")
+    }
+
+    pub fn load() -> Result<State> {
+        State::load_from(&State::path())
+    }
+
+    fn load_from(p: &Path) -> Result<State> {
+        if !p.exists() {
+            return Ok(State::default());
+        }
+        let s = read_to_string(p)?;
+        toml::from_str(&s).context(format!("Fail to parse state file {:?}", p))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&State::path())
+    }
+
+    fn save_to(&self, p: &Path) -> Result<()> {
+        if let Some(dir) = p.parent() {
+            create_dir_all(dir)?;
+        }
+        write(p, toml::to_string(self)?).context(format!("Fail to write state file {:?}", p))?;
+        debug!("state saved to {:?}", p);
+        Ok(())
+    }
+
+    /// remember created links and forget removed ones
+    pub fn record<'a>(&mut self, ops: impl IntoIterator<Item = &'a Op>) {
+        for op in ops {
+            match op {
+                Op::Symlink(from, to, _) => {
+                    self.links.retain(|l| &l.to != to);
+                    self.links.push(Link {
+                        from: from.clone(),
+                        to: to.clone(),
+                    });
+                }
+                Op::Unlink(to) => self.links.retain(|l| &l.to != to),
+                // a drifted copy pulled back into its source is the new baseline of the copy
+                Op::Copy(from, to) if self.copies.contains_key(from) => {
+                    if let Ok(hash) = hash_file(from) {
+                        self.copies.insert(from.clone(), hash);
+                    }
+                }
+                Op::Copy(_, to) => {
+                    if let Ok(hash) = hash_file(to) {
+                        self.copies.insert(to.clone(), hash);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// targets of recorded links still pointing where lkdots pointed them
+    pub fn owned_links(&self) -> HashSet<String> {
+        self.links
+            .iter()
+            .filter(|l| is_recorded_link(l))
+            .map(|l| l.to.clone())
+            .collect()
+    }
+
+    /// copied target `to` was changed since it was copied
+    pub fn is_drifted(&self, to: &str) -> bool {
+        match (self.copies.get(to), hash_file(to)) {
+            (Some(recorded), Ok(hash)) => recorded != &hash,
+            _ => false,
+        }
+    }
+
+    /// copies of `ops` which would overwrite changes made to the target, as conflicts
+    /// unless the entry overwrites or backs up conflicting targets
+    pub fn mark_drifted(&self, e: &Entry, base_dir: &Path, ops: Vec<Op>) -> Result<Vec<Op>> {
+        if !ops
+            .iter()
+            .any(|op| matches!(op, Op::Copy(_, to) if self.is_drifted(to)))
+        {
+            return Ok(ops);
+        }
+        let opts = e.link_options(base_dir)?;
+        let mut result = vec![];
+        for op in ops {
+            match &op {
+                Op::Copy(from, to) if !e.force && self.is_drifted(to) => {
+                    if opts.on_conflict == OnConflict::Abort {
+                        result.push(Op::Conflict(from.clone(), to.clone()));
+                    } else {
+                        resolve_conflict(from, to, Resolution::Backup, &opts, &mut result)?;
+                    }
+                }
+                _ => result.push(op),
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// link is still the one created by lkdots, relative or absolute
+fn is_recorded_link(link: &Link) -> bool {
+    let to_dir = match Path::new(&link.to).parent().and_then(|p| p.to_str()) {
+        Some(dir) => dir,
+        None => return false,
+    };
+    match (read_link(&link.to), relative_path(&link.from, to_dir)) {
+        (Ok(target), Ok(relative)) => target == relative || target == Path::new(&link.from),
+        _ => false,
+    }
+}
+
+/// unlink recorded links no entry manages anymore
+pub fn create_prune_ops(state: &State, entries: &[Entry], base_dir: &Path) -> Result<Vec<Op>> {
+    let mut managed = HashSet::new();
+    for e in entries.iter().filter(|e| e.match_platform()) {
+        for op in e.create_ops(base_dir)? {
+            if let Op::Symlink(_, to, _) | Op::Existed(to) = op {
+                managed.insert(to);
+            }
+        }
+    }
+    Ok(state
+        .links
+        .iter()
+        .filter(|l| !managed.contains(&l.to))
+        .filter(|l| is_recorded_link(l))
+        .map(|l| Op::Unlink(l.to.clone()))
+        .collect())
+}
+
+#[test]
+fn test_save_and_load() {
+    let dir = std::env::temp_dir().join("lkdots-test-state");
+    let _ = std::fs::remove_dir_all(&dir);
+    let p = dir.join("lkdots/state.toml");
+    State::default().save_to(&p).unwrap();
+    assert!(State::load_from(&p).unwrap().links.is_empty());
+
+    let mut state = State::default();
+    state.links.push(Link {
+        from: "/dots/zshrc".to_string(),
+        to: "/home/a/.zshrc".to_string(),
+    });
+    state
+        .hashes
+        .insert("/dots/ssh/config".to_string(), "abc".to_string());
+    state.gitignores.push("/dots/.gitignore".to_string());
+    state
+        .copies
+        .insert("/home/a/.gitconfig".to_string(), "def".to_string());
+    state.save_to(&p).unwrap();
+    let loaded = State::load_from(&p).unwrap();
+    assert_eq!(loaded.links, state.links);
+    assert_eq!(loaded.hashes, state.hashes);
+    assert_eq!(loaded.gitignores, state.gitignores);
+    assert_eq!(loaded.copies, state.copies);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_drifted_copies() {
+    use crate::config::{Config, ConfigFileStruct};
+
+    let dir = std::env::temp_dir().join("lkdots-test-drifted");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let from = dir.join("gitconfig").to_string_lossy().to_string();
+    let to = dir.join(".gitconfig").to_string_lossy().to_string();
+    std::fs::write(&from, "a").unwrap();
+    std::fs::copy(&from, &to).unwrap();
+    let mut state = State::default();
+    state.record(&[Op::Copy(from.clone(), to.clone())]);
+    assert!(!state.is_drifted(&to));
+
+    let s = format!(
+        "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"{}\"\nto = \"{}\"\nmode = \"copy\"\n",
+        from, to
+    );
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), &s)
+        .unwrap()
+        .into();
+    let mut e = c.entries[0].clone();
+    let ops = vec![Op::Copy(from.clone(), to.clone())];
+    assert_eq!(state.mark_drifted(&e, &dir, ops.clone()).unwrap(), ops);
+
+    // changed since it was copied
+    std::fs::write(&to, "b").unwrap();
+    assert!(state.is_drifted(&to));
+    assert_eq!(
+        state.mark_drifted(&e, &dir, ops.clone()).unwrap(),
+        vec![Op::Conflict(from.clone(), to.clone())]
+    );
+    e.force = true;
+    assert_eq!(state.mark_drifted(&e, &dir, ops.clone()).unwrap(), ops);
+
+    // pulled back into the source
+    std::fs::copy(&to, &from).unwrap();
+    state.record(&[Op::Copy(to.clone(), from.clone())]);
+    assert!(!state.is_drifted(&to));
+    assert!(!state.copies.contains_key(&from));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_record_and_prune() {
+    use crate::config::{Config, ConfigFileStruct};
+    use std::os::unix::fs::symlink;
+
+    let dir = std::env::temp_dir().join("lkdots-test-prune");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("home")).unwrap();
+    let p = |f: &str| dir.join(f).to_string_lossy().to_string();
+    for f in ["zshrc", "vimrc", "tmux.conf"] {
+        std::fs::write(dir.join(f), f).unwrap();
+    }
+    symlink("../zshrc", dir.join("home/.zshrc")).unwrap();
+    symlink(p("vimrc"), dir.join("home/.vimrc")).unwrap();
+    // replaced by the user after lkdots linked it
+    symlink("../zshrc", dir.join("home/.tmux.conf")).unwrap();
+
+    let mut state = State::default();
+    state.record(&[
+        Op::Symlink(p("zshrc"), p("home/.zshrc"), "../zshrc".to_string()),
+        Op::Symlink(p("vimrc"), p("home/.vimrc"), p("vimrc")),
+        Op::Symlink(
+            p("tmux.conf"),
+            p("home/.tmux.conf"),
+            "../tmux.conf".to_string(),
+        ),
+        Op::Symlink(p("gone"), p("home/.gone"), "../gone".to_string()),
+    ]);
+    // linked again and unlinked
+    state.record(&[
+        Op::Symlink(p("zshrc"), p("home/.zshrc"), "../zshrc".to_string()),
+        Op::Unlink(p("home/.gone")),
+    ]);
+    let targets: Vec<_> = state.links.iter().map(|l| l.to.as_str()).collect();
+    assert_eq!(
+        targets,
+        vec![p("home/.vimrc"), p("home/.tmux.conf"), p("home/.zshrc")]
+    );
+    assert_eq!(
+        state.owned_links(),
+        HashSet::from([p("home/.zshrc"), p("home/.vimrc")])
+    );
+
+    let s = format!(
+        "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"zshrc\"\nto = \"{}\"\n",
+        p("home/.zshrc")
+    );
+    let c: Config = ConfigFileStruct::load(&dir.join("lkdots.toml"), &s)
+        .unwrap()
+        .into();
+    assert_eq!(
+        create_prune_ops(&state, &c.entries, &dir).unwrap(),
+        vec![Op::Unlink(p("home/.vimrc"))]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,165 @@
+use crate::{
+    config::{Entry, Mode},
+    i18n::tr,
+    operations::Op,
+    output,
+    state::State,
+    symlink_util::is_broken_symlink,
+};
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Linked,
+    Missing,
+    Conflict,
+    Broken,
+    /// copy changed since it was copied
+    Drifted,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Linked => write!(f, "{}", output::green("linked")),
+            Status::Missing => write!(f, "{}", output::yellow("missing")),
+            Status::Conflict => write!(f, "{}", output::red("conflict")),
+            Status::Broken => write!(f, "{}", output::red("broken")),
+            Status::Drifted => write!(f, "{}", output::yellow("drifted")),
+        }
+    }
+}
+
+pub fn status_from_ops(ops: &[Op]) -> Vec<(String, Status)> {
+    ops.iter()
+        .filter_map(|op| match op {
+            Op::Symlink(_, to, _) | Op::Copy(_, to) | Op::Hardlink(_, to) => {
+                Some((to.clone(), Status::Missing))
+            }
+            Op::Existed(to) => Some((to.clone(), Status::Linked)),
+            Op::Conflict(_, to) if is_broken_symlink(to) => Some((to.clone(), Status::Broken)),
+            Op::Conflict(_, to) => Some((to.clone(), Status::Conflict)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct TargetStatus<'a> {
+    path: &'a str,
+    status: Status,
+}
+
+#[derive(Serialize)]
+struct EntryStatus<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    from: &'a str,
+    to: &'a str,
+    targets: Vec<TargetStatus<'a>>,
+}
+
+/// `status_from_ops`, with copies changed since they were copied as drifted
+fn copy_status(e: &Entry, state: &State, ops: &[Op]) -> Vec<(String, Status)> {
+    let mut result = status_from_ops(ops);
+    if e.mode == Mode::Copy {
+        for (to, status) in result.iter_mut() {
+            if *status != Status::Conflict && state.is_drifted(to) {
+                *status = Status::Drifted;
+            }
+        }
+    }
+    result
+}
+
+pub fn print_status(entries: &[Entry], state: &State, base_dir: &Path, json: bool) -> Result<()> {
+    let statuses = entries
+        .par_iter()
+        .filter(|e| e.match_platform())
+        .map(|e| -> Result<(&Entry, Vec<(String, Status)>)> {
+            // report conflicts as they are, instead of planned backups
+            let mut plain = e.clone();
+            plain.backup = false;
+            plain.force = false;
+            Ok((e, copy_status(e, state, &plain.create_ops(base_dir)?)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if json {
+        let statuses = statuses
+            .iter()
+            .map(|(entry, targets)| EntryStatus {
+                name: entry.name.as_deref(),
+                from: entry.from.as_str(),
+                to: entry.to.as_str(),
+                targets: targets
+                    .iter()
+                    .map(|(path, status)| TargetStatus {
+                        path,
+                        status: *status,
+                    })
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&statuses)?);
+        return Ok(());
+    }
+
+    let mut counts = [0usize; 5];
+    for (entry, targets) in statuses.iter() {
+        println!("{}", output::bold(entry.label()));
+        for (to, status) in targets {
+            counts[*status as usize] += 1;
+            println!("  [{}] {}", status, to);
+        }
+    }
+    let summary = tr(
+        "status_summary",
+        &[
+            &output::green(counts[Status::Linked as usize]),
+            &output::yellow(counts[Status::Missing as usize]),
+            &output::red(counts[Status::Conflict as usize]),
+            &output::red(counts[Status::Broken as usize]),
+            &output::yellow(counts[Status::Drifted as usize]),
+        ],
+    );
+    println!("{}", summary);
+    Ok(())
+}
+
+#[test]
+fn test_copy_status() {
+    use crate::config::{Config, ConfigFileStruct};
+
+    let dir = std::env::temp_dir().join("lkdots-test-copy-status");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let from = dir.join("gitconfig").to_string_lossy().to_string();
+    let to = dir.join(".gitconfig").to_string_lossy().to_string();
+    std::fs::write(&from, "a").unwrap();
+    std::fs::copy(&from, &to).unwrap();
+    let mut state = State::default();
+    state.record(&[Op::Copy(from.clone(), to.clone())]);
+    let s = format!(
+        "gitignore = \".gitignore\"\n[[entries]]\nfrom = \"{}\"\nto = \"{}\"\nmode = \"copy\"\n",
+        from, to
+    );
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), &s)
+        .unwrap()
+        .into();
+    let ops = vec![Op::Existed(to.clone())];
+    assert_eq!(
+        copy_status(&c.entries[0], &state, &ops),
+        vec![(to.clone(), Status::Linked)]
+    );
+    std::fs::write(&to, "b").unwrap();
+    assert_eq!(
+        copy_status(&c.entries[0], &state, &ops),
+        vec![(to.clone(), Status::Drifted)]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,3 +1,5 @@
+use crate::{config::WindowsFallback, copy_util::copy_dir_all};
+use log::debug;
 use permissions::{is_creatable, is_writable};
 use std::{
     fs::Metadata,
@@ -10,8 +12,28 @@ pub fn get_symbol_meta_data(p: &str) -> Result<Metadata> {
     p.symlink_metadata()
 }
 
-pub fn create_symlink(src: &str, dst: &str, relative: &str) -> Result<()> {
-    if !is_creatable(dst)? && !is_writable(dst)? {
+/// symbol link pointing to nothing, e.g. after the repository is moved
+pub fn is_broken_symlink(p: &str) -> bool {
+    let p = Path::new(p);
+    match p.symlink_metadata() {
+        Ok(metadata) => metadata.is_symlink() && !p.exists(),
+        Err(_) => false,
+    }
+}
+
+/// `dst` can be created or overwritten by current user
+pub fn can_write(dst: &str) -> Result<bool> {
+    Ok(is_creatable(dst)? || is_writable(dst)?)
+}
+
+/// symbol link `dst` to `relative`, falling back to `fallback` when Windows doesn't permit it
+pub fn create_symlink(
+    src: &str,
+    dst: &str,
+    relative: &str,
+    fallback: WindowsFallback,
+) -> Result<()> {
+    if !can_write(dst)? {
         return Err(Error::new(
             ErrorKind::PermissionDenied,
             format!("{} is not writable", dst),
@@ -19,10 +41,66 @@ pub fn create_symlink(src: &str, dst: &str, relative: &str) -> Result<()> {
     }
 
     let metadata = get_symbol_meta_data(src)?;
-    if metadata.is_dir() {
+    let res = if metadata.is_dir() {
         symlink::symlink_dir(relative, dst)
     } else {
         symlink::symlink_file(relative, dst)
+    };
+    match res {
+        Err(err) if is_privilege_error(&err) => {
+            symlink_fallback(src, dst, metadata.is_dir(), fallback, err)
+        }
+        res => res,
+    }
+}
+
+/// ERROR_PRIVILEGE_NOT_HELD, symbol links need developer mode or administrator on Windows
+fn is_privilege_error(err: &Error) -> bool {
+    cfg!(windows) && err.raw_os_error() == Some(1314)
+}
+
+fn symlink_fallback(
+    src: &str,
+    dst: &str,
+    is_dir: bool,
+    fallback: WindowsFallback,
+    err: Error,
+) -> Result<()> {
+    debug!("fallback to {:?} for {}", fallback, dst);
+    match fallback {
+        WindowsFallback::Error => Err(err),
+        WindowsFallback::Junction if is_dir => create_junction(src, dst),
+        WindowsFallback::Junction => std::fs::hard_link(src, dst),
+        WindowsFallback::Copy if is_dir => copy_dir_all(src, dst),
+        WindowsFallback::Copy => std::fs::copy(src, dst).map(|_| ()),
+    }
+}
+
+#[cfg(windows)]
+fn create_junction(src: &str, dst: &str) -> Result<()> {
+    junction::create(src, dst)
+}
+
+#[cfg(not(windows))]
+fn create_junction(_src: &str, _dst: &str) -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "directory junctions are only supported on Windows",
+    ))
+}
+
+pub fn remove_symlink(dst: &str) -> Result<()> {
+    let metadata = get_symbol_meta_data(dst)?;
+    if !metadata.is_symlink() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not a symbol link", dst),
+        ));
+    }
+    if Path::new(dst).is_dir() {
+        symlink::remove_symlink_dir(dst)
+    } else {
+        symlink::remove_symlink_file(dst)
     }
 }
 
@@ -0,0 +1,215 @@
+use crate::fs_view::{Fs, RealFs};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// a filesystem change which can be undone
+#[derive(Debug)]
+enum Change {
+    CreatedDir(PathBuf),
+    /// symbol link, hard link or copied file
+    Created(PathBuf),
+    /// file overwritten by a copy, original is kept at the second path until commit
+    Replaced(PathBuf, PathBuf),
+    Moved(PathBuf, PathBuf),
+    /// removed symbol link and its target
+    Unlinked(PathBuf, PathBuf),
+    /// removed file or directory, kept at the second path until commit
+    Removed(PathBuf, PathBuf),
+    /// file changed by chmod, with its original mode
+    ModeChanged(PathBuf, u32),
+    /// file changed by chown, with its original uid and gid
+    OwnerChanged(PathBuf, u32, u32),
+}
+
+/// journal of filesystem changes made by `excute`, so a failed run can be rolled back
+#[derive(Debug)]
+pub struct Transaction {
+    journal: Vec<Change>,
+    fs: Arc<dyn Fs>,
+}
+
+impl Default for Transaction {
+    fn default() -> Self {
+        Transaction::with_fs(Arc::new(RealFs::default()))
+    }
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Transaction::default()
+    }
+
+    /// a transaction making its changes to `fs`
+    pub fn with_fs(fs: Arc<dyn Fs>) -> Self {
+        Transaction {
+            journal: vec![],
+            fs,
+        }
+    }
+
+    pub fn fs(&self) -> &dyn Fs {
+        self.fs.as_ref()
+    }
+
+    pub fn mkdirp(&mut self, p: &str) -> Result<()> {
+        let missing: Vec<PathBuf> = Path::new(p)
+            .ancestors()
+            .take_while(|a| !a.as_os_str().is_empty() && !self.fs.exists(a))
+            .map(Path::to_path_buf)
+            .collect();
+        self.fs.create_dir_all(Path::new(p))?;
+        // parents first, so rollback removes children first
+        for dir in missing.into_iter().rev() {
+            self.journal.push(Change::CreatedDir(dir));
+        }
+        Ok(())
+    }
+
+    pub fn symlink(&mut self, from: &str, to: &str, relative: &str) -> Result<()> {
+        self.fs
+            .symlink(Path::new(from), Path::new(to), Path::new(relative))?;
+        self.journal.push(Change::Created(to.into()));
+        Ok(())
+    }
+
+    pub fn hard_link(&mut self, from: &str, to: &str) -> Result<()> {
+        self.fs
+            .hard_link(Path::new(from), Path::new(to))
+            .context(format!("Fail to hard link {} to {}", from, to))?;
+        self.journal.push(Change::Created(to.into()));
+        Ok(())
+    }
+
+    pub fn copy(&mut self, from: &str, to: &str) -> Result<()> {
+        if self.fs.kind(Path::new(to)).is_some() {
+            let saved = PathBuf::from(format!("{}.lkdots.rollback", to));
+            self.fs.rename(Path::new(to), &saved)?;
+            self.journal.push(Change::Replaced(to.into(), saved));
+        } else {
+            self.journal.push(Change::Created(to.into()));
+        }
+        self.fs.copy(Path::new(from), Path::new(to))?;
+        Ok(())
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        self.fs
+            .rename(Path::new(from), Path::new(to))
+            .context(format!("Fail to move {} to {}", from, to))?;
+        self.journal.push(Change::Moved(from.into(), to.into()));
+        Ok(())
+    }
+
+    pub fn unlink(&mut self, p: &str) -> Result<()> {
+        let target = self.fs.read_link(Path::new(p))?;
+        self.fs.remove_symlink(Path::new(p))?;
+        self.journal.push(Change::Unlinked(p.into(), target));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, p: &str) -> Result<()> {
+        let saved = PathBuf::from(format!("{}.lkdots.rollback", p));
+        self.fs
+            .rename(Path::new(p), &saved)
+            .context(format!("Fail to remove {}", p))?;
+        self.journal.push(Change::Removed(p.into(), saved));
+        Ok(())
+    }
+
+    /// set mode of `p`, or of every file below it
+    pub fn chmod(&mut self, p: &str, mode: u32) -> Result<()> {
+        for f in self.fs.files(Path::new(p)) {
+            let old = self.fs.mode(&f)?;
+            if old != mode {
+                self.fs
+                    .set_mode(&f, mode)
+                    .context(format!("Fail to chmod {}", f.display()))?;
+                self.journal.push(Change::ModeChanged(f, old));
+            }
+        }
+        Ok(())
+    }
+
+    /// set owner of `p`, or of every file below it
+    pub fn chown(&mut self, p: &str, owner: &str) -> Result<()> {
+        for f in self.fs.files(Path::new(p)) {
+            let (uid, gid) = self.fs.owner(&f)?;
+            self.fs.chown(&f, owner)?;
+            self.journal.push(Change::OwnerChanged(f, uid, gid));
+        }
+        Ok(())
+    }
+
+    /// changes of `other` after the ones of this transaction, both made to the same filesystem
+    pub fn append(&mut self, mut other: Transaction) {
+        self.journal.append(&mut other.journal);
+    }
+
+    /// keep all changes
+    pub fn commit(self) -> Result<()> {
+        for change in self.journal {
+            match change {
+                Change::Replaced(_, saved) => self.fs.remove_file(&saved)?,
+                Change::Removed(_, saved) if self.fs.is_dir(&saved) => {
+                    self.fs.remove_dir_all(&saved)?
+                }
+                Change::Removed(_, saved) => self.fs.remove_file(&saved)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// undo all changes in reverse order, best effort
+    pub fn rollback(self) {
+        let fs = self.fs.as_ref();
+        for change in self.journal.into_iter().rev() {
+            info!("rollback: {:?}", change);
+            let res = match &change {
+                Change::CreatedDir(p) => fs.remove_dir(p),
+                // junctions created on Windows are directories
+                Change::Created(p) => fs.remove_file(p).or_else(|_| fs.remove_dir(p)),
+                Change::Replaced(p, saved) => fs.remove_file(p).and_then(|_| fs.rename(saved, p)),
+                Change::Moved(from, to) => fs.rename(to, from),
+                Change::Unlinked(p, target) => fs.restore_symlink(target, p),
+                Change::Removed(p, saved) => fs.rename(saved, p),
+                Change::ModeChanged(p, mode) => fs.set_mode(p, *mode),
+                Change::OwnerChanged(p, uid, gid) => fs.set_owner(p, *uid, *gid),
+            };
+            if let Err(err) = res {
+                warn!("Fail to rollback {:?}: {}", change, err);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rollback_in_memory() {
+    use crate::fs_view::{FsView, MemFs};
+
+    let fs = Arc::new(MemFs::new());
+    fs.create_dir_all(Path::new("/dots")).unwrap();
+    fs.write(Path::new("/dots/zshrc"), "new").unwrap();
+    fs.create_dir_all(Path::new("/home/a")).unwrap();
+    fs.write(Path::new("/home/a/.zshrc"), "old").unwrap();
+    let mut tx = Transaction::with_fs(fs.clone());
+    tx.mkdirp("/home/a/.config/nvim").unwrap();
+    tx.copy("/dots/zshrc", "/home/a/.zshrc").unwrap();
+    tx.chmod("/home/a/.zshrc", 0o600).unwrap();
+    assert_eq!(
+        fs.read_to_string(Path::new("/home/a/.zshrc")).unwrap(),
+        "new"
+    );
+    tx.rollback();
+    assert_eq!(
+        fs.read_to_string(Path::new("/home/a/.zshrc")).unwrap(),
+        "old"
+    );
+    assert_eq!(fs.mode(Path::new("/home/a/.zshrc")).unwrap(), 0o644);
+    assert!(!fs.exists(Path::new("/home/a/.config")));
+    assert!(!fs.exists(Path::new("/home/a/.zshrc.lkdots.rollback")));
+}
@@ -0,0 +1,279 @@
+use crate::{
+    config::Entry,
+    diff::diff,
+    interactive::{can_pull, resolve},
+    operations::{Op, Resolution},
+    state::State,
+};
+use anyhow::Result;
+use crossterm::event::KeyCode;
+use std::path::Path;
+
+/// ops of an entry and whether they are going to be executed
+pub struct Item<'a> {
+    pub entry: &'a Entry<'a>,
+    pub ops: Vec<Op>,
+    pub enabled: bool,
+    pub expanded: bool,
+    /// conflicts resolved by skipping them
+    pub skipped: usize,
+}
+
+impl<'a> Item<'a> {
+    pub fn conflicts(&self) -> usize {
+        self.ops
+            .iter()
+            .filter(|op| matches!(op, Op::Conflict(_, _)))
+            .count()
+    }
+}
+
+/// a line of the plan tree
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Row {
+    Entry(usize),
+    /// op of an entry
+    Op(usize, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Apply,
+    Quit,
+}
+
+pub struct App<'a> {
+    pub items: Vec<Item<'a>>,
+    pub cursor: usize,
+    /// shown instead of the key help until the next key
+    pub message: Option<String>,
+    base_dir: &'a Path,
+    /// copies recorded by previous runs, only drifted ones can be pulled
+    state: &'a State,
+}
+
+impl<'a> App<'a> {
+    /// entries with conflicts start expanded
+    pub fn new(
+        entries: &[&'a Entry<'a>],
+        base_dir: &'a Path,
+        state: &'a State,
+        opss: Vec<Vec<Op>>,
+    ) -> App<'a> {
+        let items = entries
+            .iter()
+            .zip(opss)
+            .map(|(entry, ops)| {
+                let mut item = Item {
+                    entry,
+                    ops,
+                    enabled: true,
+                    expanded: false,
+                    skipped: 0,
+                };
+                item.expanded = item.conflicts() > 0;
+                item
+            })
+            .collect();
+        App {
+            items,
+            cursor: 0,
+            message: None,
+            base_dir,
+            state,
+        }
+    }
+
+    pub fn rows(&self) -> Vec<Row> {
+        let mut rows = vec![];
+        for (i, item) in self.items.iter().enumerate() {
+            rows.push(Row::Entry(i));
+            if item.expanded {
+                rows.extend((0..item.ops.len()).map(|j| Row::Op(i, j)));
+            }
+        }
+        rows
+    }
+
+    fn entry_row(&self, entry: usize) -> usize {
+        self.rows()
+            .iter()
+            .position(|r| *r == Row::Entry(entry))
+            .unwrap_or(0)
+    }
+
+    fn row(&self) -> Option<Row> {
+        self.rows().get(self.cursor).copied()
+    }
+
+    /// text of the details pane for the row under the cursor
+    pub fn details(&self) -> String {
+        match self.row() {
+            Some(Row::Entry(i)) => {
+                let item = &self.items[i];
+                format!(
+                    "{} -> {}\n{} ops, {} conflicts{}",
+                    item.entry.from,
+                    item.entry.to,
+                    item.ops.len(),
+                    item.conflicts(),
+                    if item.enabled { "" } else { ", disabled" }
+                )
+            }
+            Some(Row::Op(i, j)) => match &self.items[i].ops[j] {
+                Op::Conflict(from, to) => diff(from, to),
+                op => op.to_string(),
+            },
+            None => String::new(),
+        }
+    }
+
+    /// replace the conflict under the cursor with ops of `resolution`
+    fn resolve(&mut self, resolution: Resolution) -> Result<()> {
+        let (i, j) = match self.row() {
+            Some(Row::Op(i, j)) => (i, j),
+            _ => return Ok(()),
+        };
+        let item = &mut self.items[i];
+        let (from, to) = match &item.ops[j] {
+            Op::Conflict(from, to) => (from.clone(), to.clone()),
+            _ => return Ok(()),
+        };
+        let opts = item.entry.link_options(self.base_dir)?;
+        if resolution == Resolution::Pull && !can_pull(self.state, &opts, &from, &to) {
+            self.message = Some(format!(
+                "{} can't be pulled, it is not a copy changed since it was copied",
+                to
+            ));
+            return Ok(());
+        }
+        let ops = resolve(item.entry, &opts, &from, &to, resolution)?;
+        if resolution == Resolution::Skip {
+            item.skipped += 1;
+        }
+        item.ops.splice(j..=j, ops);
+        Ok(())
+    }
+
+    /// apply the key, returning what to do when the review is over
+    pub fn handle(&mut self, key: KeyCode) -> Option<Action> {
+        self.message = None;
+        let len = self.rows().len();
+        // resolving a conflict can leave the cursor past the last row
+        self.cursor = self.cursor.min(len.saturating_sub(1));
+        let entry = match self.row() {
+            Some(Row::Entry(i)) | Some(Row::Op(i, _)) => i,
+            None => return None,
+        };
+        let resolution = match key {
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.cursor = self.cursor.saturating_sub(1);
+                None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.cursor = (self.cursor + 1).min(len.saturating_sub(1));
+                None
+            }
+            KeyCode::Char(' ') => {
+                self.items[entry].enabled = !self.items[entry].enabled;
+                None
+            }
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => {
+                self.items[entry].expanded = key != KeyCode::Enter || !self.items[entry].expanded;
+                if !self.items[entry].expanded {
+                    self.cursor = self.entry_row(entry);
+                }
+                None
+            }
+            KeyCode::Left | KeyCode::Char('h') => {
+                self.items[entry].expanded = false;
+                self.cursor = self.entry_row(entry);
+                None
+            }
+            KeyCode::Char('s') => Some(Resolution::Skip),
+            KeyCode::Char('b') => Some(Resolution::Backup),
+            KeyCode::Char('o') => Some(Resolution::Overwrite),
+            KeyCode::Char('p') => Some(Resolution::Pull),
+            KeyCode::Char('y') => {
+                let conflicts = self
+                    .items
+                    .iter()
+                    .filter(|item| item.enabled)
+                    .map(Item::conflicts)
+                    .sum::<usize>();
+                if conflicts == 0 {
+                    return Some(Action::Apply);
+                }
+                self.message = Some(format!(
+                    "{} conflicts left, resolve them or disable their entries",
+                    conflicts
+                ));
+                None
+            }
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+            _ => None,
+        };
+        if let Some(resolution) = resolution {
+            if let Err(err) = self.resolve(resolution) {
+                self.message = Some(format!("{:#}", err));
+            }
+        }
+        None
+    }
+
+    /// ops of every entry, none for disabled ones, and the number of skipped conflicts
+    pub fn into_plan(self) -> (Vec<Vec<Op>>, usize) {
+        let skipped = self
+            .items
+            .iter()
+            .filter(|item| item.enabled)
+            .map(|item| item.skipped)
+            .sum();
+        let opss = self
+            .items
+            .into_iter()
+            .map(|item| if item.enabled { item.ops } else { vec![] })
+            .collect();
+        (opss, skipped)
+    }
+}
+
+#[test]
+fn test_app() {
+    use crate::config::{Config, ConfigFileStruct};
+    let s = "[[entries]]\nfrom = \"git\"\nto = \"~/.gitconfig\"\n\
+        [[entries]]\nfrom = \"zsh\"\nto = \"~/.zshrc\"\n";
+    let c: Config = ConfigFileStruct::load(Path::new("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    let entries = c.entries.iter().collect::<Vec<_>>();
+    let opss = vec![
+        vec![Op::Existed("/home/a/.gitconfig".to_string())],
+        vec![Op::Conflict(
+            "/dots/zsh".to_string(),
+            "/home/a/.zshrc".to_string(),
+        )],
+    ];
+    let state = State::default();
+    let mut app = App::new(&entries, Path::new("/dots"), &state, opss);
+    // conflicting entries start expanded
+    assert_eq!(app.rows(), [Row::Entry(0), Row::Entry(1), Row::Op(1, 0)]);
+    assert_eq!(app.handle(KeyCode::Char('y')), None);
+    assert!(app
+        .message
+        .as_ref()
+        .unwrap()
+        .starts_with("1 conflicts left"));
+    app.handle(KeyCode::Down);
+    app.handle(KeyCode::Down);
+    app.handle(KeyCode::Char('s'));
+    assert_eq!(app.items[1].conflicts(), 0);
+    app.handle(KeyCode::Left);
+    assert_eq!(app.cursor, 1);
+    app.handle(KeyCode::Up);
+    app.handle(KeyCode::Char(' '));
+    assert_eq!(app.handle(KeyCode::Char('y')), Some(Action::Apply));
+    let (opss, skipped) = app.into_plan();
+    assert!(opss[0].is_empty() && opss[1].is_empty());
+    assert_eq!(skipped, 1);
+}
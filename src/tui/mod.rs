@@ -0,0 +1,86 @@
+//! terminal UI reviewing the plan before it is executed
+
+mod app;
+mod ui;
+
+use crate::{config::Entry, operations::Op, state::State};
+use anyhow::Result;
+use app::{Action, App};
+use crossterm::{
+    cursor::Show,
+    event::{self, Event, KeyEventKind},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    Terminal,
+};
+use std::{
+    io::stdout,
+    panic,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    },
+};
+
+/// raw mode and the alternate screen are entered
+static RAW: AtomicBool = AtomicBool::new(false);
+static PANIC_HOOK: Once = Once::new();
+
+/// leave raw mode and the alternate screen, if entered
+fn restore() -> Result<()> {
+    if RAW.swap(false, Ordering::SeqCst) {
+        disable_raw_mode()?;
+        execute!(stdout(), LeaveAlternateScreen, Show)?;
+    }
+    Ok(())
+}
+
+fn event_loop<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<Action> {
+    loop {
+        terminal.draw(|f| ui::draw(f, app))?;
+        if let Event::Key(key) = event::read()? {
+            // windows reports releases too
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(action) = app.handle(key.code) {
+                return Ok(action);
+            }
+        }
+    }
+}
+
+/// let the user toggle `entries` and resolve conflicts of their `opss`, returns the ops to
+/// execute, empty for disabled entries, and the number of skipped conflicts, or none if
+/// the user quits
+pub fn review(
+    entries: &[&Entry],
+    base_dir: &Path,
+    state: &State,
+    opss: Vec<Vec<Op>>,
+) -> Result<Option<(Vec<Vec<Op>>, usize)>> {
+    let mut app = App::new(entries, base_dir, state, opss);
+    // restored before the panic message is printed, it would be lost on the alternate screen
+    PANIC_HOOK.call_once(|| {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let _ = restore();
+            hook(info);
+        }));
+    });
+    enable_raw_mode()?;
+    RAW.store(true, Ordering::SeqCst);
+    execute!(stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    let action = event_loop(&mut terminal, &mut app);
+    // the terminal is restored even if drawing failed
+    restore()?;
+    Ok(match action? {
+        Action::Apply => Some(app.into_plan()),
+        Action::Quit => None,
+    })
+}
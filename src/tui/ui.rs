@@ -0,0 +1,82 @@
+use super::app::{App, Row};
+use crate::operations::Op;
+use ratatui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    Frame,
+};
+
+const HELP: &str = "j/k move  enter expand  space toggle entry  s/b/o/p skip/backup/overwrite/pull conflict  y apply  q quit";
+
+fn entry_item<'a>(app: &App, i: usize) -> ListItem<'a> {
+    let item = &app.items[i];
+    let conflicts = item.conflicts();
+    let text = format!(
+        "{} [{}] {} -> {}{}",
+        if item.expanded { "v" } else { ">" },
+        if item.enabled { "x" } else { " " },
+        item.entry.from,
+        item.entry.to,
+        if conflicts > 0 {
+            format!("  {} conflicts", conflicts)
+        } else {
+            String::new()
+        }
+    );
+    let style = match (item.enabled, conflicts) {
+        (false, _) => Style::default().fg(Color::DarkGray),
+        (true, 0) => Style::default().add_modifier(Modifier::BOLD),
+        (true, _) => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+    };
+    ListItem::new(text).style(style)
+}
+
+fn op_item<'a>(op: &Op, enabled: bool) -> ListItem<'a> {
+    let style = match op {
+        _ if !enabled => Style::default().fg(Color::DarkGray),
+        Op::Conflict(_, _) => Style::default().fg(Color::Red),
+        Op::Existed(_) => Style::default(),
+        _ => Style::default().fg(Color::Yellow),
+    };
+    ListItem::new(format!("      {}", op)).style(style)
+}
+
+/// plan tree, details of the row under the cursor and the key help
+pub fn draw<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage(60),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .split(f.size());
+
+    let items = app
+        .rows()
+        .into_iter()
+        .map(|row| match row {
+            Row::Entry(i) => entry_item(app, i),
+            Row::Op(i, j) => op_item(&app.items[i].ops[j], app.items[i].enabled),
+        })
+        .collect::<Vec<_>>();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title("plan"))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default();
+    state.select(Some(app.cursor));
+    f.render_stateful_widget(list, chunks[0], &mut state);
+
+    let details = Paragraph::new(app.details())
+        .block(Block::default().borders(Borders::ALL).title("details"))
+        .wrap(Wrap { trim: false });
+    f.render_widget(details, chunks[1]);
+
+    let footer = match app.message.as_ref() {
+        Some(message) => Paragraph::new(message.clone()).style(Style::default().fg(Color::Red)),
+        None => Paragraph::new(HELP),
+    };
+    f.render_widget(footer, chunks[2]);
+}
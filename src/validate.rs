@@ -0,0 +1,387 @@
+use crate::{errors::Error, path_util::expand_path};
+use anyhow::Result;
+use std::{collections::HashMap, ops::Range, path::Path};
+use toml_edit::{ImDocument, Item, TableLike};
+
+/// keys of `ConfigFileStruct`
+const KEYS: [&str; 22] = [
+    "include",
+    "entries",
+    "packages",
+    "gitignore",
+    "gitignore_mode",
+    "exclude",
+    "backup_dir",
+    "identities",
+    "recipients",
+    "pre_link",
+    "post_link",
+    "windows_fallback",
+    "remove_plaintext",
+    "crypt_buffer_size",
+    "max_file_size",
+    "encrypted_suffix",
+    "encrypted_dir",
+    "armor",
+    "symlink_type",
+    "overlap",
+    "git",
+    "hooks",
+];
+
+/// keys of `ConfigFileEntry`
+const ENTRY_KEYS: [&str; 26] = [
+    "from",
+    "to",
+    "platforms",
+    "arch",
+    "encrypt",
+    "encrypt_mode",
+    "ciphertext_mode",
+    "gitignore",
+    "exclude",
+    "encrypt_exclude",
+    "encrypt_follow_symlinks",
+    "backup",
+    "recipients",
+    "tags",
+    "requires",
+    "name",
+    "description",
+    "after",
+    "mode",
+    "link_strategy",
+    "symlink_type",
+    "chmod",
+    "chown",
+    "when",
+    "pre_link",
+    "post_link",
+];
+
+/// keys of `ConfigFilePackage`
+const PACKAGE_KEYS: [&str; 3] = ["name", "base_dir", "entries"];
+
+/// keys of `ConfigFileGit`
+const GIT_KEYS: [&str; 2] = ["auto_commit", "commit_message"];
+
+/// keys of `ConfigFileHooks`
+const HOOKS_KEYS: [&str; 3] = ["pre_plan", "pre_apply", "post_apply"];
+
+const PLATFORMS: [&str; 9] = [
+    "linux", "darwin", "windows", "window", "freebsd", "openbsd", "android", "termux", "wsl",
+];
+
+/// values of `std::env::consts::ARCH`, with the `amd64` and `arm64` aliases
+const ARCHES: [&str; 21] = [
+    "x86",
+    "x86_64",
+    "amd64",
+    "arm",
+    "aarch64",
+    "arm64",
+    "loongarch32",
+    "loongarch64",
+    "m68k",
+    "csky",
+    "mips",
+    "mips32r6",
+    "mips64",
+    "mips64r6",
+    "powerpc",
+    "powerpc64",
+    "riscv32",
+    "riscv64",
+    "s390x",
+    "sparc",
+    "sparc64",
+];
+
+/// a problem of config pointing to its position
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// 1-based line and column of byte `offset` in `src`
+fn position(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+    (line, column)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// `, did you mean `x`?` for a known word close to `word`
+fn suggestion(word: &str, known: &[&str]) -> String {
+    known
+        .iter()
+        .map(|k| (edit_distance(word, k), k))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+        .map(|(_, k)| format!(", did you mean `{}`?", k))
+        .unwrap_or_default()
+}
+
+/// `~`, absolute paths, environment variables and `{home}` like shorthands
+fn is_absolute_target(to: &str) -> bool {
+    to.starts_with('~')
+        || to.starts_with('$')
+        || to.starts_with('%')
+        || to.starts_with('{')
+        || Path::new(to).is_absolute()
+        || to.starts_with('/')
+}
+
+struct Validator<'a> {
+    src: &'a str,
+    issues: Vec<Issue>,
+}
+
+impl<'a> Validator<'a> {
+    fn push(&mut self, span: Option<Range<usize>>, message: String) {
+        let (line, column) = position(self.src, span.map(|s| s.start).unwrap_or(0));
+        self.issues.push(Issue {
+            line,
+            column,
+            message,
+        });
+    }
+
+    fn unknown_keys(&mut self, table: &dyn TableLike, known: &[&str], context: &str) {
+        for (key, item) in table.iter() {
+            if !known.contains(&key) {
+                self.push(
+                    item.span(),
+                    format!(
+                        "unknown key `{}` in {}{}",
+                        key,
+                        context,
+                        suggestion(key, known)
+                    ),
+                );
+            }
+        }
+    }
+
+    fn platform(&mut self, name: &str, span: Option<Range<usize>>) {
+        if !PLATFORMS.contains(&name) {
+            self.push(
+                span,
+                format!(
+                    "unknown platform `{}`{}",
+                    name,
+                    suggestion(name, &PLATFORMS)
+                ),
+            );
+        }
+    }
+
+    fn path(&mut self, item: Option<&Item>, key: &str, absolute: bool) {
+        let s = match item.and_then(|i| i.as_str()) {
+            Some(s) => s,
+            None => return,
+        };
+        if s.trim().is_empty() {
+            self.push(item.and_then(|i| i.span()), format!("`{}` is empty", key));
+        } else if absolute && !is_absolute_target(s) {
+            self.push(
+                item.and_then(|i| i.span()),
+                format!("`{}` {} should be absolute or start with `~`", key, s),
+            );
+        }
+    }
+
+    fn entry(&mut self, e: &dyn TableLike) {
+        self.unknown_keys(e, &ENTRY_KEYS, "entry");
+        self.path(e.get("from"), "from", false);
+        match e.get("to") {
+            Some(to) if to.is_str() => self.path(Some(to), "to", true),
+            Some(to) => {
+                if let Some(targets) = to.as_table_like() {
+                    for (platform, target) in targets.iter() {
+                        self.platform(platform, target.span());
+                        self.path(Some(target), "to", true);
+                    }
+                }
+            }
+            None => {}
+        }
+        if let Some(platforms) = e.get("platforms").and_then(|p| p.as_array()) {
+            for p in platforms.iter() {
+                if let Some(name) = p.as_str() {
+                    self.platform(name, p.span());
+                }
+            }
+        }
+        // `encrypt_mode` used to be the octal mode of ciphertexts
+        if let Some(mode) = e.get("encrypt_mode") {
+            match mode.as_str() {
+                Some("files") | Some("archive") => {}
+                _ => self.push(
+                    mode.span(),
+                    "`encrypt_mode` is `files` or `archive`, use `ciphertext_mode` for the octal mode of *.enc files".to_string(),
+                ),
+            }
+        }
+        if let Some(arches) = e.get("arch").and_then(|a| a.as_array()) {
+            for a in arches.iter() {
+                match a.as_str() {
+                    Some(name) if !ARCHES.contains(&name) => self.push(
+                        a.span(),
+                        format!("unknown arch `{}`{}", name, suggestion(name, &ARCHES)),
+                    ),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// `[[entries]]` or `entries = [{ .. }]`
+fn tables(item: Option<&Item>) -> Vec<&dyn TableLike> {
+    match item {
+        Some(Item::ArrayOfTables(tables)) => tables.iter().map(|t| t as &dyn TableLike).collect(),
+        Some(item) => item
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|e| e.as_inline_table())
+                    .map(|t| t as &dyn TableLike)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        None => vec![],
+    }
+}
+
+/// `from` and span of `to` of entries sharing a target
+type Sources<'a> = Vec<(Option<&'a str>, Option<Range<usize>>)>;
+
+/// check config `src` at `path` for mistakes `toml` would silently accept or report poorly,
+/// all issues are reported at once
+pub fn validate(path: &Path, src: &str) -> Result<()> {
+    // `DocumentMut` drops spans
+    let doc = ImDocument::parse(src)
+        .map_err(|e| Error::ParseError(format!("Fail to parse {}: {}", path.display(), e)))?;
+    let mut v = Validator {
+        src,
+        issues: vec![],
+    };
+    v.unknown_keys(doc.as_table(), &KEYS, "config");
+    if let Some(git) = doc.get("git").and_then(|g| g.as_table_like()) {
+        v.unknown_keys(git, &GIT_KEYS, "[git]");
+    }
+    if let Some(hooks) = doc.get("hooks").and_then(|h| h.as_table_like()) {
+        v.unknown_keys(hooks, &HOOKS_KEYS, "[hooks]");
+    }
+
+    let mut entries = tables(doc.get("entries"));
+    for p in tables(doc.get("packages")) {
+        v.unknown_keys(p, &PACKAGE_KEYS, "package");
+        entries.extend(tables(p.get("entries")));
+    }
+    let mut targets: HashMap<&str, Sources> = HashMap::new();
+    for e in entries {
+        v.entry(e);
+        if let Some(to) = e.get("to").and_then(|to| to.as_str()) {
+            let from = e.get("from").and_then(|f| f.as_str());
+            let span = e.get("to").and_then(|to| to.span());
+            targets.entry(to).or_default().push((from, span));
+        }
+    }
+    // several directories can be merged into the same target, missing sources are left to doctor
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let is_file = |from: Option<&str>| {
+        from.and_then(|f| expand_path(f).ok())
+            .map(|f| dir.join(f).is_file())
+            .unwrap_or(false)
+    };
+    for (to, sources) in targets {
+        if sources.len() > 1 && sources.iter().any(|(from, _)| is_file(*from)) {
+            v.push(
+                sources[1].1.clone(),
+                format!(
+                    "`to` {} is the target of several entries, only directories can share it",
+                    to
+                ),
+            );
+        }
+    }
+
+    if v.issues.is_empty() {
+        return Ok(());
+    }
+    v.issues.sort_by_key(|i| (i.line, i.column));
+    Err(Error::ParseError(format!(
+        "Invalid config {}\n{}",
+        path.display(),
+        v.issues
+            .iter()
+            .map(|i| format!("  {}", i))
+            .collect::<Vec<_>>()
+            .join("\n")
+    ))
+    .into())
+}
+
+#[test]
+fn test_validate() {
+    let src = r#"gitignore = ".gitignore"
+[[entries]]
+form = "nvim"
+to = "~/.config/nvim"
+platforms = ["linux", "darwn"]
+arch = ["aarch46", "sparc64", "m68k"]
+
+[[entries]]
+from = ""
+to = ".config/zsh"
+
+[[entries]]
+from = "Cargo.toml"
+to = "~/.zshrc"
+
+[[entries]]
+from = "src"
+to = "~/.zshrc"
+encrypt_mode = "600"
+"#;
+    let err = validate(Path::new("lkdots.toml"), src)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("line 3, column 8: unknown key `form` in entry, did you mean `from`?"));
+    assert!(err.contains("unknown platform `darwn`, did you mean `darwin`?"));
+    assert!(err.contains("unknown arch `aarch46`, did you mean `aarch64`?"));
+    assert!(!err.contains("sparc64") && !err.contains("m68k"));
+    assert!(err.contains("line 9, column 8: `from` is empty"));
+    assert!(err.contains("`to` .config/zsh should be absolute"));
+    assert!(err.contains("line 18, column 6: `to` ~/.zshrc is the target of several entries"));
+    assert!(err.contains("line 19, column 16: `encrypt_mode` is `files` or `archive`"));
+    assert!(validate(Path::new("lkdots.toml"), "gitignore = \".gitignore\"").is_ok());
+}
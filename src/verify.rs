@@ -0,0 +1,188 @@
+use crate::{
+    archive::{archive_dir, archive_name, hash_archive, hash_dir},
+    config::Entry,
+    crypto::{hash_decrypted, hash_file, Key},
+    output,
+};
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Problem {
+    /// ciphertext can't be decrypted with the given key
+    Undecryptable,
+    /// plaintext changed since it was encrypted
+    Stale,
+    /// plaintext was never encrypted
+    Missing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub path: String,
+    pub problem: Problem,
+    pub message: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self.problem {
+            Problem::Undecryptable => output::red("undecryptable"),
+            Problem::Stale => output::yellow("stale"),
+            Problem::Missing => output::yellow("missing"),
+        };
+        write!(f, "[{}] {}: {}", label, self.path, self.message)
+    }
+}
+
+/// the archive of an `encrypt_mode = "archive"` entry against its source directory
+fn verify_archive(e: &Entry, base_dir: &Path, key: &Key) -> Result<Vec<Finding>> {
+    let files = e.archive_files(base_dir)?;
+    let mut result = vec![];
+    for file in e.crypt_files(base_dir, &[])? {
+        let path = file.to_string_lossy().to_string();
+        let dir = match archive_dir(&e.naming, &path) {
+            Some(dir) => dir,
+            None => {
+                if !Path::new(&e.naming.ciphertext(&archive_name(&path))).exists() {
+                    result.push(Finding {
+                        path,
+                        problem: Problem::Missing,
+                        message: "has no archive, run `lkdots encrypt`".to_string(),
+                    });
+                }
+                continue;
+            }
+        };
+        match hash_archive(&path, key) {
+            Err(err) => result.push(Finding {
+                path,
+                problem: Problem::Undecryptable,
+                message: err.to_string(),
+            }),
+            Ok(hash) if !files.is_empty() && hash_dir(Path::new(&dir), &files)? != hash => result
+                .push(Finding {
+                    path: dir,
+                    problem: Problem::Stale,
+                    message: "differs from its archive, run `lkdots encrypt`".to_string(),
+                }),
+            Ok(_) => {}
+        }
+    }
+    Ok(result)
+}
+
+fn verify_entry(e: &Entry, base_dir: &Path, key: &Key) -> Result<Vec<Finding>> {
+    if e.encrypt_archive {
+        return verify_archive(e, base_dir, key);
+    }
+    let mut result = vec![];
+    for file in e.crypt_files(base_dir, &[])? {
+        let path = file.to_string_lossy().to_string();
+        if let Some(plain) = e.naming.plaintext(&path) {
+            // decrypt into the hasher only, plaintext is never written
+            let hash = match hash_decrypted(&path, key) {
+                Ok(hash) => hash,
+                Err(err) => {
+                    result.push(Finding {
+                        path,
+                        problem: Problem::Undecryptable,
+                        message: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+            if Path::new(&plain).exists() && hash_file(&plain)? != hash {
+                result.push(Finding {
+                    path: plain,
+                    problem: Problem::Stale,
+                    message: "differs from its ciphertext, run `lkdots encrypt`".to_string(),
+                });
+            }
+        } else if !Path::new(&e.naming.ciphertext(&path)).exists() {
+            result.push(Finding {
+                path,
+                problem: Problem::Missing,
+                message: "has no ciphertext, run `lkdots encrypt`".to_string(),
+            });
+        }
+    }
+    Ok(result)
+}
+
+/// problems of encrypted files of all encrypted entries
+pub fn verify(entries: &[Entry], base_dir: &Path, key: &Key) -> Result<Vec<Finding>> {
+    Ok(entries
+        .par_iter()
+        .filter(|e| e.encrypt && e.match_platform())
+        .map(|e| verify_entry(e, base_dir, key))
+        .collect::<Result<Vec<_>>>()?
+        .concat())
+}
+
+pub fn print_verify(entries: &[Entry], base_dir: &Path, key: &Key, json: bool) -> Result<()> {
+    let findings = verify(entries, base_dir, key)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&findings)?);
+    } else {
+        for f in findings.iter() {
+            println!("{}", f);
+        }
+    }
+    if !findings.is_empty() {
+        return Err(anyhow!("{} problems of encrypted files", findings.len()));
+    }
+    if !json {
+        println!("{}", output::green("all encrypted files are up to date"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_verify() {
+    use crate::config::{Config, ConfigFileStruct};
+    use crate::crypto::encrypt_file;
+    let dir = std::env::temp_dir().join("lkdots-test-verify");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("ssh")).unwrap();
+    let key = Key::Passphrase("abc".to_string());
+    for f in ["config", "known_hosts"] {
+        let p = dir.join("ssh").join(f);
+        std::fs::write(&p, f).unwrap();
+        encrypt_file(&p.to_string_lossy(), &key, &[]).unwrap();
+    }
+    std::fs::write(dir.join("ssh/known_hosts"), "changed").unwrap();
+    std::fs::write(dir.join("ssh/id.enc"), "corrupted").unwrap();
+    std::fs::write(dir.join("ssh/new"), "new").unwrap();
+    let s = "[[entries]]\nfrom = \"ssh\"\nto = \"~/.ssh\"\nencrypt = true\n";
+    let c: Config = ConfigFileStruct::load(&dir.join("lkdots.toml"), s)
+        .unwrap()
+        .into();
+    let mut findings = verify(&c.entries, &dir, &key).unwrap();
+    findings.sort_by(|a, b| a.path.cmp(&b.path));
+    let found: Vec<_> = findings
+        .iter()
+        .map(|f| (f.path.as_str(), f.problem))
+        .collect();
+    let path = |f: &str| dir.join("ssh").join(f).to_string_lossy().to_string();
+    assert_eq!(
+        found,
+        vec![
+            (path("id.enc").as_str(), Problem::Undecryptable),
+            (path("known_hosts").as_str(), Problem::Stale),
+            (path("new").as_str(), Problem::Missing),
+        ]
+    );
+    // a wrong key can't decrypt any of them
+    let wrong = Key::Passphrase("def".to_string());
+    let undecryptable = verify(&c.entries, &dir, &wrong)
+        .unwrap()
+        .into_iter()
+        .filter(|f| f.problem == Problem::Undecryptable)
+        .count();
+    assert_eq!(undecryptable, 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,104 @@
+use crate::{crypto::Naming, exclude::is_vcs_path};
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+use notify::RecursiveMode;
+use notify_debouncer_mini::new_debouncer;
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::channel,
+    time::Duration,
+};
+
+/// ciphertexts, temporary files and VCS internals are written by lkdots or git themselves,
+/// reacting to them would loop forever
+fn is_ignored(p: &Path, naming: &Naming) -> bool {
+    let s = p.to_string_lossy();
+    naming.is_ciphertext(&s) || s.ends_with(".lkdots.tmp") || p.ancestors().any(is_vcs_path)
+}
+
+/// call `on_change` whenever `config_path` or one of `sources` changes, changes within
+/// `debounce` are batched into one call. `sources` gives the sources and the naming of
+/// ciphertexts of the config, it is called again after the config changes, so added or removed
+/// entries are followed. Errors of `on_change` are logged, not returned.
+pub fn watch(
+    config_path: &Path,
+    sources: impl Fn() -> Result<(Vec<PathBuf>, Naming)>,
+    debounce: Duration,
+    mut on_change: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let config_path = config_path.canonicalize()?;
+    let (tx, rx) = channel();
+    let mut debouncer = new_debouncer(debounce, None, tx)?;
+    let config_dir = config_path.parent().unwrap_or_else(|| Path::new("/"));
+    let mut watched = vec![];
+    let mut naming = Naming::default();
+    let mut rewatch = |watched: &mut Vec<PathBuf>, naming: &mut Naming| -> Result<()> {
+        let watcher = debouncer.watcher();
+        for p in watched.drain(..) {
+            let _ = watcher.unwatch(&p);
+        }
+        // editors often replace the file instead of writing to it, which drops a watch on the file
+        watcher.watch(config_dir, RecursiveMode::NonRecursive)?;
+        let (paths, config_naming) = sources()?;
+        *naming = config_naming;
+        // missing sources are skipped, event paths are relative to the canonical watched paths
+        for p in paths.into_iter().filter_map(|p| p.canonicalize().ok()) {
+            debug!("watch {}", p.display());
+            watcher.watch(&p, RecursiveMode::Recursive)?;
+            watched.push(p);
+        }
+        info!("watching {} sources", watched.len());
+        Ok(())
+    };
+    rewatch(&mut watched, &mut naming)?;
+    for events in rx {
+        let events = match events {
+            Ok(events) => events,
+            Err(errs) => {
+                for err in errs {
+                    warn!("{}", err);
+                }
+                continue;
+            }
+        };
+        let changed = events
+            .iter()
+            .map(|e| e.path.as_path())
+            .filter(|p| *p == config_path || watched.iter().any(|w| p.starts_with(w)))
+            .filter(|p| !is_ignored(p, &naming))
+            .collect::<Vec<_>>();
+        if changed.is_empty() {
+            continue;
+        }
+        for p in changed.iter() {
+            info!("changed: {}", p.display());
+        }
+        if changed.iter().any(|p| *p == config_path) {
+            if let Err(err) = rewatch(&mut watched, &mut naming) {
+                warn!("{:#}", err);
+            }
+        }
+        if let Err(err) = on_change() {
+            warn!("{:#}", err);
+        }
+    }
+    Err(anyhow!("file watcher stopped"))
+}
+
+#[test]
+fn test_is_ignored() {
+    let naming = Naming::default();
+    assert!(is_ignored(Path::new("/dotfiles/ssh/config.enc"), &naming));
+    assert!(is_ignored(Path::new("/dotfiles/.git/index"), &naming));
+    assert!(is_ignored(
+        Path::new("/dotfiles/ssh/config.lkdots.tmp"),
+        &naming
+    ));
+    assert!(!is_ignored(Path::new("/dotfiles/nvim/init.lua"), &naming));
+    let age = Naming {
+        suffix: ".age".to_string(),
+        mirror: None,
+    };
+    assert!(is_ignored(Path::new("/dotfiles/ssh/config.age"), &age));
+    assert!(!is_ignored(Path::new("/dotfiles/ssh/config.enc"), &age));
+}